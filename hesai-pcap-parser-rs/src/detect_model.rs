@@ -2,6 +2,17 @@ pub enum HesaiModel {
     XT32, AT128, UNKNOWN
 }
 
+impl HesaiModel {
+    /// bytes of a point cloud packet up to the end of the tail read by the parser
+    pub fn packet_len(&self) -> Option<usize> {
+        match self {
+            HesaiModel::XT32 => Some(1076),
+            HesaiModel::AT128 => Some(1086),
+            HesaiModel::UNKNOWN => None,
+        }
+    }
+}
+
 pub fn detect_model(pre_header: &[u8]) -> HesaiModel {
     let major_version = pre_header[2];
     let minor_version = pre_header[3];
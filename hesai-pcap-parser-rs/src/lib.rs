@@ -1,9 +1,7 @@
-pub mod parseargs;
 pub mod run;
 mod detect_model;
 mod parse_xt32;
 mod parse_at128;
 mod constants_at128;
 
-pub use parseargs::*;
 pub use run::*;
@@ -1,10 +1,12 @@
 use std::f32::consts::PI;
+use std::net::SocketAddr;
 
-use writer_common::{azimuthsplitwriter::AzimuthSplitWriter, velopoint::VeloPoint};
+use anyhow::Result;
+use writer_common::{paralleldecoder::PointSink, pointbatch::PointBatch, sensormetadata::{Calibration, SensorMetadata}, splittingwriter::DynSplittingWriter, velopoint::VeloPoint};
 
 use crate::constants_at128::{FIRING_TIMING_OFFSET_OF_EACH_ANGLE, HORIZONTAL_OFFSET, START_FRAME, RESOLUTION, AZIMUTH_ADJUST, ELEVATION_ADJUST, ELEVATION_ANGLE};
 
-pub fn write_header_at128(packet_body: &[u8], writer: &mut AzimuthSplitWriter) {
+pub fn write_header_at128(packet_body: &[u8], source_address: SocketAddr, writer: &mut DynSplittingWriter) -> Result<()> {
     let header = &packet_body[6..12];
     let laser_num = header[0] as u32;
     let tail = &packet_body[1046..1086];
@@ -16,10 +18,20 @@ pub fn write_header_at128(packet_body: &[u8], writer: &mut AzimuthSplitWriter) {
     };
     let motor_speed = ((tail[19] as u32) << 8) + (tail[18] as u32);
     let frequency = motor_speed as f32 / 60.0;
-    writer.write_attribute(laser_num, frequency, return_mode, "Hesai", "AT128");
+    let mut metadata = SensorMetadata::new("Hesai", "AT128");
+    metadata.laser_num = laser_num;
+    metadata.frequency = frequency;
+    metadata.return_mode = return_mode;
+    metadata.source_address = Some(source_address);
+    // the per azimuth adjustments (AZIMUTH_ADJUST, ELEVATION_ADJUST) are not included
+    metadata.calibration = Some(Calibration {
+        elevation: ELEVATION_ANGLE.iter().map(|&angle| (angle * RESOLUTION as i32) as f32 / 25600.0).collect(),
+        azimuth_offset: HORIZONTAL_OFFSET.iter().map(|&offset| -(offset * RESOLUTION as i32) as f32 / 25600.0).collect(),
+    });
+    writer.write_attribute(metadata)
 }
 
-pub fn parse_packet_body_at128(packet_body: &[u8], writer: &mut AzimuthSplitWriter) {
+pub fn parse_packet_body_at128(packet_body: &[u8], writer: &mut impl PointSink) -> Result<()> {
     let header = &packet_body[6..12];
     let block_num = header[1] as u32;
 
@@ -36,17 +48,26 @@ pub fn parse_packet_body_at128(packet_body: &[u8], writer: &mut AzimuthSplitWrit
                         + ((tail[21] as u32) << 8) 
                         + ((tail[20] as u32));
     
+    let mut batch = PointBatch::with_capacity((block_num * 128) as usize);
     for block_index in 0..block_num {
         let block_timestamp_ns = calc_block_timestamp_ns(unix_epoch_sec, timestamp_us, block_index+1, return_mode);
         let block_start = (block_index*515) as usize;
-        parse_block(&body[block_start..block_start+515], block_timestamp_ns, writer);
+        // dual return packets alternate the blocks of the first and the second return
+        let return_index = if is_single_return(return_mode) { 0 } else { (block_index % 2) as u8 };
+        parse_block(&body[block_start..block_start+515], block_timestamp_ns, return_index, &mut batch);
     }
+    writer.write_batch(&batch)
+}
+
+/// 0x37: strongest, 0x38: last. the other modes have two returns
+fn is_single_return(return_mode: u8) -> bool {
+    return_mode == 0x37 || return_mode == 0x38
 }
 
 fn calc_block_timestamp_ns(unix_epoch_sec: u64, timestamp_us: u32, block_id: u32, return_mode: u8) -> u64 {
     // unix timeは大きすぎるので、分以下の部分だけを使う
     let t0 = (unix_epoch_sec % 3600) * 1000000000 + timestamp_us as u64 * 1000;
-    if return_mode == 0x37 || return_mode == 0x38 {
+    if is_single_return(return_mode) {
         t0 + 100000 - 9249 - 41666 * (3 - block_id as u64)
     }
     else {
@@ -63,7 +84,7 @@ fn calc_polar_coordinate(azimuth_deg: f32, v_angle_deg: f32, distance_m: f32) ->
     (x,y,z)
 }
 
-fn parse_block(packet_block: &[u8], block_timestamp_ns: u64, writer: &mut AzimuthSplitWriter) {
+fn parse_block(packet_block: &[u8], block_timestamp_ns: u64, return_index: u8, batch: &mut PointBatch) {
     let encoder_angle_raw = ((packet_block[1] as u16) << 8) + (packet_block[0] as u16);
     let encoder_fine_angle_raw = packet_block[2];
     for channel in 0..128 as usize {
@@ -79,14 +100,14 @@ fn parse_block(packet_block: &[u8], block_timestamp_ns: u64, writer: &mut Azimut
             v_angle as f32, 
             distance as f32 * 4.0 / 1000.0);
 
-        writer.write_row(VeloPoint { 
+        batch.push_return(VeloPoint { 
             intensity: reflectivity, 
             channel: channel as u8, 
             azimuth: (channel_azimuth_deg * 100.0) as u16, 
             distance_m: distance as f32 * 4.0 / 1000.0,
             timestamp: channel_timestamp_ns, 
             altitude: (v_angle * 100.0) as i16, 
-            x, y, z }, false)
+            x, y, z }, encoder_angle_raw as i64, return_index);
     }
 }
 
@@ -1,8 +1,10 @@
 use std::f32::consts::PI;
+use std::net::SocketAddr;
 
-use writer_common::{azimuthsplitwriter::AzimuthSplitWriter, velopoint::VeloPoint};
+use anyhow::Result;
+use writer_common::{paralleldecoder::PointSink, pointbatch::PointBatch, sensormetadata::{Calibration, SensorMetadata}, splittingwriter::DynSplittingWriter, velopoint::VeloPoint};
 
-pub fn write_header_xt32(packet_body: &[u8], writer: &mut AzimuthSplitWriter) {
+pub fn write_header_xt32(packet_body: &[u8], source_address: SocketAddr, writer: &mut DynSplittingWriter) -> Result<()> {
     let header = &packet_body[6..12];
     let laser_num = header[0] as u32;
     let tail = &packet_body[1052..1076];
@@ -14,10 +16,19 @@ pub fn write_header_xt32(packet_body: &[u8], writer: &mut AzimuthSplitWriter) {
     };
     let motor_speed = ((tail[12] as u32) << 8) + (tail[11] as u32);
     let frequency = motor_speed as f32 / 60.0;
-    writer.write_attribute(laser_num, frequency, return_mode, "Hesai", "XT32");
+    let mut metadata = SensorMetadata::new("Hesai", "XT32");
+    metadata.laser_num = laser_num;
+    metadata.frequency = frequency;
+    metadata.return_mode = return_mode;
+    metadata.source_address = Some(source_address);
+    metadata.calibration = Some(Calibration {
+        elevation: (0..32).map(|channel| channel_to_v_angle(channel) as f32).collect(),
+        azimuth_offset: vec![0.0; 32],
+    });
+    writer.write_attribute(metadata)
 }
 
-pub fn parse_packet_body_xt32(packet_body: &[u8], writer: &mut AzimuthSplitWriter) {
+pub fn parse_packet_body_xt32(packet_body: &[u8], writer: &mut impl PointSink) -> Result<()> {
     let header = &packet_body[6..12];
     let block_num = header[1] as u32;
 
@@ -31,16 +42,25 @@ pub fn parse_packet_body_xt32(packet_body: &[u8], writer: &mut AzimuthSplitWrite
                         + ((tail[20] as u32) << 8) 
                         + ((tail[19] as u32));
 
+    let mut batch = PointBatch::with_capacity((block_num * 32) as usize);
     for block_index in 0..block_num {
         let block_timestamp_ns = calc_block_timestamp_ns(date_time, timestamp_us, block_index+1, return_mode);
         let block_start = (block_index*130) as usize;
-        parse_block(&body[block_start..block_start+130], block_timestamp_ns, writer);
+        // dual return packets alternate the blocks of the first and the second return
+        let return_index = if is_single_return(return_mode) { 0 } else { (block_index % 2) as u8 };
+        parse_block(&body[block_start..block_start+130], block_timestamp_ns, return_index, &mut batch);
     }
+    writer.write_batch(&batch)
+}
+
+/// 0x37: strongest, 0x38: last. the other modes have two returns
+fn is_single_return(return_mode: u8) -> bool {
+    return_mode == 0x37 || return_mode == 0x38
 }
 
 fn calc_block_timestamp_ns(date_time: &[u8], timestamp_us: u32, block_id: u32, return_mode: u8) -> u64 {
     let t0 = (date_time[4] as u64) * 60 * 1000000000 + (date_time[5] as u64) * 1000000000 + timestamp_us as u64 * 1000;
-    if return_mode == 0x37 || return_mode == 0x38 {
+    if is_single_return(return_mode) {
         t0 + 3280 - 50000 * (8 - block_id as u64)
     }
     else {
@@ -61,7 +81,7 @@ fn calc_polar_coordinate(azimuth_deg: f32, v_angle_deg: f32, distance_m: f32) ->
     (x,y,z)
 }
 
-fn parse_block(packet_block: &[u8], block_timestamp_ns: u64, writer: &mut AzimuthSplitWriter) {
+fn parse_block(packet_block: &[u8], block_timestamp_ns: u64, return_index: u8, batch: &mut PointBatch) {
     let azimuth = ((packet_block[1] as u32) << 8) + (packet_block[0] as u32);
     for channel in 0..32 as u8 {
         let channel_timestamp_ns = block_timestamp_ns + 1512 * channel as u64 + 280;
@@ -75,13 +95,13 @@ fn parse_block(packet_block: &[u8], block_timestamp_ns: u64, writer: &mut Azimut
             v_angle as f32, 
             distance as f32 * 4.0 / 1000.0);
 
-        writer.write_row(VeloPoint { 
+        batch.push_return(VeloPoint { 
             intensity: reflectivity, 
             channel, 
             azimuth: azimuth as u16, 
             distance_m: distance as f32 * 4.0 / 1000.0,
             timestamp: channel_timestamp_ns, 
             altitude: (v_angle * 100) as i16, 
-            x, y, z }, false)
+            x, y, z }, azimuth as i64, return_index);
     }
 }
@@ -1,95 +1,88 @@
-use std::{path::Path, fs::File, time::Instant};
+use std::net::SocketAddr;
 
-use pcap_parser::{LegacyPcapReader, traits::PcapReaderIterator, PcapBlockOwned, PcapError};
-use writer_common::{framewriter::{FrameWriter, CsvWriter, HdfWriter, PcdWriter}, azimuthsplitwriter::AzimuthSplitWriter};
+use anyhow::{anyhow, Result};
+use writer_common::{capture::CaptureFile, convertargs::ConvertArgs, framesplitter::SplitType, framewriter::FrameWriter, packetfilter::PacketConverter, paralleldecoder::{ParallelDecoder, PointSink}, splittingwriter::{SplittingWriter, DynSplittingWriter}, udpdecoder::UdpPacket};
 
-use crate::{Args, OutType, detect_model::{detect_model, HesaiModel}, parse_xt32::{parse_packet_body_xt32, write_header_xt32}, parse_at128::{parse_packet_body_at128, write_header_at128}};
-
-pub fn run(args: Args) {
-    let input_file_path = Path::new(&args.input);
-    let stem = input_file_path.file_stem().unwrap();
-    let mut file_dir = input_file_path.parent().unwrap().to_str().unwrap().to_string();
-    if file_dir == "" {
-        file_dir = ".".to_string();
-    }
+use crate::{detect_model::{detect_model, HesaiModel}, parse_xt32::{parse_packet_body_xt32, write_header_xt32}, parse_at128::{parse_packet_body_at128, write_header_at128}};
 
+pub fn run(args: &ConvertArgs) -> Result<()> {
+    args.split.ensure_no_signal("hesai")?;
     //let start = Instant::now();
-    let file = File::open(&args.input).unwrap();
-    let mut num_packets = 0;
-    let mut reader = LegacyPcapReader::new(65536, file).expect("LegacyPcapReader");
+    let capture = CaptureFile::open(&args.input)?;
+    args.filter.convert_outputs(&capture, &args.input, &args.output, is_data_packet, |_filter, writer_internal| {
+        Converter::new(args, &capture, writer_internal)
+    })
+    //let end = start.elapsed();
+    //println!("{}.{:03}sec", end.as_secs(), end.subsec_millis() / 1000)
+}
 
-    let dir = stem.to_str().unwrap().to_string();
+/// lidar packets start with the pre-header 0xEEFF and are long enough for the model of their
+/// protocol version. shorter packets from other devices on the same port are skipped
+pub fn is_data_packet(packet: &[u8]) -> bool {
+    packet.len() >= 6 && packet[0..2] == [0xEE, 0xFF]
+        && detect_model(&packet[0..6]).packet_len().map_or(false, |len| packet.len() >= len)
+}
 
-    let writer_internal: Box<dyn FrameWriter> = match args.out_type {
-        OutType::Csv => Box::new(CsvWriter::create(file_dir, dir, stem.to_str().unwrap().to_string())),
-        OutType::Hdf => Box::new(HdfWriter::create(file_dir, stem.to_str().unwrap().to_string(), args.compression)),
-        OutType::Pcd => Box::new(PcdWriter::create(file_dir, dir, stem.to_str().unwrap().to_string())),
-    };
-    let mut writer = Box::new(AzimuthSplitWriter::new_with_min_offset(writer_internal, 60*100, 0));
+struct Converter {
+    writer: Box<DynSplittingWriter>,
+    decoder: Option<ParallelDecoder>,
+    header_written: bool,
+}
 
-    let mut header_written = false;
+impl Converter {
+    fn new(args: &ConvertArgs, capture: &CaptureFile, writer_internal: Box<dyn FrameWriter>) -> Result<Converter> {
+        let splitter = args.split.create_splitter(SplitType::Azimuth, 60*100);
+        let mut writer = Box::new(SplittingWriter::new(writer_internal, splitter, 0));
+        writer.track_input(capture.len());
+        writer.set_source(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), args.source_file()?);
 
-    let time_start = Instant::now();
-    loop {
-        match reader.next() {
-            Ok((offset, block)) => {
-                num_packets += 1;
-                match block {
-                    PcapBlockOwned::Legacy(packet) => {
-                        // println!("{}", packet.data.len());
-                        // etherのヘッダ長は14byte
-                        let ether_data = &packet.data[14..];
-                        // ipv4のヘッダ長は可変(基本20byte)
-                        let ip_header_size = ((ether_data[0] & 15) * 4) as usize;
-                        let packet_size = (((ether_data[2] as u32) << 8) + ether_data[3] as u32) as usize;
-                        let ip_data = &ether_data[ip_header_size..packet_size];
-                        // udpのヘッダ長は8byte
-                        let udp_data = &ip_data[8..ip_data.len()];
-                        if udp_data[0] != 0xEE {
-                            // ignore non-lidar packets
-                            reader.consume(offset);
-                            continue;
-                        }
-                        parse_packet_body(udp_data, &mut writer);
-                        if !header_written {
-                            header_written = true;
-                            write_header(udp_data, &mut writer);
-                        }
-                    },
-                    _ => ()
-                }
-                reader.consume(offset);
-            },
-            Err(PcapError::Eof) => break,
-            Err(PcapError::Incomplete) => {
-                reader.refill().unwrap();
-            },
-            Err(e) => panic!("error while reading: {:?}", e),
+        let decoder = if args.decode.threads > 1 {
+            Some(ParallelDecoder::new(args.decode.threads, |packet, chunk| parse_packet_body(packet, chunk)))
+        } else {
+            None
+        };
+        Ok(Converter { writer, decoder, header_written: false })
+    }
+}
+
+impl PacketConverter for Converter {
+    fn push(&mut self, packet: &UdpPacket, position: u64) -> Result<()> {
+        self.writer.set_input_position(position);
+        match self.decoder.as_mut() {
+            Some(decoder) => decoder.push(packet.payload, self.writer.as_mut())?,
+            None => parse_packet_body(packet.payload, self.writer.as_mut())?,
         }
+        if !self.header_written {
+            self.header_written = true;
+            write_header(packet.payload, packet.source, &mut self.writer)?;
+        }
+        Ok(())
     }
-    let duration = time_start.elapsed();
 
-    println!("{} packets have been processed in {:?}", num_packets, duration);
-    //let end = start.elapsed();
-    //println!("{}.{:03}sec", end.as_secs(), end.subsec_millis() / 1000)
+    fn finish(mut self) -> Result<()> {
+        if let Some(decoder) = self.decoder {
+            decoder.finish(self.writer.as_mut())?;
+        }
+        self.writer.finalize()
+    }
 }
 
-fn write_header(packet_body: &[u8], writer: &mut AzimuthSplitWriter) {
+fn write_header(packet_body: &[u8], source_address: SocketAddr, writer: &mut DynSplittingWriter) -> Result<()> {
     let pre_header = &packet_body[0..6];
     let model = detect_model(pre_header);
     match model {
-        HesaiModel::XT32 => write_header_xt32(packet_body, writer),
-        HesaiModel::AT128 => write_header_at128(packet_body, writer),
-        _ => panic!("Unknown model"),
+        HesaiModel::XT32 => write_header_xt32(packet_body, source_address, writer),
+        HesaiModel::AT128 => write_header_at128(packet_body, source_address, writer),
+        _ => Err(anyhow!("unknown model")),
     }
 }
 
-fn parse_packet_body(packet_body: &[u8], writer: &mut AzimuthSplitWriter) {
+fn parse_packet_body(packet_body: &[u8], writer: &mut impl PointSink) -> Result<()> {
     let pre_header = &packet_body[0..6];
     let model = detect_model(pre_header);
     match model {
         HesaiModel::XT32 => parse_packet_body_xt32(packet_body, writer),
         HesaiModel::AT128 => parse_packet_body_at128(packet_body, writer),
-        _ => panic!("Unknown model"),
+        _ => Err(anyhow!("unknown model")),
     }
 }
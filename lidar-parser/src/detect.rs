@@ -0,0 +1,106 @@
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Error, Result};
+use writer_common::{capture::CaptureFile, inputfile::InputFile, packetfilter::PacketFilter, udpdecoder::UdpDecoder};
+
+/// file signature of LVX and LVX2 files
+const LVX_SIGNATURE: &[u8] = b"livox_tech";
+/// factory byte of HDL-32E, VLP-16, Puck Hi-Res, VLP-32C, Velarray and VLS-128
+const VELODYNE_PRODUCT_IDS: [u8; 6] = [0x21, 0x22, 0x24, 0x28, 0x31, 0xA1];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Vendor {
+    Velodyne,
+    Hesai,
+    Leishen,
+    Livox,
+}
+
+impl FromStr for Vendor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "velodyne" => Ok(Vendor::Velodyne),
+            "hesai" => Ok(Vendor::Hesai),
+            "leishen" => Ok(Vendor::Leishen),
+            "livox" => Ok(Vendor::Livox),
+            _ => Err(anyhow!("unknown vendor: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Vendor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Vendor::Velodyne => "velodyne",
+            Vendor::Hesai => "hesai",
+            Vendor::Leishen => "leishen",
+            Vendor::Livox => "livox",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Whether the input starts with the LVX signature.
+pub fn is_lvx(input: &str) -> Result<bool> {
+    let mut signature = [0u8; LVX_SIGNATURE.len()];
+    let mut file = InputFile::open(input)?;
+    Ok(file.read_exact(&mut signature).is_ok() && signature == LVX_SIGNATURE)
+}
+
+/// Picks the decoder of the input from the LVX signature or the first UDP payload passing
+/// `filter` that is recognized by `detect_packet`.
+pub fn detect_vendor(input: &str, filter: &PacketFilter) -> Result<Vendor> {
+    if is_lvx(input)? {
+        return Ok(Vendor::Livox);
+    }
+    let capture = CaptureFile::open(input)?;
+    let mut decoder = UdpDecoder::new();
+    let mut packets = capture.packets()?;
+    while let Some(packet) = packets.next_packet()? {
+        let Some(udp) = decoder.decode(&packet) else {
+            continue;
+        };
+        if !filter.matches(&udp) {
+            continue;
+        }
+        if let Some(vendor) = detect_packet(udp.payload) {
+            return Ok(vendor);
+        }
+    }
+    bail!("no packets of a supported lidar found in {}", input)
+}
+
+/// Recognizes the vendor of a UDP payload.
+///
+/// - Velodyne: data packets with the factory bytes (return mode 0x37-0x39 and a known product ID)
+/// - Hesai: the 0xEEFF pre-header with protocol version 6.1 (XT32) or 4.3 (AT128) and the packet length of the model
+/// - Livox: SDK2 header (version 0) whose length field is the payload size
+/// - Leishen: MSOP packets with the echo byte, or DIFOP packets with their 0xA5FF005A header
+///
+/// Leishen data packets have few markers, so they are tried last.
+pub fn detect_packet(payload: &[u8]) -> Option<Vendor> {
+    // Leishen MSOP packets share the layout, so the product ID has to match too
+    if velo_pcap_parser_rs::is_data_packet(payload)
+        && matches!(payload[1204], 0x37..=0x39)
+        && VELODYNE_PRODUCT_IDS.contains(&payload[1205])
+    {
+        return Some(Vendor::Velodyne);
+    }
+    if hesai_pcap_parser_rs::is_data_packet(payload) {
+        return Some(Vendor::Hesai);
+    }
+    if livox_parser_rs::is_data_packet(payload) {
+        return Some(Vendor::Livox);
+    }
+    if ls_pcap_parser_rs::is_data_packet(payload) && matches!(payload[1205], 0x01 | 0x02) {
+        return Some(Vendor::Leishen);
+    }
+    if payload.len() == 1206 && payload[0..4] == [0xA5, 0xFF, 0x00, 0x5A] {
+        return Some(Vendor::Leishen);
+    }
+    None
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use writer_common::{
+    capture::{CaptureFile, CaptureFormat},
+    inputfile::{Compression, InputFile},
+    udpdecoder::UdpDecoder,
+};
+
+use crate::detect::{detect_packet, is_lvx, Vendor};
+
+/// Packets of a sensor found in the capture.
+struct SensorSummary {
+    source: SocketAddr,
+    vendor: Vendor,
+    packets: u64,
+}
+
+/// Prints the format of the input and the sensors found in it.
+pub fn print_info(input: &str) -> Result<()> {
+    let mut file = InputFile::open(input)?;
+    println!("file: {}", input);
+    println!("size: {} bytes", file.len());
+    let compression = match file.compression() {
+        Compression::None => "none",
+        Compression::Gzip => "gzip",
+        Compression::Zstd => "zstd",
+        Compression::Xz => "xz",
+    };
+    println!("compression: {}", compression);
+
+    if is_lvx(input)? {
+        let mut public_header_block = [0u8; 24];
+        file.read_exact(&mut public_header_block)?;
+        let version = &public_header_block[16..20];
+        println!("format: lvx {}.{}.{}.{}", version[0], version[1], version[2], version[3]);
+        println!("vendor: {}", Vendor::Livox);
+        return Ok(());
+    }
+
+    let capture = CaptureFile::open(input)?;
+    let format = match capture.format() {
+        CaptureFormat::Pcap { big_endian, nanosecond } => format!(
+            "pcap ({} endian, {} timestamps)",
+            if big_endian { "big" } else { "little" },
+            if nanosecond { "nanosecond" } else { "microsecond" },
+        ),
+        CaptureFormat::PcapNg => "pcapng".to_string(),
+    };
+    println!("format: {}", format);
+
+    let mut num_packets = 0;
+    let mut num_udp_packets = 0;
+    let mut first_timestamp = None;
+    let mut last_timestamp = 0;
+    let mut sensors: Vec<SensorSummary> = Vec::new();
+    let mut sensor_index = HashMap::new();
+    let mut decoder = UdpDecoder::new();
+    let mut packets = capture.packets()?;
+    while let Some(packet) = packets.next_packet()? {
+        num_packets += 1;
+        if packet.timestamp_ns > 0 {
+            first_timestamp.get_or_insert(packet.timestamp_ns);
+            last_timestamp = packet.timestamp_ns;
+        }
+        let Some(udp) = decoder.decode(&packet) else {
+            continue;
+        };
+        num_udp_packets += 1;
+        let Some(vendor) = detect_packet(udp.payload) else {
+            continue;
+        };
+        let index = *sensor_index.entry((udp.source, vendor)).or_insert_with(|| {
+            sensors.push(SensorSummary { source: udp.source, vendor, packets: 0 });
+            sensors.len() - 1
+        });
+        sensors[index].packets += 1;
+    }
+
+    println!("packets: {} ({} udp)", num_packets, num_udp_packets);
+    if decoder.malformed_frames() > 0 {
+        println!("malformed frames: {}", decoder.malformed_frames());
+    }
+    if let Some(first_timestamp) = first_timestamp {
+        println!("duration: {:.3} s", last_timestamp.saturating_sub(first_timestamp) as f64 / 1e9);
+    }
+    if sensors.is_empty() {
+        println!("sensors: none");
+    } else {
+        println!("sensors:");
+        for sensor in sensors.iter() {
+            println!("  {} {} ({} packets)", sensor.source, sensor.vendor, sensor.packets);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+mod detect;
+mod info;
+
+use std::process::exit;
+
+use anyhow::Result;
+use getopts::Options;
+use writer_common::convertargs::ConvertArgs;
+
+use crate::detect::{detect_vendor, Vendor};
+use crate::info::print_info;
+
+const COMMAND: &str = "lidar-parser";
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let result = match args.get(1).map(|s| s.as_str()) {
+        Some("convert") => convert(&args[2..]),
+        Some("info") => info(&args[2..]),
+        Some("help") | Some("-h") | Some("--help") | None => {
+            print_usage();
+            Ok(())
+        },
+        Some(command) => {
+            eprintln!("unknown command: {}", command);
+            print_usage();
+            exit(1);
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Error: {:?}", e);
+        exit(1);
+    }
+}
+
+fn print_usage() {
+    println!("Usage: {} <command> [options] <input>", COMMAND);
+    println!();
+    println!("Commands:");
+    println!("    convert    convert a pcap, pcapng or lvx file to point cloud frames");
+    println!("    info       print the format of the input and the sensors in it");
+    println!("    help       print this help menu");
+    println!();
+    println!("Run `{} <command> --help` for the options of a command.", COMMAND);
+}
+
+fn convert(args: &[String]) -> Result<()> {
+    let command_prefix = format!("{} convert", COMMAND);
+    let mut opts = Options::new();
+    ConvertArgs::register(&mut opts);
+    opts.optopt("", "vendor", "decoder to use instead of detecting it from the input: velodyne, hesai, leishen or livox", "VENDOR");
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(args) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_help(opts, &command_prefix);
+            exit(1);
+        }
+    };
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_help(opts, &command_prefix);
+        exit(0);
+    }
+    let convert_args = match ConvertArgs::from_matches(&matches) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_help(opts, &command_prefix);
+            exit(1);
+        }
+    };
+    let vendor = match matches.opt_get::<Vendor>("vendor") {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_help(opts, &command_prefix);
+            exit(1);
+        }
+    };
+
+    let vendor = match vendor {
+        Some(vendor) => vendor,
+        None => {
+            let vendor = detect_vendor(&convert_args.input, &convert_args.filter.filter)?;
+            println!("detected {} input", vendor);
+            vendor
+        },
+    };
+    match vendor {
+        Vendor::Velodyne => velo_pcap_parser_rs::run(&convert_args),
+        Vendor::Hesai => hesai_pcap_parser_rs::run(&convert_args),
+        Vendor::Leishen => ls_pcap_parser_rs::run(&convert_args),
+        Vendor::Livox => livox_parser_rs::run(&convert_args),
+    }
+}
+
+fn info(args: &[String]) -> Result<()> {
+    let command_prefix = format!("{} info", COMMAND);
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    let matches = match opts.parse(args) {
+        Ok(v) => v,
+        Err(e) => {
+            eprintln!("{}", e);
+            print_help(opts, &command_prefix);
+            exit(1);
+        }
+    };
+    if matches.opt_present("h") || matches.free.is_empty() {
+        print_help(opts, &command_prefix);
+        exit(0);
+    }
+    print_info(&matches.free[0])
+}
+
+fn print_help(opts: Options, command_prefix: &str) {
+    print!("{}", opts.usage(format!("Usage: {} [options] <input>", command_prefix).as_str()));
+}
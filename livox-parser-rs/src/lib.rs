@@ -1,6 +1,4 @@
-pub mod parseargs;
 pub mod run;
 mod parser;
 
-pub use parseargs::*;
 pub use run::*;
@@ -1,16 +1,13 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader, Cursor, Read, Seek},
-};
+use std::io::{BufRead, Cursor, Read, Seek};
 
 use anyhow::{anyhow, ensure, Error};
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
-use writer_common::{timesplitwriter::TimeSplitWriter, velopoint::VeloPoint};
+use writer_common::{inputfile::InputFile, sensormetadata::SensorMetadata, pointbatch::PointBatch, splittingwriter::DynSplittingWriter, velopoint::VeloPoint};
 
 pub fn parse_lvx(
-    reader: &mut BufReader<File>,
+    reader: &mut InputFile,
     frame_time_ms: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
     let mut private_header_block = [0u8; 5];
     reader.read_exact(&mut private_header_block)?;
@@ -39,7 +36,11 @@ pub fn parse_lvx(
         }
     };
 
-    writer.write_attribute(0, frequency, return_mode, "Livox", model);
+    let mut metadata = SensorMetadata::new("Livox", model);
+    metadata.frequency = frequency;
+    metadata.return_mode = return_mode;
+    metadata.serial = Some(serial_code(&device_info_block[0..16]));
+    writer.write_attribute(metadata)?;
 
     loop {
         // read each frame
@@ -54,13 +55,20 @@ pub fn parse_lvx(
         // println!("current_offset: {}, next_offset: {}", current_offset, next_offset);
         let mut frame_body = vec![0u8; (next_offset - current_offset - 24) as usize];
         reader.read_exact(&mut frame_body)?;
+        writer.set_input_position(reader.position());
 
         parse_lvx_frame_body(&frame_body, writer)?;
     }
     Ok(())
 }
 
-fn parse_lvx_frame_body(buffer: &Vec<u8>, writer: &mut TimeSplitWriter) -> Result<(), Error> {
+/// Lidar SN code in the device info block. null terminated
+pub(crate) fn serial_code(code: &[u8]) -> String {
+    let end = code.iter().position(|&c| c == 0).unwrap_or(code.len());
+    String::from_utf8_lossy(&code[..end]).to_string()
+}
+
+fn parse_lvx_frame_body(buffer: &Vec<u8>, writer: &mut DynSplittingWriter) -> Result<(), Error> {
     let mut cursor = Cursor::new(buffer);
     loop {
         // read each package
@@ -113,8 +121,9 @@ fn parse_lvx_frame_body(buffer: &Vec<u8>, writer: &mut TimeSplitWriter) -> Resul
 fn parse_lvx_data0_list(
     cursor: &mut Cursor<&Vec<u8>>,
     timestamp: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..100 {
         let x = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
         let y = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
@@ -122,7 +131,7 @@ fn parse_lvx_data0_list(
         let reflectivity = cursor.read_u8()?;
         let azimuth = (x.atan2(y) * 18000.0 / std::f32::consts::PI).rem_euclid(36000.0) as u16;
         let altitude = (z.atan2((x * x + y * y).sqrt()) * 18000.0 / std::f32::consts::PI) as i16;
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: reflectivity,
             channel: 0,
             timestamp,
@@ -132,9 +141,9 @@ fn parse_lvx_data0_list(
             x,
             y,
             z,
-        });
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// Parse a package of data type 1
@@ -150,8 +159,9 @@ fn parse_lvx_data0_list(
 fn parse_lvx_data1_list(
     cursor: &mut Cursor<&Vec<u8>>,
     timestamp: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..100 {
         let depth = cursor.read_i32::<LittleEndian>()?;
         let theta = cursor.read_u16::<LittleEndian>()?;
@@ -169,7 +179,7 @@ fn parse_lvx_data1_list(
             * (azimuth as f32 * std::f32::consts::PI / 18000.0).cos();
         let z = distance_m * (altitude as f32 * std::f32::consts::PI / 18000.0).sin();
 
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: reflectivity,
             channel: 0,
             timestamp,
@@ -179,9 +189,9 @@ fn parse_lvx_data1_list(
             x,
             y,
             z,
-        });
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// Parse a package of data type 2
@@ -198,8 +208,9 @@ fn parse_lvx_data1_list(
 fn parse_lvx_data2_list(
     cursor: &mut Cursor<&Vec<u8>>,
     timestamp: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..96 {
         let x = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
         let y = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
@@ -209,7 +220,7 @@ fn parse_lvx_data2_list(
 
         let azimuth = (x.atan2(y) * 18000.0 / std::f32::consts::PI).rem_euclid(36000.0) as u16;
         let altitude = (z.atan2((x * x + y * y).sqrt()) * 18000.0 / std::f32::consts::PI) as i16;
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: reflectivity,
             channel: tag,
             timestamp,
@@ -219,9 +230,9 @@ fn parse_lvx_data2_list(
             x,
             y,
             z,
-        });
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// Parse a package of data type 3
@@ -238,8 +249,9 @@ fn parse_lvx_data2_list(
 fn parse_lvx_data3_list(
     cursor: &mut Cursor<&Vec<u8>>,
     timestamp: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..96 {
         let depth = cursor.read_i32::<LittleEndian>()?;
         let theta = cursor.read_u16::<LittleEndian>()?;
@@ -258,7 +270,7 @@ fn parse_lvx_data3_list(
             * (azimuth as f32 * std::f32::consts::PI / 18000.0).cos();
         let z = distance_m * (altitude as f32 * std::f32::consts::PI / 18000.0).sin();
 
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: reflectivity,
             channel: tag,
             timestamp,
@@ -268,9 +280,9 @@ fn parse_lvx_data3_list(
             x,
             y,
             z,
-        });
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// Parse a package of data type 4
@@ -292,10 +304,11 @@ fn parse_lvx_data3_list(
 fn parse_lvx_data4_list(
     cursor: &mut Cursor<&Vec<u8>>,
     timestamp: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..48 {
-        for _ in 0..2 {
+        for return_index in 0..2 {
             let x = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
             let y = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
             let z = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
@@ -305,7 +318,7 @@ fn parse_lvx_data4_list(
             let azimuth = (x.atan2(y) * 18000.0 / std::f32::consts::PI).rem_euclid(36000.0) as u16;
             let altitude =
                 (z.atan2((x * x + y * y).sqrt()) * 18000.0 / std::f32::consts::PI) as i16;
-            writer.write_row(VeloPoint {
+            batch.push_return(VeloPoint {
                 intensity: reflectivity,
                 channel: tag,
                 timestamp,
@@ -315,10 +328,10 @@ fn parse_lvx_data4_list(
                 x,
                 y,
                 z,
-            });
+            }, azimuth as i64, return_index);
         }
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// Parse a package of data type 5
@@ -338,12 +351,13 @@ fn parse_lvx_data4_list(
 fn parse_lvx_data5_list(
     cursor: &mut Cursor<&Vec<u8>>,
     timestamp: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..48 {
         let theta = cursor.read_u16::<LittleEndian>()?;
         let phi = cursor.read_u16::<LittleEndian>()?;
-        for _ in 0..2 {
+        for return_index in 0..2 {
             let depth = cursor.read_u32::<LittleEndian>()?;
             let reflectivity = cursor.read_u8()?;
             let tag = cursor.read_u8()?;
@@ -359,7 +373,7 @@ fn parse_lvx_data5_list(
                 * (azimuth as f32 * std::f32::consts::PI / 18000.0).cos();
             let z = distance_m * (altitude as f32 * std::f32::consts::PI / 18000.0).sin();
 
-            writer.write_row(VeloPoint {
+            batch.push_return(VeloPoint {
                 intensity: reflectivity,
                 channel: tag,
                 timestamp,
@@ -369,10 +383,10 @@ fn parse_lvx_data5_list(
                 x,
                 y,
                 z,
-            });
+            }, azimuth as i64, return_index);
         }
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// Parse a package of data type 6
@@ -390,7 +404,7 @@ fn parse_lvx_data5_list(
 fn parse_lvx_data6_list(
     cursor: &mut Cursor<&Vec<u8>>,
     _timestamp: u64,
-    _writer: &mut TimeSplitWriter,
+    _writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
     cursor.seek(std::io::SeekFrom::Current(24))?; // skip 6 * 4 bytes
     Ok(())
@@ -1,16 +1,15 @@
-use std::{
-    fs::File,
-    io::{BufRead, BufReader, Cursor, Read, Seek},
-};
+use std::io::{BufRead, Cursor, Read, Seek};
 
 use anyhow::{anyhow, ensure, Error};
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
-use writer_common::{timesplitwriter::TimeSplitWriter, velopoint::VeloPoint};
+use writer_common::{inputfile::InputFile, sensormetadata::SensorMetadata, pointbatch::PointBatch, splittingwriter::DynSplittingWriter, velopoint::VeloPoint};
+
+use crate::parser::lvx::serial_code;
 
 pub fn parse_lvx2(
-    reader: &mut BufReader<File>,
+    reader: &mut InputFile,
     frame_time_ms: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
     let mut private_header_block = [0u8; 5];
     reader.read_exact(&mut private_header_block)?;
@@ -37,7 +36,10 @@ pub fn parse_lvx2(
         15 => "HAP", // 仕様上では10だが、ファイルを見ると15になっている
         _ => unreachable!(),
     };
-    writer.write_attribute(0, frequency, 0, "Livox", model);
+    let mut metadata = SensorMetadata::new("Livox", model);
+    metadata.frequency = frequency;
+    metadata.serial = Some(serial_code(&device_info_block[0..16]));
+    writer.write_attribute(metadata)?;
 
     loop {
         // read each frame
@@ -50,13 +52,14 @@ pub fn parse_lvx2(
         let next_offset = LittleEndian::read_u64(&frame_header[8..16]);
         let mut frame_body = vec![0u8; (next_offset - current_offset - 24) as usize];
         reader.read_exact(&mut frame_body)?;
+        writer.set_input_position(reader.position());
 
         parse_lvx2_frame_body(&frame_body, writer)?;
     }
     Ok(())
 }
 
-fn parse_lvx2_frame_body(buffer: &Vec<u8>, writer: &mut TimeSplitWriter) -> Result<(), Error> {
+fn parse_lvx2_frame_body(buffer: &Vec<u8>, writer: &mut DynSplittingWriter) -> Result<(), Error> {
     let mut cursor = Cursor::new(buffer);
     loop {
         // read each package
@@ -105,8 +108,9 @@ fn parse_lvx2_data1_list(
     cursor: &mut Cursor<&Vec<u8>>,
     timestamp: u64,
     length: u32,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..length {
         let x = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
         let y = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
@@ -116,7 +120,7 @@ fn parse_lvx2_data1_list(
 
         let azimuth = (x.atan2(y) * 18000.0 / std::f32::consts::PI).rem_euclid(36000.0) as u16;
         let altitude = (z.atan2((x * x + y * y).sqrt()) * 18000.0 / std::f32::consts::PI) as i16;
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: reflectivity,
             channel: tag,
             timestamp,
@@ -126,9 +130,9 @@ fn parse_lvx2_data1_list(
             x,
             y,
             z,
-        });
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// Parse a package of data type 2
@@ -142,8 +146,9 @@ fn parse_lvx2_data2_list(
     cursor: &mut Cursor<&Vec<u8>>,
     timestamp: u64,
     length: u32,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..length {
         let x = cursor.read_i16::<LittleEndian>()? as f32 / 100.0;
         let y = cursor.read_i16::<LittleEndian>()? as f32 / 100.0;
@@ -153,7 +158,7 @@ fn parse_lvx2_data2_list(
 
         let azimuth = (x.atan2(y) * 18000.0 / std::f32::consts::PI).rem_euclid(36000.0) as u16;
         let altitude = (z.atan2((x * x + y * y).sqrt()) * 18000.0 / std::f32::consts::PI) as i16;
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: reflectivity,
             channel: tag,
             timestamp,
@@ -163,7 +168,7 @@ fn parse_lvx2_data2_list(
             x,
             y,
             z,
-        });
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
@@ -1,14 +1,15 @@
 use std::io::{Cursor, Seek};
 
-use anyhow::Error;
+use anyhow::{anyhow, Error};
 use byteorder::{ByteOrder, LittleEndian, ReadBytesExt};
-use writer_common::{timesplitwriter::TimeSplitWriter, velopoint::VeloPoint};
+use writer_common::{pointbatch::PointBatch, splittingwriter::DynSplittingWriter, velopoint::VeloPoint};
 
-pub fn write_header(packet_body: &[u8], writer: &mut TimeSplitWriter) {
+pub fn write_header(packet_body: &[u8], writer: &mut DynSplittingWriter) -> Result<(), Error> {
     // Not implemented
+    Ok(())
 }
 
-pub fn parse_packet_body(packet_body: &[u8], writer: &mut TimeSplitWriter) {
+pub fn parse_packet_body(packet_body: &[u8], writer: &mut DynSplittingWriter) -> Result<(), Error> {
     let header = &packet_body[0..36];
     // let version = header[0]; // 0x00
     // let length = LittleEndian::read_u16(&header[1..3]);
@@ -23,22 +24,11 @@ pub fn parse_packet_body(packet_body: &[u8], writer: &mut TimeSplitWriter) {
     let mut cursor = Cursor::new(&packet_body[36..]);
 
     match data_type {
-        0x00 => {
-            parse_data0(&mut cursor, writer).unwrap();
-        }
-        0x01 => {
-            parse_data1_list(&mut cursor, timestamp, writer).unwrap();
-        }
-        0x02 => {
-            parse_data2_list(&mut cursor, timestamp, writer).unwrap();
-        }
-        0x03 => {
-            parse_data3_list(&mut cursor, timestamp, writer).unwrap();
-        }
-        _ => {
-            eprintln!("Unsupported data type: {}", data_type);
-            std::process::exit(1);
-        }
+        0x00 => parse_data0(&mut cursor, writer),
+        0x01 => parse_data1_list(&mut cursor, timestamp, writer),
+        0x02 => parse_data2_list(&mut cursor, timestamp, writer),
+        0x03 => parse_data3_list(&mut cursor, timestamp, writer),
+        _ => Err(anyhow!("Unsupported data type: {}", data_type)),
     }
 }
 
@@ -54,7 +44,7 @@ pub fn parse_packet_body(packet_body: &[u8], writer: &mut TimeSplitWriter) {
 /// - acc_x: float32 (g)
 /// - acc_y: float32 (g)
 /// - acc_z: float32 (g)
-fn parse_data0(cursor: &mut Cursor<&[u8]>, writer: &mut TimeSplitWriter) -> Result<(), Error> {
+fn parse_data0(cursor: &mut Cursor<&[u8]>, writer: &mut DynSplittingWriter) -> Result<(), Error> {
     cursor.seek(std::io::SeekFrom::Current(24))?; // skip 6 * 4 bytes
     Ok(())
 }
@@ -69,8 +59,9 @@ fn parse_data0(cursor: &mut Cursor<&[u8]>, writer: &mut TimeSplitWriter) -> Resu
 fn parse_data1_list(
     cursor: &mut Cursor<&[u8]>,
     timestamp: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..96 {
         let x = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
         let y = cursor.read_i32::<LittleEndian>()? as f32 / 1000.0;
@@ -80,7 +71,7 @@ fn parse_data1_list(
 
         let azimuth = (x.atan2(y) * 18000.0 / std::f32::consts::PI).rem_euclid(36000.0) as u16;
         let altitude = (z.atan2((x * x + y * y).sqrt()) * 18000.0 / std::f32::consts::PI) as i16;
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: reflectivity,
             channel: tag,
             timestamp,
@@ -90,9 +81,9 @@ fn parse_data1_list(
             x,
             y,
             z,
-        });
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// Parse a data type 2
@@ -105,8 +96,9 @@ fn parse_data1_list(
 fn parse_data2_list(
     cursor: &mut Cursor<&[u8]>,
     timestamp: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..96 {
         let x = cursor.read_i16::<LittleEndian>()? as f32 / 100.0;
         let y = cursor.read_i16::<LittleEndian>()? as f32 / 100.0;
@@ -116,7 +108,7 @@ fn parse_data2_list(
 
         let azimuth = (x.atan2(y) * 18000.0 / std::f32::consts::PI).rem_euclid(36000.0) as u16;
         let altitude = (z.atan2((x * x + y * y).sqrt()) * 18000.0 / std::f32::consts::PI) as i16;
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: reflectivity,
             channel: tag,
             timestamp,
@@ -126,9 +118,9 @@ fn parse_data2_list(
             x,
             y,
             z,
-        });
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// Parse a data type 3
@@ -141,8 +133,9 @@ fn parse_data2_list(
 fn parse_data3_list(
     cursor: &mut Cursor<&[u8]>,
     timestamp: u64,
-    writer: &mut TimeSplitWriter,
+    writer: &mut DynSplittingWriter,
 ) -> Result<(), Error> {
+    let mut batch = PointBatch::new();
     for _ in 0..96 {
         let depth = cursor.read_u32::<LittleEndian>()?;
         let theta = cursor.read_u16::<LittleEndian>()?;
@@ -161,7 +154,7 @@ fn parse_data3_list(
             * (altitude as f32 * std::f32::consts::PI / 18000.0).cos()
             * (azimuth as f32 * std::f32::consts::PI / 18000.0).cos();
         let z = distance_m * (altitude as f32 * std::f32::consts::PI / 18000.0).sin();
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: reflectivity,
             channel: tag,
             timestamp,
@@ -171,7 +164,7 @@ fn parse_data3_list(
             x,
             y,
             z,
-        });
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
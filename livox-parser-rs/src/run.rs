@@ -1,109 +1,83 @@
-use std::{path::{Path, PathBuf}, fs::File, io::Read, time::Instant};
+use std::{path::PathBuf, io::Read, time::Instant};
 
-use pcap_parser::{LegacyPcapReader, traits::PcapReaderIterator, PcapBlockOwned, PcapError};
-use writer_common::{framewriter::{FrameWriter, CsvWriter, HdfWriter, PcdWriter}, timesplitwriter::TimeSplitWriter};
+use anyhow::{anyhow, bail, ensure, Result};
+use writer_common::{capture::CaptureFile, convertargs::ConvertArgs, framesplitter::SplitType, framewriter::FrameWriter, inputfile::{strip_compression_extension, InputFile}, packetfilter::PacketConverter, splittingwriter::{SplittingWriter, DynSplittingWriter}, udpdecoder::UdpPacket};
 
-use crate::{parseargs::{Args, OutType}, parser::{lvx::parse_lvx, lvx2::parse_lvx2, pcap::{parse_packet_body, write_header}}};
+use crate::parser::{lvx::parse_lvx, lvx2::parse_lvx2, pcap::{parse_packet_body, write_header}};
 
-pub fn run(args: Args) {
-    let input_file_path = Path::new(&args.input);
-    let stem = input_file_path.file_stem().unwrap();
-    let mut file_dir = input_file_path.parent().unwrap().to_str().unwrap().to_string();
-    if file_dir == "" {
-        file_dir = ".".to_string();
-    }
-
-    let dir = stem.to_str().unwrap().to_string();
-
-    let writer_internal: Box<dyn FrameWriter> = match args.out_type {
-        OutType::Csv => Box::new(CsvWriter::create(file_dir, dir, stem.to_str().unwrap().to_string())),
-        OutType::Hdf => Box::new(HdfWriter::create(file_dir, stem.to_str().unwrap().to_string(), args.compression)),
-        OutType::Pcd => Box::new(PcdWriter::create(file_dir, dir, stem.to_str().unwrap().to_string())),
-    };
-    let mut writer = TimeSplitWriter::new(writer_internal, args.frame_time_ms * 1000 * 1000, 0);
-
-    let file_path = PathBuf::from(&args.input);
+pub fn run(args: &ConvertArgs) -> Result<()> {
+    args.split.ensure_no_signal("livox")?;
+    ensure!(args.decode.threads == 1, "--decode-threads is not supported for livox input");
+    let file_path = PathBuf::from(strip_compression_extension(&args.input));
     let extension = file_path.extension().unwrap().to_str().unwrap();
     match extension {
-        "pcap" => {
-            read_pcap_file(&args.input, args.frame_time_ms, &mut writer);
-        }
+        "pcap" | "pcapng" => {
+            let capture = CaptureFile::open(&args.input)?;
+            args.filter.convert_outputs(&capture, &args.input, &args.output, is_data_packet, |_filter, writer_internal| {
+                let mut writer = create_writer(args, writer_internal)?;
+                writer.track_input(capture.len());
+                Ok(PcapConverter { writer, header_written: false })
+            })
+        },
         "lvx" | "lvx2" => {
-            read_lvx_file(&args.input, args.frame_time_ms, &mut writer);
-        }
-        _ => {
-            eprintln!("Invalid file format");
-            std::process::exit(1);
-        }
+            let filter = &args.filter.filter;
+            ensure!(!args.filter.demux && filter.src_ip.is_none() && filter.port.is_none(), "--src-ip, --port and --demux are only supported for pcap input");
+            let mut writer = create_writer(args, args.output.create_writer(&args.input)?)?;
+            read_lvx_file(&args.input, args.split.frame_time_ms, &mut writer)
+        },
+        _ => Err(anyhow!("Invalid file format")),
     }
 }
 
-fn read_pcap_file(path: &str, frame_time_ms: u64, mut writer: &mut TimeSplitWriter) {
-    let file = File::open(path).unwrap();
-    let mut num_packets = 0;
-    let mut reader = LegacyPcapReader::new(65536, file).expect("LegacyPcapReader");
+fn create_writer(args: &ConvertArgs, writer_internal: Box<dyn FrameWriter>) -> Result<DynSplittingWriter> {
+    let splitter = args.split.create_splitter(SplitType::Time, 0);
+    let mut writer = SplittingWriter::new(writer_internal, splitter, 0);
+    writer.set_source(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), args.source_file()?);
+    Ok(writer)
+}
 
-    let mut header_written = false;
+/// packets of the Livox SDK2 ethernet protocol (version 0) with a full header whose length field
+/// is the payload size and whose data type is known (0-3). other UDP traffic on a multi-sensor
+/// capture rarely passes all three
+pub fn is_data_packet(packet: &[u8]) -> bool {
+    packet.len() >= 36 && packet[0] == 0x00
+        && u16::from_le_bytes([packet[1], packet[2]]) as usize == packet.len()
+        && packet[10] <= 3
+}
 
-    let time_start = Instant::now();
-    loop {
-        match reader.next() {
-            Ok((offset, block)) => {
-                num_packets += 1;
-                match block {
-                    PcapBlockOwned::Legacy(packet) => {
-                        // println!("{}", packet.data.len());
-                        // etherのヘッダ長は14byte
-                        let ether_data = &packet.data[14..];
-                        // ipv4のヘッダ長は可変(基本20byte)
-                        let ip_header_size = ((ether_data[0] & 15) * 4) as usize;
-                        let packet_size = (((ether_data[2] as u32) << 8) + ether_data[3] as u32) as usize;
-                        let ip_data = &ether_data[ip_header_size..packet_size];
-                        // udpのヘッダ長は8byte
-                        let udp_data = &ip_data[8..ip_data.len()];
-                        // if udp_data[0] != 0xEE {
-                        //     // ignore non-lidar packets
-                        //     reader.consume(offset);
-                        //     continue;
-                        // }
-                        parse_packet_body(udp_data, &mut writer);
-                        if !header_written {
-                            header_written = true;
-                            write_header(udp_data, &mut writer);
-                        }
-                    },
-                    _ => ()
-                }
-                reader.consume(offset);
-            },
-            Err(PcapError::Eof) => {
-                writer.finalize();
-                break;
-            },
-            Err(PcapError::Incomplete) => {
-                reader.refill().unwrap();
-            },
-            Err(e) => panic!("error while reading: {:?}", e),
+struct PcapConverter {
+    writer: DynSplittingWriter,
+    header_written: bool,
+}
+
+impl PacketConverter for PcapConverter {
+    fn push(&mut self, packet: &UdpPacket, position: u64) -> Result<()> {
+        self.writer.set_input_position(position);
+        parse_packet_body(packet.payload, &mut self.writer)?;
+        if !self.header_written {
+            self.header_written = true;
+            write_header(packet.payload, &mut self.writer)?;
         }
+        Ok(())
     }
-    let duration = time_start.elapsed();
 
-    println!("{} packets have been processed in {:?}", num_packets, duration);
+    fn finish(mut self) -> Result<()> {
+        self.writer.finalize()
+    }
 }
 
-fn read_lvx_file(path: &str, frame_time_ms: u64, mut writer: &mut TimeSplitWriter) {
-    let file = File::open(path).unwrap();
-    let mut reader = std::io::BufReader::new(file);
+fn read_lvx_file(path: &str, frame_time_ms: u64, mut writer: &mut DynSplittingWriter) -> Result<()> {
+    let mut reader = InputFile::open(path)?;
+    writer.track_input(reader.len());
 
     let time_start = Instant::now();
 
     // check public header
     let mut public_header_block = [0u8; 24];
-    reader.read_exact(&mut public_header_block).unwrap();
+    reader.read_exact(&mut public_header_block)?;
     if public_header_block[..10] != "livox_tech".as_bytes()[..] // file signature
     || public_header_block[20..24] != [0x67, 0xA7, 0x0E, 0xAC] {// magic code
-        eprintln!("Invalid file format");
-        std::process::exit(1);
+        bail!("Invalid file format");
     }
     let ver_a = public_header_block[16];
     let ver_b = public_header_block[17];
@@ -111,18 +85,18 @@ fn read_lvx_file(path: &str, frame_time_ms: u64, mut writer: &mut TimeSplitWrite
     let ver_d = public_header_block[19];
     match (ver_a, ver_b, ver_c, ver_d) {
         (1, 1, 0, 0) => {
-            parse_lvx(&mut reader, frame_time_ms, &mut writer).unwrap();
+            parse_lvx(&mut reader, frame_time_ms, &mut writer)?;
         },
         (2, 0, 0, 0) => {
-            parse_lvx2(&mut reader, frame_time_ms, &mut writer).unwrap();
+            parse_lvx2(&mut reader, frame_time_ms, &mut writer)?;
         }
         _ => {
-            eprintln!("Invalid file format version: {}.{}.{}.{}", ver_a, ver_b, ver_c, ver_d);
-            std::process::exit(1);
+            bail!("Invalid file format version: {}.{}.{}.{}", ver_a, ver_b, ver_c, ver_d);
         }
     }
-    writer.finalize();
+    writer.finalize()?;
     let duration = time_start.elapsed();
 
     println!("file have been processed in {:?}", duration);
+    Ok(())
 }
@@ -1,8 +1,6 @@
-pub mod parseargs;
 pub mod run;
 mod packetinfo;
-mod signalsplitwriter;
 mod constants;
 
-pub use parseargs::*;
-pub use run::*;
\ No newline at end of file
+pub use run::*;
+pub use packetinfo::is_data_packet;
\ No newline at end of file
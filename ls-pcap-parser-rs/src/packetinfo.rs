@@ -1,9 +1,10 @@
-use std::fs::File;
+use std::net::SocketAddr;
 
 use anyhow::{Error, ensure, anyhow};
 use byteorder::{ByteOrder, BigEndian};
-use pcap_parser::*;
-use pcap_parser::traits::PcapReaderIterator;
+use writer_common::capture::CaptureFile;
+use writer_common::packetfilter::PacketFilter;
+use writer_common::udpdecoder::UdpDecoder;
 
 #[derive(Debug)]
 pub enum ReturnMode {
@@ -15,61 +16,50 @@ pub enum ReturnMode {
 pub struct PcapInfo {
     pub return_mode: ReturnMode,
     pub frequency: f32, // Hz
+    pub source_address: Option<SocketAddr>,
 }
 
-pub fn parse_packet_info(filename: &str) -> Result<PcapInfo, Error> {
-    let file = File::open(filename).unwrap();
-    let mut reader = LegacyPcapReader::new(65536, file).expect("LegacyPcapReader");
+/// MSOP packets carrying the points. DIFOP (device) packets start with 0xA5.
+pub fn is_data_packet(packet: &[u8]) -> bool {
+    packet.len() == 1206 && (packet[0] < 128 || packet[0] == 0xFF)
+}
 
+/// The device packets are matched by the source address only, as they are sent from another
+/// port than the points.
+pub fn parse_packet_info(capture: &CaptureFile, filter: &PacketFilter) -> Result<PcapInfo, Error> {
     let mut frequency: Option<f32> = None;
     let mut return_mode: Option<ReturnMode> = None;
+    let mut source_address: Option<SocketAddr> = None;
+
+    let mut udp_decoder = UdpDecoder::new();
+    let mut packets = capture.packets()?;
+    while let Some(packet) = packets.next_packet()? {
+        let Some(udp) = udp_decoder.decode(&packet) else {
+            continue;
+        };
+        let udp_data = udp.payload;
+        if udp_data.is_empty() || !filter.matches_ip(udp.source.ip()) {
+            continue;
+        }
 
-    loop {
-        match reader.next() {
-            Ok((offset, block)) => {
-                match block {
-                    PcapBlockOwned::Legacy(packet) => {
-                        let ether_type = &packet.data[12..14];
-                        if ether_type != &[0x08, 0x00] {
-                            // not ipv4
-                            reader.consume(offset);
-                            continue;
-                        }
-                        
-                        let udp_data = &packet.data[42..];
-                        if udp_data.is_empty() {
-                            reader.consume(offset);
-                            continue;
-                        }
-                        
-                        let first_byte = udp_data[0];
-                        if (first_byte < 128 || first_byte == 0xFF) && return_mode.is_none() {
-                            // data package
-                            let factory_return_mode = udp_data[1205];
-                            return_mode = match factory_return_mode {
-                                0x01 => Some(ReturnMode::Single),
-                                0x02 => Some(ReturnMode::Dual),
-                                _ => return Err(anyhow!("unknown return mode: {}", factory_return_mode)),
-                            };
-                        }
-                        if first_byte == 0xA5 && frequency.is_none() {
-                            // device package
-                            let motor_speed_rpm = BigEndian::read_u16(&udp_data[8..10]);
-                            frequency = Some(motor_speed_rpm as f32 / 60.0);
-                        }
-                    }
-                    _ => ()
-                }
-                if frequency.is_some() && return_mode.is_some() {
-                    break;
-                }
-                reader.consume(offset);
-            },
-            Err(PcapError::Eof) => break,
-            Err(PcapError::Incomplete) => {
-                reader.refill().unwrap();
-            },
-            Err(e) => panic!("error while reading: {:?}", e),
+        let first_byte = udp_data[0];
+        if is_data_packet(udp_data) && filter.matches(&udp) && return_mode.is_none() {
+            // data package
+            let factory_return_mode = udp_data[1205];
+            return_mode = match factory_return_mode {
+                0x01 => Some(ReturnMode::Single),
+                0x02 => Some(ReturnMode::Dual),
+                _ => return Err(anyhow!("unknown return mode: {}", factory_return_mode)),
+            };
+            source_address = Some(udp.source);
+        }
+        if first_byte == 0xA5 && udp_data.len() >= 10 && frequency.is_none() {
+            // device package
+            let motor_speed_rpm = BigEndian::read_u16(&udp_data[8..10]);
+            frequency = Some(motor_speed_rpm as f32 / 60.0);
+        }
+        if frequency.is_some() && return_mode.is_some() {
+            break;
         }
     }
 
@@ -79,5 +69,6 @@ pub fn parse_packet_info(filename: &str) -> Result<PcapInfo, Error> {
     Ok(PcapInfo {
         return_mode: return_mode.unwrap(),
         frequency: frequency.unwrap(),
+        source_address,
     })
 }
@@ -1,109 +1,88 @@
 use std::f32::consts::PI;
-use std::fs::File;
-use std::path::Path;
 use std::time::Instant;
 
-use anyhow::Error;
+use anyhow::{Error, Result};
 use byteorder::{BigEndian, ByteOrder};
-use pcap_parser::*;
-use pcap_parser::traits::PcapReaderIterator;
-use writer_common::framewriter::{FrameWriter, CsvWriter, HdfWriter, PcdWriter};
+use writer_common::capture::CaptureFile;
+use writer_common::convertargs::ConvertArgs;
+use writer_common::framesplitter::SplitType;
+use writer_common::framewriter::FrameWriter;
+use writer_common::packetfilter::{PacketConverter, PacketFilter};
+use writer_common::paralleldecoder::{ParallelDecoder, PointSink};
+use writer_common::pointbatch::PointBatch;
+use writer_common::sensormetadata::SensorMetadata;
+use writer_common::splittingwriter::{SplittingWriter, DynSplittingWriter};
+use writer_common::udpdecoder::UdpPacket;
 use writer_common::velopoint::VeloPoint;
 
-use crate::signalsplitwriter::SignalSplitWriter;
-use crate::{Args, OutType};
-use crate::packetinfo::{parse_packet_info, PcapInfo, ReturnMode};
+use crate::packetinfo::{is_data_packet, parse_packet_info, PcapInfo, ReturnMode};
 use crate::constants::*;
 
-pub fn run(args: Args) {
-    let input_file_path = Path::new(&args.input);
-    let stem = input_file_path.file_stem().unwrap();
-    let mut file_dir = input_file_path.parent().unwrap().to_str().unwrap().to_string();
-    if file_dir == "" {
-        file_dir = ".".to_string();
-    }
+pub fn run(args: &ConvertArgs) -> Result<()> {
+    let capture = CaptureFile::open(&args.input)?;
+    args.filter.convert_outputs(&capture, &args.input, &args.output, is_data_packet, |filter, writer_internal| {
+        Converter::new(args, &capture, filter, writer_internal)
+    })
+}
 
-    let dir = stem.to_str().unwrap().to_string();
+struct Converter {
+    writer: Box<DynSplittingWriter>,
+    decoder: Option<ParallelDecoder>,
+}
 
-    let writer_internal: Box<dyn FrameWriter> = match args.out_type {
-        OutType::Csv => Box::new(CsvWriter::create(file_dir, dir, stem.to_str().unwrap().to_string())),
-        OutType::Hdf => Box::new(HdfWriter::create(file_dir, stem.to_str().unwrap().to_string(), args.compression)),
-        OutType::Pcd => Box::new(PcdWriter::create(file_dir, dir, stem.to_str().unwrap().to_string())),
-    };
-    let mut writer = Box::new(SignalSplitWriter::new(writer_internal));
-
-    let time_start = Instant::now();
-    let pcap_info = parse_packet_info(&args.input).unwrap();
-    let end = time_start.elapsed();
-    println!("{}us", end.as_micros());
-    println!("{:?}", pcap_info);
-
-    write_header(&pcap_info, &mut writer);
-
-    let file = File::open(&args.input).unwrap();
-    let mut num_packets = 0;
-    let mut reader = LegacyPcapReader::new(65536, file).expect("LegacyPcapReader");
-
-    let time_start = Instant::now();
-    loop {
-        match reader.next() {
-            Ok((offset, block)) => {
-                match block {
-                    PcapBlockOwned::Legacy(packet) => {
-                        // println!("{}", packet.data.len());
-                        let ether_type = &packet.data[12..14];
-                        if ether_type != &[0x08, 0x00] {
-                            // not ipv4
-                            reader.consume(offset);
-                            continue;
-                        }
-                        
-                        let udp_data = &packet.data[42..];
-                        if udp_data.is_empty() {
-                            reader.consume(offset);
-                            continue;
-                        }
-                        num_packets += 1;
-
-                        let first_byte = udp_data[0];
-                        if first_byte < 128 || first_byte == 255 {
-                            // data package
-                            let factory_return_mode = udp_data[1205];
-                            match factory_return_mode {
-                                0x01 => parse_body_single(udp_data, &mut writer).expect("parse failed"),
-                                0x02 => parse_body_dual(udp_data, &mut writer).expect("parse failed"),
-                                _ => (),
-                            }
-                        }
-                    },
-                    _ => ()
-                }
-                reader.consume(offset);
-            },
-            Err(PcapError::Eof) => {
-                writer.finalize();
-                break;
-            },
-            Err(PcapError::Incomplete) => {
-                reader.refill().unwrap();
-            },
-            Err(e) => panic!("error while reading: {:?}", e),
+impl Converter {
+    fn new(args: &ConvertArgs, capture: &CaptureFile, filter: &PacketFilter, writer_internal: Box<dyn FrameWriter>) -> Result<Converter> {
+        let splitter = args.split.create_splitter(SplitType::Signal, 0);
+        let mut writer = Box::new(SplittingWriter::new(writer_internal, splitter, 0));
+        writer.track_input(capture.len());
+        writer.set_source(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), args.source_file()?);
+
+        let time_start = Instant::now();
+        let pcap_info = parse_packet_info(capture, filter)?;
+        let end = time_start.elapsed();
+        println!("{}us", end.as_micros());
+        println!("{:?}", pcap_info);
+
+        write_header(&pcap_info, &mut writer)?;
+
+        let decoder = if args.decode.threads > 1 {
+            Some(ParallelDecoder::new(args.decode.threads, |packet, chunk| parse_packet(packet, chunk)))
+        } else {
+            None
+        };
+        Ok(Converter { writer, decoder })
+    }
+}
+
+impl PacketConverter for Converter {
+    fn push(&mut self, packet: &UdpPacket, position: u64) -> Result<()> {
+        self.writer.set_input_position(position);
+        match self.decoder.as_mut() {
+            Some(decoder) => decoder.push(packet.payload, self.writer.as_mut()),
+            None => parse_packet(packet.payload, self.writer.as_mut()),
         }
     }
-    let duration = time_start.elapsed();
 
-    println!("{} packets have been processed in {:?}", num_packets, duration);
+    fn finish(mut self) -> Result<()> {
+        if let Some(decoder) = self.decoder {
+            decoder.finish(self.writer.as_mut())?;
+        }
+        self.writer.finalize()
+    }
 }
 
-fn write_header(info: &PcapInfo, writer: &mut SignalSplitWriter) {
+fn write_header(info: &PcapInfo, writer: &mut DynSplittingWriter) -> Result<()> {
     let laser_num = 128;
     let return_mode = match info.return_mode {
         ReturnMode::Single => 0,
         ReturnMode::Dual => 2,
     };
-    let manufacturer = "Leishen";
-    let model = "CH128x1";
-    writer.write_attribute(laser_num, info.frequency, return_mode, manufacturer, model);
+    let mut metadata = SensorMetadata::new("Leishen", "CH128x1");
+    metadata.laser_num = laser_num;
+    metadata.frequency = info.frequency;
+    metadata.return_mode = return_mode;
+    metadata.source_address = info.source_address;
+    writer.write_attribute(metadata)
 }
 
 /// construction:
@@ -120,12 +99,27 @@ fn write_header(info: &PcapInfo, writer: &mut SignalSplitWriter) {
 ///   - timestamp (4 bytes) [unit: us]
 ///   - vendor (1 byte)
 ///   - echo (1 byte)
-fn parse_body_single(body: &[u8], writer: &mut SignalSplitWriter) -> Result<(), Error> {
+fn parse_packet(udp_data: &[u8], writer: &mut impl PointSink) -> Result<(), Error> {
+    let first_byte = udp_data[0];
+    if first_byte < 128 || first_byte == 255 {
+        // data package
+        let factory_return_mode = udp_data[1205];
+        match factory_return_mode {
+            0x01 => parse_body_single(udp_data, writer)?,
+            0x02 => parse_body_dual(udp_data, writer)?,
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn parse_body_single(body: &[u8], writer: &mut impl PointSink) -> Result<(), Error> {
     let hour = body[1197];
     let minute = body[1198];
     let second = body[1199];
     let us = BigEndian::read_u32(&body[1200..1204]);
     let timestamp_ns = ((hour as u64 * 60 + minute as u64) * 60 + second as u64) * 1000000000 + us as u64 * 1000;
+    let mut batch = PointBatch::with_capacity(171);
     for _ in 0..171 {
         let line_num = body[0];
         let horizontal_angle = BigEndian::read_u16(&body[1..3]);
@@ -144,7 +138,7 @@ fn parse_body_single(body: &[u8], writer: &mut SignalSplitWriter) -> Result<(),
         let y = distance_m * omega.cos() * alpha.cos();
         let z = distance_m * omega.sin();
 
-        writer.write_row(VeloPoint {
+        batch.push(VeloPoint {
             intensity: strength,
             channel: line_num,
             azimuth,
@@ -152,9 +146,9 @@ fn parse_body_single(body: &[u8], writer: &mut SignalSplitWriter) -> Result<(),
             timestamp: timestamp_ns,
             altitude,
             x, y, z,
-        })
+        }, azimuth as i64);
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 /// construction:
@@ -171,21 +165,24 @@ fn parse_body_single(body: &[u8], writer: &mut SignalSplitWriter) -> Result<(),
 ///   - timestamp (4 bytes) [unit: us]
 ///   - vendor (1 byte)
 ///   - echo (1 byte)
-fn parse_body_dual(body: &[u8], writer: &mut SignalSplitWriter) -> Result<(), Error> {
+fn parse_body_dual(body: &[u8], writer: &mut impl PointSink) -> Result<(), Error> {
     let second = body[1199];
     let us = BigEndian::read_u32(&body[1200..1204]);
     let timestamp_ns = second as u64 * 1000000000 + us as u64 * 1000;
 
+    let mut batch = PointBatch::with_capacity(218);
     for i in 0..109 {
         let point = &body[11 * i..11 * (i + 1)];
         let line_num = point[0];
         if line_num == 255 {
-            // frame split signal
-            writer.split_frame();
+            // frame split signal. the points before it belong to the previous frame
+            writer.write_batch(&batch)?;
+            batch.clear();
+            writer.signal_frame()?;
             continue;
         }
         let horizontal_angle = BigEndian::read_u16(&point[1..3]);
-        for _ in 0..2 {
+        for return_index in 0..2 {
             let distance_cm = BigEndian::read_u16(&point[3..5]) as f32 
             + point[5] as f32 / 256.0;
             let strength = point[6];
@@ -201,7 +198,7 @@ fn parse_body_dual(body: &[u8], writer: &mut SignalSplitWriter) -> Result<(), Er
             let y = distance_m * omega.cos() * alpha.cos();
             let z = distance_m * omega.sin();
 
-            writer.write_row(VeloPoint {
+            batch.push_return(VeloPoint {
                 intensity: strength,
                 channel: line_num,
                 azimuth,
@@ -209,10 +206,10 @@ fn parse_body_dual(body: &[u8], writer: &mut SignalSplitWriter) -> Result<(), Er
                 timestamp: timestamp_ns,
                 altitude,
                 x, y, z,
-            })
+            }, azimuth as i64, return_index);
         }
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 fn get_altitude(azimuth: u16, line_num: u8) -> i16 {
@@ -1,128 +1,68 @@
-use pcap_parser::*;
-use pcap_parser::traits::PcapReaderIterator;
-use writer_common::{framewriter::{FrameWriter, CsvWriter, HdfWriter, PcdWriter}, velopoint::VeloPoint, valueslopesplitwriter::ValueSlopeSplitWriter};
-use std::fs::File;
-use std::path::Path;
-use std::process::exit;
-use std::time::Instant;
-use getopts::Options;
+use writer_common::{velopoint::VeloPoint, capture::CaptureFile, convertargs::ConvertArgs, framesplitter::SplitType, framewriter::FrameWriter, packetfilter::{PacketConverter, PacketFilter}, paralleldecoder::{ParallelDecoder, PointSink}, pointbatch::PointBatch, sensormetadata::{Calibration, SensorMetadata}, splittingwriter::{SplittingWriter, DynSplittingWriter}, udpdecoder::{UdpDecoder, UdpPacket}};
+use std::net::SocketAddr;
 use anyhow::{Result, Error, ensure, anyhow};
 use byteorder::{LittleEndian, ByteOrder};
 
 // TODO: dual returnでreturnが1つしかない場合に対応する
 
-pub fn run(args: Args) {
-    let input_file_path = Path::new(&args.input);
-    let stem = input_file_path.file_stem().unwrap();
-    let mut file_dir = input_file_path.parent().unwrap().to_str().unwrap().to_string();
-    if file_dir == "" {
-        file_dir = ".".to_string();
-    }
+pub fn run(args: &ConvertArgs) -> Result<()> {
+    args.split.ensure_no_signal("velodyne")?;
+    let capture = CaptureFile::open(&args.input)?;
+    args.filter.convert_outputs(&capture, &args.input, &args.output, is_data_packet, |filter, writer_internal| {
+        Converter::new(args, &capture, filter, writer_internal)
+    })
+}
 
-    let dir = stem.to_str().unwrap().to_string();
+/// Velodyne data packets start with the flag of the first block. Position packets are skipped.
+pub fn is_data_packet(packet: &[u8]) -> bool {
+    packet.len() == 1206 && packet[0..2] == [0xFF, 0xEE]
+}
 
-    let pcap_info = parse_packet_info(&args.input).unwrap();
+struct Converter {
+    info: PcapInfo,
+    writer: Box<DynSplittingWriter>,
+    decoder: Option<ParallelDecoder>,
+}
 
-    let writer_internal: Box<dyn FrameWriter> = match args.out_type {
-        OutType::Csv => Box::new(CsvWriter::create(file_dir, dir, stem.to_str().unwrap().to_string())),
-        OutType::Hdf => Box::new(HdfWriter::create(file_dir, stem.to_str().unwrap().to_string(), args.compression)),
-        OutType::Pcd => Box::new(PcdWriter::create(file_dir, dir, stem.to_str().unwrap().to_string())),
-    };
-    let mut writer = Box::new(ValueSlopeSplitWriter::new(writer_internal, pcap_info.num_frames as u64));
-
-    write_header(&pcap_info, &mut writer);
-
-    let file = File::open(&args.input).unwrap();
-    let mut num_packets = 0;
-    let mut reader = LegacyPcapReader::new(65536, file).expect("LegacyPcapReader");
-
-    let time_start = Instant::now();
-    loop {
-        match reader.next() {
-            Ok((offset, block)) => {
-                num_packets += 1;
-                match block {
-                    PcapBlockOwned::Legacy(packet) => {
-                        // println!("{}", packet.data.len());
-                        // etherのヘッダ長は14byte
-                        let ether_data = &packet.data[14..];
-                        // ipv4のヘッダ長は可変(基本20byte)
-                        let ip_header_size = ((ether_data[0] & 15) * 4) as usize;
-                        let packet_size = (((ether_data[2] as u32) << 8) + ether_data[3] as u32) as usize;
-                        let ip_data = &ether_data[ip_header_size..packet_size];
-                        // udpのヘッダ長は8byte
-                        let udp_data = &ip_data[8..ip_data.len()];
-                        parse_packet_body(udp_data, &pcap_info, &mut writer).expect("parse failed");
-                    },
-                    _ => ()
-                }
-                reader.consume(offset);
-            },
-            Err(PcapError::Eof) => {
-                writer.finalize();
-                break;
-            },
-            Err(PcapError::Incomplete) => {
-                reader.refill().unwrap();
-            },
-            Err(e) => panic!("error while reading: {:?}", e),
-        }
-    }
-    let duration = time_start.elapsed();
+impl Converter {
+    fn new(args: &ConvertArgs, capture: &CaptureFile, filter: &PacketFilter, writer_internal: Box<dyn FrameWriter>) -> Result<Converter> {
+        let pcap_info = parse_packet_info(capture, filter)?;
 
-    println!("{} packets have been processed in {:?}", num_packets, duration);
-}
+        let splitter = args.split.create_splitter(SplitType::Slope, 0);
+        let mut writer = Box::new(SplittingWriter::new(writer_internal, splitter, 0));
+        writer.track_input(capture.len());
+        writer.set_source(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"), args.source_file()?);
 
-pub enum OutType {
-    Csv,
-    Hdf,
-    Pcd,
-}
+        write_header(&pcap_info, &mut writer)?;
 
-pub struct Args {
-    input: String,
-    out_type: OutType,
-    compression: bool,
+        let decoder = if args.decode.threads > 1 {
+            let info = pcap_info.clone();
+            Some(ParallelDecoder::new(args.decode.threads, move |packet, chunk| parse_packet_body(packet, &info, chunk)))
+        } else {
+            None
+        };
+        Ok(Converter { info: pcap_info, writer, decoder })
+    }
 }
 
-pub fn parse_args(command_prefix: &str, args: &Vec<String>) -> Args {
-    let mut opts = Options::new();
-    opts.optopt("o", "output", "output type", "csv|hdf|pcd");
-    opts.optflag("h", "help", "print this help menu");
-    opts.optflag("c", "compression", "enable compression");
-    let matches = opts.parse(args).unwrap();
-    if matches.opt_present("h") {
-        print_help(opts, command_prefix);
-        exit(0);
-    }
-    let input = if !matches.free.is_empty() {
-        matches.free[0].clone()
-    } else {
-        print_help(opts, command_prefix);
-        exit(0);
-    };
-    let out_type = if matches.opt_present("o") {
-        match matches.opt_str("o").unwrap().as_str() {
-            "csv" => OutType::Csv,
-            "hdf" => OutType::Hdf,
-            "pcd" => OutType::Pcd,
-            _ => {
-                print_help(opts, command_prefix);
-                exit(0);
-            }
+impl PacketConverter for Converter {
+    fn push(&mut self, packet: &UdpPacket, position: u64) -> Result<()> {
+        self.writer.set_input_position(position);
+        match self.decoder.as_mut() {
+            Some(decoder) => decoder.push(packet.payload, self.writer.as_mut()),
+            None => parse_packet_body(packet.payload, &self.info, self.writer.as_mut()),
         }
-    } else {
-        OutType::Csv
-    };
-    let compression = matches.opt_present("c");
-    Args { input, out_type, compression }
-}
+    }
 
-fn print_help(opts: Options, command_prefix: &str) {
-    print!("{}", opts.usage(format!("Usage: {} [options] <input>", command_prefix).as_str()));
+    fn finish(mut self) -> Result<()> {
+        if let Some(decoder) = self.decoder {
+            decoder.finish(self.writer.as_mut())?;
+        }
+        self.writer.finalize()
+    }
 }
 
-fn write_header(info: &PcapInfo, writer: &mut ValueSlopeSplitWriter) {
+fn write_header(info: &PcapInfo, writer: &mut DynSplittingWriter) -> Result<()> {
     let laser_num = match info.product {
         VeloProduct::Vlp16 => 16,
         VeloProduct::Vlp32c => 32,
@@ -132,15 +72,30 @@ fn write_header(info: &PcapInfo, writer: &mut ValueSlopeSplitWriter) {
         ReturnMode::Last => 1,
         ReturnMode::Dual => 2,
     };
-    let manufacturer = "Velodyne";
     let model = match info.product {
         VeloProduct::Vlp16 => "VLP-16",
         VeloProduct::Vlp32c => "VLP-32C",
     };
-    writer.write_attribute(laser_num, info.frequency, return_mode, manufacturer, model);
+    let calibration = match info.product {
+        VeloProduct::Vlp16 => Calibration {
+            elevation: VLP16_LASER_ANGLES.to_vec(),
+            azimuth_offset: vec![0.0; 16],
+        },
+        VeloProduct::Vlp32c => Calibration {
+            elevation: VLP32C_LASER_ANGLES.to_vec(),
+            azimuth_offset: VLP32C_AZIMUTH_OFFSETS.iter().map(|&offset| offset as f32 * ROTATION_RESOLUTION).collect(),
+        },
+    };
+    let mut metadata = SensorMetadata::new("Velodyne", model);
+    metadata.laser_num = laser_num;
+    metadata.frequency = info.frequency;
+    metadata.return_mode = return_mode;
+    metadata.source_address = info.source_address;
+    metadata.calibration = Some(calibration);
+    writer.write_attribute(metadata)
 }
 
-fn parse_packet_body(packet_body: &[u8], info: &PcapInfo, writer: &mut ValueSlopeSplitWriter) -> Result<(), Error> {
+fn parse_packet_body(packet_body: &[u8], info: &PcapInfo, writer: &mut impl PointSink) -> Result<(), Error> {
     ensure!(packet_body.len() == 1206, "packet size is not 1206");
     let timestamp = LittleEndian::read_u32(&packet_body[1200..1204]);
 
@@ -178,7 +133,7 @@ const VLP16_LASER_ANGLES: [f32; 16] = [
     -15.0, 1.0, -13.0, 3.0, -11.0, 5.0, -9.0, 7.0, -7.0, 9.0, -5.0, 11.0, -3.0, 13.0, -1.0, 15.0,
 ];
 const VLP16_DISTANCE_RESOLUTION: f32 = 0.002;
-fn parse_vlp16_single(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer: &mut ValueSlopeSplitWriter) -> Result<(), Error> {
+fn parse_vlp16_single(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer: &mut impl PointSink) -> Result<(), Error> {
     // blocks: 100 bytes * 12
     //   flag(0xFFEE)  : 2 bytes
     //   azimuth       : 2 bytes
@@ -187,6 +142,7 @@ fn parse_vlp16_single(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writ
     //     distance    : 2 bytes
     //     reflectivity: 1 byte
     
+    let mut batch = PointBatch::with_capacity(384);
     for i in 0..12 {
         let block = &blocks[i*100..(i+1)*100];
         let flag = ((block[0] as u16) << 8) + block[1] as u16;
@@ -220,14 +176,14 @@ fn parse_vlp16_single(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writ
                 let distance = ((channel_data[1] as u16) << 8) + channel_data[0] as u16;
                 let reflectivity = channel_data[2];
                 let point = build_velo_point(distance as f32, precise_azimuth, channel as u8, (precise_timestamp * 1000.0) as u64, reflectivity, &VLP16_LASER_ANGLES, VLP16_DISTANCE_RESOLUTION);
-                writer.write_row(point, block_azimuth as i64);
+                batch.push(point, block_azimuth as i64);
             }
         }
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
-fn parse_vlp16_dual(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer: &mut ValueSlopeSplitWriter) -> Result<(), Error> {
+fn parse_vlp16_dual(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer: &mut impl PointSink) -> Result<(), Error> {
     // blocks: 100 bytes * 12
     //   flag(0xFFEE)  : 2 bytes
     //   azimuth       : 2 bytes
@@ -236,6 +192,7 @@ fn parse_vlp16_dual(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer
     //     distance    : 2 bytes
     //     reflectivity: 1 byte
     
+    let mut batch = PointBatch::with_capacity(384);
     for i in (0..12).step_by(2) {
         let block_1 = &blocks[i*100..(i+1)*100];
         let block_2 = &blocks[(i+1)*100..(i+2)*100];
@@ -275,15 +232,15 @@ fn parse_vlp16_dual(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer
                 });
                 if points[0].distance_m == points[1].distance_m {
                     // 同じ点の場合、後の点を無視する
-                    writer.write_row(points[0].clone(), block_azimuth as i64);
+                    batch.push(points[0].clone(), block_azimuth as i64);
                 } else {
-                    writer.write_row(points[0].clone(), block_azimuth as i64);
-                    writer.write_row(points[1].clone(), block_azimuth as i64);
+                    batch.push_return(points[0].clone(), block_azimuth as i64, 0);
+                    batch.push_return(points[1].clone(), block_azimuth as i64, 1);
                 }
             }
         }
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 const VLP32C_LASER_ANGLES: [f32; 32] = [
@@ -299,7 +256,7 @@ const VLP32C_AZIMUTH_OFFSETS: [i32; 32] = [
     140, -140,  140, -420,  420, -140,  140, -140
 ];
 const VLP32C_DISTANCE_RESOLUTION: f32 = 0.004;
-fn parse_vlp32c_single(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer: &mut ValueSlopeSplitWriter) -> Result<(), Error> {
+fn parse_vlp32c_single(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer: &mut impl PointSink) -> Result<(), Error> {
     // blocks: 100 bytes * 12
     //   flag(0xFFEE)  : 2 bytes
     //   azimuth       : 2 bytes
@@ -307,6 +264,7 @@ fn parse_vlp32c_single(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, wri
     //     distance    : 2 bytes
     //     reflectivity: 1 byte
     
+    let mut batch = PointBatch::with_capacity(384);
     for i in 0..12 {
         let block = &blocks[i*100..(i+1)*100];
         let flag = ((block[0] as u16) << 8) + block[1] as u16;
@@ -340,13 +298,13 @@ fn parse_vlp32c_single(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, wri
             let distance = LittleEndian::read_u16(&channel_data[0..2]);
             let reflectivity = channel_data[2];
             let point = build_velo_point(distance as f32, precise_azimuth, channel as u8, (precise_timestamp * 1000.0) as u64, reflectivity, &VLP32C_LASER_ANGLES, VLP32C_DISTANCE_RESOLUTION);
-            writer.write_row(point, block_azimuth as i64);
+            batch.push(point, block_azimuth as i64);
         }
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
-fn parse_vlp32c_dual(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer: &mut ValueSlopeSplitWriter) -> Result<(), Error> {
+fn parse_vlp32c_dual(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, writer: &mut impl PointSink) -> Result<(), Error> {
     // blocks: 100 bytes * 12
     //   flag(0xFFEE)  : 2 bytes
     //   azimuth       : 2 bytes
@@ -354,6 +312,7 @@ fn parse_vlp32c_dual(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, write
     //     distance    : 2 bytes
     //     reflectivity: 1 byte
     
+    let mut batch = PointBatch::with_capacity(384);
     for i in (0..12).step_by(2) {
         let block_1 = &blocks[i*100..(i+1)*100];
         let block_2 = &blocks[(i+1)*100..(i+2)*100];
@@ -393,14 +352,14 @@ fn parse_vlp32c_dual(blocks: &[u8], azimuth_per_scan: u16, timestamp: u32, write
             });
             if points[0].distance_m == points[1].distance_m {
                 // 同じ点の場合、後の点を無視する
-                writer.write_row(points[0].clone(), block_azimuth as i64);
+                batch.push(points[0].clone(), block_azimuth as i64);
             } else {
-                writer.write_row(points[0].clone(), block_azimuth as i64);
-                writer.write_row(points[1].clone(), block_azimuth as i64);
+                batch.push_return(points[0].clone(), block_azimuth as i64, 0);
+                batch.push_return(points[1].clone(), block_azimuth as i64, 1);
             }
         }
     }
-    Ok(())
+    writer.write_batch(&batch)
 }
 
 
@@ -428,7 +387,7 @@ fn build_velo_point(distance: f32, azimuth: u16, channel: u8, timestamp_ns: u64,
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 enum ReturnMode {
     Strongest,
     Last,
@@ -436,66 +395,41 @@ enum ReturnMode {
 }
 
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 enum VeloProduct {
     Vlp16,
     Vlp32c,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 struct PcapInfo {
     return_mode: ReturnMode,
     product: VeloProduct,
-    #[allow(dead_code)]
-    num_frames: u16,
     frequency: f32, // Hz
+    source_address: Option<SocketAddr>,
 }
 
-fn parse_packet_info(filename: &str) -> Result<PcapInfo, Error> {
-    let file = File::open(filename).unwrap();
-    let mut reader = LegacyPcapReader::new(65536, file).expect("LegacyPcapReader");
-
+fn parse_packet_info(capture: &CaptureFile, filter: &PacketFilter) -> Result<PcapInfo, Error> {
     let mut packet_first_body: Option<Vec<u8>> = None;
     let mut packet_second_body: Option<Vec<u8>> = None;
-    let mut num_frames: u16 = 1;
-    let mut prev_azimuth: u16 = 0;
-
-    loop {
-        match reader.next() {
-            Ok((offset, block)) => {
-                match block {
-                    PcapBlockOwned::Legacy(packet) => {
-                        // etherのヘッダ長は14byte
-                        let ether_data = &packet.data[14..];
-                        // ipv4のヘッダ長は可変(基本20byte)
-                        let ip_header_size = ((ether_data[0] & 15) * 4) as usize;
-                        let packet_size = (((ether_data[2] as u32) << 8) + ether_data[3] as u32) as usize;
-                        let ip_data = &ether_data[ip_header_size..packet_size];
-                        // udpのヘッダ長は8byte
-                        let udp_data = &ip_data[8..];
-                        
-                        // 最初のblockのazimuthを見て、フレーム数をカウント
-                        let first_block_azimuth = ((udp_data[3] as u16) << 8) + udp_data[2] as u16;
-                        if first_block_azimuth < prev_azimuth {
-                            num_frames += 1;
-                        }
-                        prev_azimuth = first_block_azimuth;
-                        if packet_first_body.is_none() {
-                            packet_first_body = Some(udp_data.to_vec());
-                        }
-                        else if packet_second_body.is_none() {
-                            packet_second_body = Some(udp_data.to_vec());
-                        }
-                    }
-                    _ => ()
-                }
-                reader.consume(offset);
-            },
-            Err(PcapError::Eof) => break,
-            Err(PcapError::Incomplete) => {
-                reader.refill().unwrap();
-            },
-            Err(e) => panic!("error while reading: {:?}", e),
+    let mut source_address: Option<SocketAddr> = None;
+
+    let mut udp_decoder = UdpDecoder::new();
+    let mut packets = capture.packets()?;
+    while let Some(packet) = packets.next_packet()? {
+        let Some(udp) = udp_decoder.decode(&packet) else {
+            continue;
+        };
+        if !filter.matches(&udp) || !is_data_packet(udp.payload) {
+            continue;
+        }
+
+        if packet_first_body.is_none() {
+            source_address = Some(udp.source);
+            packet_first_body = Some(udp.payload.to_vec());
+        } else {
+            packet_second_body = Some(udp.payload.to_vec());
+            break;
         }
     }
 
@@ -539,7 +473,7 @@ fn parse_packet_info(filename: &str) -> Result<PcapInfo, Error> {
     Ok(PcapInfo {
         return_mode,
         product,
-        num_frames,
         frequency,
+        source_address,
     })
 }
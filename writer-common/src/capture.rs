@@ -0,0 +1,606 @@
+use std::fs::File;
+use std::io::Read;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use memmap2::Mmap;
+
+use crate::inputfile::{Compression, InputFile};
+
+const PCAP_HEADER_SIZE: usize = 24;
+const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+// pcapng block types
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 1;
+const OBSOLETE_PACKET_BLOCK: u32 = 2;
+const SIMPLE_PACKET_BLOCK: u32 = 3;
+const ENHANCED_PACKET_BLOCK: u32 = 6;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+/// if_tsresol option of the interface description block
+const OPTION_TSRESOL: u16 = 9;
+
+/// packets larger than this are treated as corruption
+const MAX_PACKET_SIZE: usize = 0x4000000;
+/// bytes decompressed at once from a compressed capture
+const READ_CHUNK_SIZE: usize = 1 << 20;
+
+/// A packet borrowed from the mapped capture.
+pub struct CapturedPacket<'a> {
+    /// capture time. units: nanoseconds since the unix epoch
+    pub timestamp_ns: u64,
+    /// LINKTYPE_* value of the interface the packet was captured on (1: Ethernet)
+    pub link_type: u32,
+    /// link layer frame as captured (truncated to the snapshot length)
+    pub data: &'a [u8],
+    /// byte offset of the end of the packet in the file, for progress reporting.
+    /// for compressed captures, the compressed bytes read so far
+    pub end_offset: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CaptureFormat {
+    Pcap {
+        big_endian: bool,
+        /// timestamps are in nanoseconds instead of microseconds
+        nanosecond: bool,
+    },
+    PcapNg,
+}
+
+/// A pcap or pcapng capture. Uncompressed files are mapped into memory and packets are borrowed
+/// from the mapping without copying, so the file is read only once however many times it is
+/// scanned. Compressed files are decompressed again on each scan.
+pub struct CaptureFile {
+    data: CaptureData,
+    format: CaptureFormat,
+    len: u64,
+}
+
+enum CaptureData {
+    Mapped(Mmap),
+    Compressed(PathBuf),
+}
+
+impl CaptureFile {
+    /// Opens a capture. The compression and the format are detected from the first bytes.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<CaptureFile> {
+        let path = path.as_ref();
+        let mut input = InputFile::open(path)?;
+        let len = input.len();
+        let mut header = [0u8; PCAP_HEADER_SIZE];
+        let data = if input.compression() == Compression::None {
+            let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+            // the capture must not be modified while it is mapped
+            let mmap = unsafe { Mmap::map(&file) }.with_context(|| format!("failed to map {}", path.display()))?;
+            ensure!(mmap.len() >= PCAP_HEADER_SIZE, "{} is too short for a capture file", path.display());
+            header.copy_from_slice(&mmap[..PCAP_HEADER_SIZE]);
+            CaptureData::Mapped(mmap)
+        } else {
+            input.read_exact(&mut header).with_context(|| format!("{} is too short for a capture file", path.display()))?;
+            CaptureData::Compressed(path.to_path_buf())
+        };
+        let format = match header[0..4] {
+            [0xd4, 0xc3, 0xb2, 0xa1] => CaptureFormat::Pcap { big_endian: false, nanosecond: false },
+            [0xa1, 0xb2, 0xc3, 0xd4] => CaptureFormat::Pcap { big_endian: true, nanosecond: false },
+            [0x4d, 0x3c, 0xb2, 0xa1] => CaptureFormat::Pcap { big_endian: false, nanosecond: true },
+            [0xa1, 0xb2, 0x3c, 0x4d] => CaptureFormat::Pcap { big_endian: true, nanosecond: true },
+            [0x0a, 0x0d, 0x0d, 0x0a] => CaptureFormat::PcapNg,
+            _ => bail!("{} is not a pcap or pcapng file: unknown magic number {:02x?}", path.display(), &header[0..4]),
+        };
+        Ok(CaptureFile { data, format, len })
+    }
+
+    /// Size of the file in bytes, compressed or not.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn format(&self) -> CaptureFormat {
+        self.format
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        matches!(self.data, CaptureData::Compressed(_))
+    }
+
+    /// Starts a scan of the packets from the beginning of the file.
+    pub fn packets(&self) -> Result<Packets<'_>> {
+        let input = match &self.data {
+            CaptureData::Mapped(mmap) => PacketInput::Mapped(&mmap[..]),
+            CaptureData::Compressed(path) => PacketInput::Stream { file: InputFile::open(path)?, buffer: Vec::new(), start: 0 },
+        };
+        let mut packets = Packets { input, format: self.format, offset: 0, big_endian: false, link_type: 0, interfaces: Vec::new(), done: false };
+        if let CaptureFormat::Pcap { big_endian, .. } = self.format {
+            ensure!(packets.input.fill(0, PCAP_HEADER_SIZE)?, "capture header is truncated");
+            packets.big_endian = big_endian;
+            // the link type field also carries the FCS length in its upper bits
+            packets.link_type = packets.read_u32(20) & 0xFFFF;
+            packets.offset = PCAP_HEADER_SIZE;
+        }
+        // pcapng byte order is set by the section header block
+        Ok(packets)
+    }
+
+    /// Counts the packets by walking the record headers only.
+    pub fn packet_count(&self) -> Result<u64> {
+        let mut count = 0;
+        let mut packets = self.packets()?;
+        while packets.next_packet()?.is_some() {
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// The bytes of a capture, addressed by their offset in the uncompressed file.
+enum PacketInput<'a> {
+    Mapped(&'a [u8]),
+    Stream {
+        file: InputFile,
+        /// uncompressed bytes from `start`
+        buffer: Vec<u8>,
+        start: usize,
+    },
+}
+
+impl PacketInput<'_> {
+    /// Makes the bytes up to `end` available, dropping the buffered bytes before `keep`.
+    /// Returns false when the file ends before `end`.
+    fn fill(&mut self, keep: usize, end: usize) -> Result<bool> {
+        match self {
+            PacketInput::Mapped(data) => Ok(end <= data.len()),
+            PacketInput::Stream { file, buffer, start } => {
+                if end <= *start + buffer.len() {
+                    return Ok(true);
+                }
+                buffer.drain(..keep - *start);
+                *start = keep;
+                while *start + buffer.len() < end {
+                    let len = buffer.len();
+                    buffer.resize(len + (end - *start - len).max(READ_CHUNK_SIZE), 0);
+                    let read = file.read(&mut buffer[len..])?;
+                    buffer.truncate(len + read);
+                    if read == 0 {
+                        return Ok(false);
+                    }
+                }
+                Ok(true)
+            },
+        }
+    }
+
+    /// Returns the bytes in `range`, which must have been filled.
+    fn bytes(&self, range: Range<usize>) -> &[u8] {
+        match self {
+            PacketInput::Mapped(data) => &data[range],
+            PacketInput::Stream { buffer, start, .. } => &buffer[range.start - start..range.end - start],
+        }
+    }
+
+    /// Position in the file for progress reporting.
+    fn position(&self, offset: usize) -> u64 {
+        match self {
+            PacketInput::Mapped(_) => offset as u64,
+            PacketInput::Stream { file, .. } => file.position(),
+        }
+    }
+}
+
+/// An interface of a pcapng section.
+struct Interface {
+    link_type: u32,
+    snap_len: u32,
+    /// timestamp units per second
+    resolution: u64,
+}
+
+/// Reads the packets of a `CaptureFile` in file order.
+///
+/// A packet borrows the reader, so that compressed captures can reuse the same buffer:
+/// `while let Some(packet) = packets.next_packet()? { ... }`
+pub struct Packets<'a> {
+    input: PacketInput<'a>,
+    format: CaptureFormat,
+    offset: usize,
+    big_endian: bool,
+    /// link type of legacy pcap files
+    link_type: u32,
+    /// interfaces of the current pcapng section
+    interfaces: Vec<Interface>,
+    /// set after the end or an error
+    done: bool,
+}
+
+impl Packets<'_> {
+    /// Returns the next packet, or `None` at the end of the capture.
+    pub fn next_packet(&mut self) -> Result<Option<CapturedPacket<'_>>> {
+        if self.done {
+            return Ok(None);
+        }
+        let result = match self.format {
+            CaptureFormat::Pcap { nanosecond, .. } => self.next_pcap(nanosecond),
+            CaptureFormat::PcapNg => self.next_pcapng(),
+        };
+        let packet = match result {
+            Ok(Some(packet)) => packet,
+            other => {
+                // stop after the end or an error
+                self.done = true;
+                other?;
+                return Ok(None);
+            },
+        };
+        Ok(Some(CapturedPacket {
+            timestamp_ns: packet.timestamp_ns,
+            link_type: packet.link_type,
+            data: self.input.bytes(packet.data),
+            end_offset: self.input.position(packet.end_offset),
+        }))
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        let bytes: [u8; 2] = self.input.bytes(offset..offset + 2).try_into().unwrap();
+        if self.big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) }
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        let bytes: [u8; 4] = self.input.bytes(offset..offset + 4).try_into().unwrap();
+        if self.big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) }
+    }
+
+    /// Makes `len` bytes from the current offset available. Returns false at the end of the file,
+    /// warning when the file ends in the middle.
+    fn fill(&mut self, len: usize) -> Result<bool> {
+        let offset = self.offset;
+        if !self.input.fill(offset, offset + 1)? {
+            return Ok(false);
+        }
+        if !self.input.fill(offset, offset + len)? {
+            warn_truncated(offset);
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn next_pcap(&mut self, nanosecond: bool) -> Result<Option<PacketRecord>> {
+        let header_offset = self.offset;
+        if !self.fill(PCAP_RECORD_HEADER_SIZE)? {
+            return Ok(None);
+        }
+        let seconds = self.read_u32(header_offset) as u64;
+        let fraction = self.read_u32(header_offset + 4) as u64;
+        let captured_len = self.read_u32(header_offset + 8) as usize;
+        ensure!(captured_len <= MAX_PACKET_SIZE, "invalid packet length {} at offset {}", captured_len, header_offset);
+        if !self.fill(PCAP_RECORD_HEADER_SIZE + captured_len)? {
+            return Ok(None);
+        }
+        let data_offset = header_offset + PCAP_RECORD_HEADER_SIZE;
+        let end_offset = data_offset + captured_len;
+        self.offset = end_offset;
+        let timestamp_ns = if nanosecond {
+            seconds * 1_000_000_000 + fraction
+        } else {
+            seconds * 1_000_000_000 + fraction * 1000
+        };
+        Ok(Some(PacketRecord {
+            timestamp_ns,
+            link_type: self.link_type,
+            data: data_offset..end_offset,
+            end_offset,
+        }))
+    }
+
+    /// Walks the blocks until the next packet block.
+    fn next_pcapng(&mut self) -> Result<Option<PacketRecord>> {
+        loop {
+            let block_offset = self.offset;
+            if !self.fill(12)? {
+                return Ok(None);
+            }
+            let block_type = self.read_u32(block_offset);
+            if block_type == SECTION_HEADER_BLOCK {
+                // the byte order magic decides how the section, including this block, is read
+                let magic = self.input.bytes(block_offset + 8..block_offset + 12);
+                self.big_endian = match u32::from_le_bytes(magic.try_into().unwrap()) {
+                    BYTE_ORDER_MAGIC => false,
+                    m if m.swap_bytes() == BYTE_ORDER_MAGIC => true,
+                    _ => bail!("invalid pcapng byte order magic at offset {}", block_offset),
+                };
+                self.interfaces.clear();
+            }
+            let block_len = self.read_u32(block_offset + 4) as usize;
+            if block_len < 12 || block_len % 4 != 0 || block_len > MAX_PACKET_SIZE {
+                bail!("invalid pcapng block length {} at offset {}", block_len, block_offset);
+            }
+            if !self.fill(block_len)? {
+                return Ok(None);
+            }
+            let block_end = block_offset + block_len;
+            self.offset = block_end;
+            let body = block_offset + 8..block_end - 4;
+            match block_type {
+                INTERFACE_DESCRIPTION_BLOCK => self.read_interface(body)?,
+                ENHANCED_PACKET_BLOCK | OBSOLETE_PACKET_BLOCK => {
+                    return self.read_packet(block_type, body, block_end).map(Some);
+                },
+                SIMPLE_PACKET_BLOCK => {
+                    return self.read_simple_packet(body, block_end).map(Some);
+                },
+                // section headers, statistics, name resolution, custom blocks
+                _ => (),
+            }
+        }
+    }
+
+    fn read_interface(&mut self, body: Range<usize>) -> Result<()> {
+        ensure!(body.len() >= 8, "invalid interface description block at offset {}", body.start - 8);
+        let link_type = self.read_u16(body.start) as u32;
+        let snap_len = self.read_u32(body.start + 4);
+        let mut resolution = 1_000_000;
+        // options: code(2) length(2) value padded to 4 bytes
+        let mut option = body.start + 8;
+        while option + 4 <= body.end {
+            let code = self.read_u16(option);
+            let len = self.read_u16(option + 2) as usize;
+            if code == 0 || option + 4 + len > body.end {
+                break;
+            }
+            if code == OPTION_TSRESOL && len >= 1 {
+                let tsresol = self.input.bytes(option + 4..option + 5)[0];
+                let exponent = (tsresol & 0x7F) as u32;
+                resolution = if tsresol & 0x80 == 0 {
+                    10u64.checked_pow(exponent)
+                } else {
+                    2u64.checked_pow(exponent)
+                }.ok_or_else(|| anyhow!("unsupported timestamp resolution {:#x}", tsresol))?;
+            }
+            option += 4 + (len + 3) / 4 * 4;
+        }
+        self.interfaces.push(Interface { link_type, snap_len, resolution });
+        Ok(())
+    }
+
+    fn read_packet(&self, block_type: u32, body: Range<usize>, block_end: usize) -> Result<PacketRecord> {
+        ensure!(body.len() >= 20, "invalid packet block at offset {}", body.start - 8);
+        let interface_id = if block_type == ENHANCED_PACKET_BLOCK {
+            self.read_u32(body.start) as usize
+        } else {
+            self.read_u16(body.start) as usize
+        };
+        let interface = self.interfaces.get(interface_id)
+            .ok_or_else(|| anyhow!("packet of unknown interface {} at offset {}", interface_id, body.start - 8))?;
+        let timestamp = ((self.read_u32(body.start + 4) as u64) << 32) | self.read_u32(body.start + 8) as u64;
+        let captured_len = self.read_u32(body.start + 12) as usize;
+        let data_offset = body.start + 20;
+        ensure!(data_offset + captured_len <= body.end, "invalid packet length {} at offset {}", captured_len, body.start - 8);
+        Ok(PacketRecord {
+            timestamp_ns: (timestamp as u128 * 1_000_000_000 / interface.resolution as u128) as u64,
+            link_type: interface.link_type,
+            data: data_offset..data_offset + captured_len,
+            end_offset: block_end,
+        })
+    }
+
+    /// Simple packet blocks belong to the first interface and have no timestamp.
+    fn read_simple_packet(&self, body: Range<usize>, block_end: usize) -> Result<PacketRecord> {
+        ensure!(body.len() >= 4, "invalid simple packet block at offset {}", body.start - 8);
+        let interface = self.interfaces.first()
+            .ok_or_else(|| anyhow!("packet of unknown interface 0 at offset {}", body.start - 8))?;
+        let original_len = self.read_u32(body.start) as usize;
+        let mut captured_len = original_len.min(body.len() - 4);
+        if interface.snap_len > 0 {
+            captured_len = captured_len.min(interface.snap_len as usize);
+        }
+        let data_offset = body.start + 4;
+        Ok(PacketRecord {
+            timestamp_ns: 0,
+            link_type: interface.link_type,
+            data: data_offset..data_offset + captured_len,
+            end_offset: block_end,
+        })
+    }
+}
+
+/// A packet located in the input, before its bytes are borrowed.
+struct PacketRecord {
+    timestamp_ns: u64,
+    link_type: u32,
+    data: Range<usize>,
+    end_offset: usize,
+}
+
+/// A capture stopped while writing leaves a partial record at the end. The complete packets are still usable.
+fn warn_truncated(offset: usize) {
+    eprintln!("ignoring a truncated packet at the end of the capture (offset {})", offset);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{pcap, temp_dir, PCAP_SECONDS};
+
+    fn write_capture(name: &str, bytes: &[u8]) -> CaptureFile {
+        let path = format!("{}/{}", temp_dir(name), name);
+        std::fs::write(&path, bytes).unwrap();
+        CaptureFile::open(&path).unwrap()
+    }
+
+    /// (timestamp_ns, link_type, data, end_offset) of all packets
+    fn read_all(capture: &CaptureFile) -> Vec<(u64, u32, Vec<u8>, u64)> {
+        let mut result = Vec::new();
+        let mut packets = capture.packets().unwrap();
+        while let Some(packet) = packets.next_packet().unwrap() {
+            result.push((packet.timestamp_ns, packet.link_type, packet.data.to_vec(), packet.end_offset));
+        }
+        result
+    }
+
+    #[test]
+    fn pcap_byte_orders_and_resolutions() {
+        for big_endian in [false, true] {
+            for nanosecond in [false, true] {
+                let bytes = pcap(big_endian, nanosecond, &[(123_456, b"first"), (999_999, b"second packet")]);
+                let capture = write_capture("capture.pcap", &bytes);
+                assert!(matches!(capture.format(), CaptureFormat::Pcap { big_endian: b, nanosecond: n } if b == big_endian && n == nanosecond));
+                let unit = if nanosecond { 1 } else { 1000 };
+                let base = PCAP_SECONDS as u64 * 1_000_000_000;
+                assert_eq!(read_all(&capture), [
+                    (base + 123_456 * unit, 1, b"first".to_vec(), 45),
+                    (base + 999_999 * unit, 1, b"second packet".to_vec(), bytes.len() as u64),
+                ]);
+                assert_eq!(capture.packet_count().unwrap(), 2);
+            }
+        }
+    }
+
+    #[test]
+    fn pcap_truncated_record_is_ignored() {
+        let mut bytes = pcap(false, false, &[(1, b"complete"), (2, b"cut short")]);
+        bytes.truncate(bytes.len() - 3);
+        let capture = write_capture("truncated.pcap", &bytes);
+        let packets = read_all(&capture);
+        assert_eq!(packets.len(), 1);
+        assert_eq!(packets[0].2, b"complete");
+    }
+
+    #[test]
+    fn compressed_capture_gives_the_same_packets() {
+        let bytes = pcap(false, false, &[(1, b"first"), (2, b"second packet")]);
+        let plain = write_capture("plain.pcap", &bytes);
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        std::io::Write::write_all(&mut encoder, &bytes).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let gzip = write_capture("plain.pcap.gz", &compressed);
+        assert!(!plain.is_compressed());
+        assert!(gzip.is_compressed());
+        assert_eq!(gzip.len(), compressed.len() as u64);
+        let without_offsets = |capture: &CaptureFile| -> Vec<_> {
+            read_all(capture).into_iter().map(|(timestamp, link_type, data, _)| (timestamp, link_type, data)).collect()
+        };
+        assert_eq!(without_offsets(&gzip), without_offsets(&plain));
+        assert_eq!(gzip.packet_count().unwrap(), 2);
+    }
+
+    /// Builds pcapng blocks in one byte order.
+    struct PcapNg {
+        bytes: Vec<u8>,
+        big_endian: bool,
+    }
+
+    impl PcapNg {
+        fn u16(&self, v: u16) -> [u8; 2] {
+            if self.big_endian { v.to_be_bytes() } else { v.to_le_bytes() }
+        }
+
+        fn u32(&self, v: u32) -> [u8; 4] {
+            if self.big_endian { v.to_be_bytes() } else { v.to_le_bytes() }
+        }
+
+        fn block(&mut self, block_type: u32, mut body: Vec<u8>) {
+            body.resize((body.len() + 3) / 4 * 4, 0);
+            let len = body.len() as u32 + 12;
+            let mut block = self.u32(block_type).to_vec();
+            block.extend_from_slice(&self.u32(len));
+            block.extend(body);
+            block.extend_from_slice(&self.u32(len));
+            self.bytes.extend(block);
+        }
+
+        fn section(&mut self, big_endian: bool) {
+            self.big_endian = big_endian;
+            let mut body = self.u32(BYTE_ORDER_MAGIC).to_vec();
+            body.extend_from_slice(&self.u16(1));
+            body.extend_from_slice(&self.u16(0));
+            body.extend_from_slice(&u64::MAX.to_le_bytes()); // section length unknown
+            self.block(SECTION_HEADER_BLOCK, body);
+        }
+
+        fn interface(&mut self, link_type: u16, tsresol: Option<u8>) {
+            let mut body = self.u16(link_type).to_vec();
+            body.extend_from_slice(&[0, 0]);
+            body.extend_from_slice(&self.u32(0));
+            if let Some(tsresol) = tsresol {
+                body.extend_from_slice(&self.u16(OPTION_TSRESOL));
+                body.extend_from_slice(&self.u16(1));
+                body.extend_from_slice(&[tsresol, 0, 0, 0]);
+                body.extend_from_slice(&[0; 4]); // opt_endofopt
+            }
+            self.block(INTERFACE_DESCRIPTION_BLOCK, body);
+        }
+
+        fn packet(&mut self, interface_id: u32, timestamp: u64, data: &[u8]) {
+            let mut body = self.u32(interface_id).to_vec();
+            body.extend_from_slice(&self.u32((timestamp >> 32) as u32));
+            body.extend_from_slice(&self.u32(timestamp as u32));
+            body.extend_from_slice(&self.u32(data.len() as u32));
+            body.extend_from_slice(&self.u32(data.len() as u32));
+            body.extend_from_slice(data);
+            self.block(ENHANCED_PACKET_BLOCK, body);
+        }
+
+        fn simple_packet(&mut self, data: &[u8]) {
+            let mut body = self.u32(data.len() as u32).to_vec();
+            body.extend_from_slice(data);
+            self.block(SIMPLE_PACKET_BLOCK, body);
+        }
+    }
+
+    #[test]
+    fn pcapng_interfaces_and_timestamp_resolutions() {
+        let base = PCAP_SECONDS as u64;
+        let mut ng = PcapNg { bytes: Vec::new(), big_endian: false };
+        ng.section(false);
+        // Ethernet in microseconds, Linux SLL in nanoseconds and 2^-10 seconds
+        ng.interface(1, None);
+        ng.interface(113, Some(9));
+        ng.interface(276, Some(0x80 | 10));
+        ng.packet(1, base * 1_000_000_000 + 7, b"sll");
+        ng.packet(0, base * 1_000_000 + 5, b"ether");
+        // statistics blocks are skipped
+        ng.block(5, vec![0; 12]);
+        ng.packet(2, base * 1024 + 512, b"sll2");
+        ng.simple_packet(b"simple");
+        let first_section_end = ng.bytes.len() as u64;
+        // a second, big endian section restarts the interface list
+        ng.section(true);
+        ng.interface(101, Some(6));
+        ng.packet(0, base * 1_000_000 + 3, b"raw ip");
+
+        let capture = write_capture("capture.pcapng", &ng.bytes);
+        assert!(matches!(capture.format(), CaptureFormat::PcapNg));
+        let packets: Vec<(u64, u32, Vec<u8>)> = read_all(&capture).into_iter().map(|(t, l, d, _)| (t, l, d)).collect();
+        let ns = base * 1_000_000_000;
+        assert_eq!(packets, [
+            (ns + 7, 113, b"sll".to_vec()),
+            (ns + 5000, 1, b"ether".to_vec()),
+            (ns + 500_000_000, 276, b"sll2".to_vec()),
+            (0, 1, b"simple".to_vec()),
+            (ns + 3000, 101, b"raw ip".to_vec()),
+        ]);
+        assert_eq!(read_all(&capture)[3].3, first_section_end);
+    }
+
+    #[test]
+    fn pcapng_packet_of_unknown_interface_is_an_error() {
+        let mut ng = PcapNg { bytes: Vec::new(), big_endian: false };
+        ng.section(false);
+        ng.interface(1, None);
+        ng.packet(1, 0, b"data");
+        let capture = write_capture("interface.pcapng", &ng.bytes);
+        let mut packets = capture.packets().unwrap();
+        assert!(packets.next_packet().is_err());
+    }
+
+    #[test]
+    fn unknown_magic_is_rejected() {
+        let path = format!("{}/text.pcap", temp_dir("magic"));
+        std::fs::write(&path, [b'x'; 64]).unwrap();
+        assert!(CaptureFile::open(&path).is_err());
+    }
+}
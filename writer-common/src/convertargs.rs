@@ -0,0 +1,56 @@
+use std::cell::OnceCell;
+
+use anyhow::{anyhow, Result};
+use getopts::{Matches, Options};
+
+use crate::{
+    framesplitter::SplitOptions,
+    outputoptions::OutputOptions,
+    packetfilter::FilterOptions,
+    paralleldecoder::DecodeOptions,
+    sensormetadata::SourceFile,
+};
+
+/// Command line arguments of a conversion, shared by the vendor parsers.
+pub struct ConvertArgs {
+    pub input: String,
+    pub output: OutputOptions,
+    pub split: SplitOptions,
+    pub decode: DecodeOptions,
+    pub filter: FilterOptions,
+    /// records the SHA-256 of the input in the metadata
+    pub hash_source: bool,
+    source_file: OnceCell<SourceFile>,
+}
+
+impl ConvertArgs {
+    pub fn register(opts: &mut Options) {
+        OutputOptions::register(opts);
+        SplitOptions::register(opts);
+        DecodeOptions::register(opts);
+        FilterOptions::register(opts);
+        opts.optflag("", "hash-source", "record the SHA-256 of the input in the metadata (reads the input once more)");
+    }
+
+    /// The first free argument is the input file.
+    pub fn from_matches(matches: &Matches) -> Result<ConvertArgs> {
+        let input = matches.free.first().cloned().ok_or_else(|| anyhow!("no input file"))?;
+        Ok(ConvertArgs {
+            input,
+            output: OutputOptions::from_matches(matches)?,
+            split: SplitOptions::from_matches(matches)?,
+            decode: DecodeOptions::from_matches(matches)?,
+            filter: FilterOptions::from_matches(matches)?,
+            hash_source: matches.opt_present("hash-source"),
+            source_file: OnceCell::new(),
+        })
+    }
+
+    /// The input file recorded in the metadata. Computed once and shared by all outputs.
+    pub fn source_file(&self) -> Result<&SourceFile> {
+        if self.source_file.get().is_none() {
+            let _ = self.source_file.set(SourceFile::from_path(&self.input, self.hash_source)?);
+        }
+        Ok(self.source_file.get().unwrap())
+    }
+}
@@ -1,27 +1,79 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, ensure, Error, Result};
+use getopts::{Matches, Options};
+
 use crate::velopoint::VeloPoint;
 
+/// Decides where one frame ends and the next begins.
+///
+/// `value` is a key supplied by the decoder alongside each point (e.g. the block azimuth of
+/// a Velodyne packet). Splitters that only look at the point itself ignore it.
 pub trait FrameSplitter {
-    fn read(&mut self, row: &VeloPoint) -> bool;
+    fn read(&mut self, row: &VeloPoint, value: i64) -> bool;
+
+    /// strategy name and parameters, recorded in the output metadata
+    fn describe(&self) -> String;
+
+    /// called when the decoder finds a frame boundary marker in the stream
+    fn signal(&mut self) -> bool {
+        false
+    }
 }
 
+impl FrameSplitter for Box<dyn FrameSplitter> {
+    fn read(&mut self, row: &VeloPoint, value: i64) -> bool {
+        (**self).read(row, value)
+    }
+
+    fn describe(&self) -> String {
+        (**self).describe()
+    }
+
+    fn signal(&mut self) -> bool {
+        (**self).signal()
+    }
+}
+
+/// Starts a new frame when the azimuth drops by more than `min_offset` (0.01 degrees).
 pub struct AzimuthSplitter {
     pub previous_azimuth: u16,
+    pub min_offset: i32,
 }
 
 impl AzimuthSplitter {
     pub fn new() -> AzimuthSplitter {
-        AzimuthSplitter { previous_azimuth: 0 }
+        AzimuthSplitter { previous_azimuth: 0, min_offset: 0 }
+    }
+
+    pub fn new_with_min_offset(min_offset: i32) -> AzimuthSplitter {
+        AzimuthSplitter { previous_azimuth: 0, min_offset }
+    }
+}
+
+impl Default for AzimuthSplitter {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl FrameSplitter for AzimuthSplitter {
-    fn read(&mut self, row: &VeloPoint) -> bool {
-        let is_new_frame = row.azimuth < self.previous_azimuth;
+    fn read(&mut self, row: &VeloPoint, _value: i64) -> bool {
+        let is_new_frame = self.previous_azimuth as i32 - row.azimuth as i32 > self.min_offset;
         self.previous_azimuth = row.azimuth;
         is_new_frame
     }
+
+    fn describe(&self) -> String {
+        format!("azimuth(min_offset={})", self.min_offset)
+    }
 }
 
+/// Starts a new frame every `interval` nanoseconds, or when the timestamp goes back by more than
+/// `interval` (e.g. at the top of the hour for Velodyne timestamps).
+///
+/// Smaller steps back are part of the stream: the second return block of a dual return packet
+/// repeats the timestamps of the first, and interleaved devices are not exactly in sync.
 pub struct TimeSplitter {
     pub frame_start_timestamp: u64,
     pub interval: u64,
@@ -34,11 +86,218 @@ impl TimeSplitter {
 }
 
 impl FrameSplitter for TimeSplitter {
-    fn read(&mut self, row: &VeloPoint) -> bool {
-        let is_new_frame = row.timestamp - self.frame_start_timestamp > self.interval;
+    fn read(&mut self, row: &VeloPoint, _value: i64) -> bool {
+        let is_new_frame = match row.timestamp.checked_sub(self.frame_start_timestamp) {
+            Some(elapsed) => elapsed > self.interval,
+            None => self.frame_start_timestamp - row.timestamp > self.interval,
+        };
         if is_new_frame {
             self.frame_start_timestamp = row.timestamp;
         }
         is_new_frame
     }
-}
\ No newline at end of file
+
+    fn describe(&self) -> String {
+        format!("time(interval_ns={})", self.interval)
+    }
+}
+
+/// Starts a new frame when the slope of the decoder supplied value changes its sign.
+pub struct ValueSlopeSplitter {
+    pub has_previous_value: bool,
+    pub previous_value: i64,
+    pub previous_slope: i64,
+}
+
+impl ValueSlopeSplitter {
+    pub fn new() -> ValueSlopeSplitter {
+        ValueSlopeSplitter {
+            has_previous_value: false,
+            previous_value: 0,
+            previous_slope: 0,
+        }
+    }
+}
+
+impl Default for ValueSlopeSplitter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameSplitter for ValueSlopeSplitter {
+    fn read(&mut self, _row: &VeloPoint, new_value: i64) -> bool {
+        if !self.has_previous_value {
+            self.has_previous_value = true;
+            self.previous_value = new_value;
+            return false;
+        }
+
+        let new_slope = new_value - self.previous_value;
+        self.previous_value = new_value;
+        if new_slope == 0 {
+            return false;
+        }
+        if self.previous_slope == 0 {
+            self.previous_slope = new_slope;
+            return false;
+        }
+        let is_slope_same_direction = new_slope.signum() == self.previous_slope.signum();
+        if is_slope_same_direction {
+            false
+        } else {
+            self.previous_slope = 0;
+            true
+        }
+    }
+
+    fn describe(&self) -> String {
+        "slope".to_string()
+    }
+}
+
+/// Splits only on frame markers reported by the decoder (e.g. Leishen).
+pub struct SignalSplitter;
+
+impl FrameSplitter for SignalSplitter {
+    fn read(&mut self, _row: &VeloPoint, _value: i64) -> bool {
+        false
+    }
+
+    fn signal(&mut self) -> bool {
+        true
+    }
+
+    fn describe(&self) -> String {
+        "signal".to_string()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum SplitType {
+    Azimuth,
+    Time,
+    Slope,
+    Signal,
+}
+
+impl FromStr for SplitType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "azimuth" => Ok(SplitType::Azimuth),
+            "time" => Ok(SplitType::Time),
+            "slope" => Ok(SplitType::Slope),
+            "signal" => Ok(SplitType::Signal),
+            _ => Err(anyhow!("unknown split type: {}", s)),
+        }
+    }
+}
+
+/// Frame split related command line options shared by all parsers.
+pub struct SplitOptions {
+    pub split_type: Option<SplitType>,
+    pub frame_time_ms: u64,
+    pub min_offset: Option<i32>,
+}
+
+impl SplitOptions {
+    pub fn register(opts: &mut Options) {
+        opts.optopt("s", "split", "frame split strategy", "azimuth|time|slope|signal");
+        opts.optopt("t", "frame-time", "frame time in milliseconds", "100");
+        opts.optopt("m", "min-offset", "azimuth drop which starts a new frame in 0.01 degrees", "0");
+    }
+
+    pub fn from_matches(matches: &Matches) -> Result<SplitOptions> {
+        Ok(SplitOptions {
+            split_type: matches.opt_get::<SplitType>("s")?,
+            frame_time_ms: matches.opt_get::<u64>("t")?.unwrap_or(100),
+            min_offset: matches.opt_get::<i32>("m")?,
+        })
+    }
+
+    /// `--split signal` needs a decoder which reports frame markers; the other parsers would
+    /// write the whole capture as one frame.
+    pub fn ensure_no_signal(&self, parser: &str) -> Result<()> {
+        ensure!(!matches!(self.split_type, Some(SplitType::Signal)), "--split signal is not supported for {} input", parser);
+        Ok(())
+    }
+
+    /// Defaults are used for whatever was not given on the command line.
+    pub fn create_splitter(&self, default_type: SplitType, default_min_offset: i32) -> Box<dyn FrameSplitter> {
+        let min_offset = self.min_offset.unwrap_or(default_min_offset);
+        match self.split_type.unwrap_or(default_type) {
+            SplitType::Azimuth => Box::new(AzimuthSplitter::new_with_min_offset(min_offset)),
+            SplitType::Time => Box::new(TimeSplitter::new(self.frame_time_ms * 1000 * 1000)),
+            SplitType::Slope => Box::new(ValueSlopeSplitter::new()),
+            SplitType::Signal => Box::new(SignalSplitter),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::point;
+
+    /// 100 ms
+    const INTERVAL: u64 = 100_000_000;
+
+    fn at(timestamp: u64) -> VeloPoint {
+        VeloPoint { timestamp, ..point(0) }
+    }
+
+    /// Indexes of the points which start a new frame.
+    fn splits(timestamps: &[u64]) -> Vec<usize> {
+        let mut splitter = TimeSplitter::new(INTERVAL);
+        timestamps.iter().enumerate()
+            .filter(|(_, &t)| splitter.read(&at(t), 0))
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    #[test]
+    fn dual_return_blocks_do_not_split_twice() {
+        // Hesai XT32: block pairs share the block timestamp and the channels are 1512 ns apart
+        let mut timestamps = Vec::new();
+        let mut first_returns = Vec::new();
+        for block in 0..20_000u64 {
+            let block_time = 1_700_000_000_000_000_000 + block * 50_000;
+            for _return in 0..2 {
+                for channel in 0..32 {
+                    timestamps.push(block_time + channel * 1512);
+                }
+            }
+            first_returns.extend((0..32).map(|channel| block_time + channel * 1512));
+        }
+        let dual = splits(&timestamps);
+        assert_eq!(dual.len(), splits(&first_returns).len());
+        // the frames are cut inside the first return blocks, not at the second ones
+        assert!(dual.iter().any(|i| i % 64 != 0));
+        assert!(dual.windows(2).all(|w| w[1] - w[0] > 64));
+    }
+
+    #[test]
+    fn interleaved_devices_do_not_split_on_each_other() {
+        // two devices in one LVX frame, the second 30 us behind the first
+        let mut timestamps = Vec::new();
+        let mut first_device = Vec::new();
+        for packet in 0..100_000u64 {
+            let start = packet * 10_000;
+            let points: Vec<u64> = (0..10).map(|i| start + i * 1000).collect();
+            timestamps.extend(points.iter().copied());
+            timestamps.extend(points.iter().map(|t| t.saturating_sub(30_000)));
+            first_device.extend(points);
+        }
+        assert_eq!(splits(&timestamps).len(), splits(&first_device).len());
+        assert_eq!(splits(&timestamps).len(), 9);
+    }
+
+    #[test]
+    fn jump_back_by_more_than_the_interval_splits() {
+        // Velodyne timestamps restart at the top of the hour
+        let hour = 3_600_000_000_000;
+        assert_eq!(splits(&[hour - 20_000_000, hour - 10_000_000, 5_000_000, 6_000_000]), [0, 2]);
+    }
+}
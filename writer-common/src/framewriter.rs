@@ -1,17 +1,60 @@
+use anyhow::Result;
+
+use crate::pointbatch::PointBatch;
+use crate::sensormetadata::SensorMetadata;
 use crate::velopoint::VeloPoint;
 
 mod csvwriter;
+mod e57writer;
+mod foxglove;
 mod hdfwriter;
+mod kittiwriter;
+mod laswriter;
+mod lzf;
+mod mcapwriter;
+mod npywriter;
+mod parallelwriter;
+mod parquetwriter;
 mod pcdwriter;
+mod plywriter;
+mod pointcloud2;
 mod progressbarext;
+mod rosbagwriter;
 
 pub use csvwriter::*;
+pub use e57writer::*;
 pub use hdfwriter::*;
+pub use kittiwriter::*;
+pub use laswriter::*;
+pub use mcapwriter::*;
+pub use npywriter::*;
+pub use parallelwriter::*;
+pub use parquetwriter::*;
 pub use pcdwriter::*;
+pub use plywriter::*;
 pub use progressbarext::ProgressBarExt;
+pub use rosbagwriter::*;
 
 pub trait FrameWriter {
-    fn write_row(&mut self, row: VeloPoint);
-    fn split_frame(&mut self);
-    fn write_attribute(&mut self, laser_num: u32, frequency: f32, return_mode: u32, manufacturer: &str, model: &str);
+    fn write_row(&mut self, row: VeloPoint) -> Result<()>;
+    /// Writes all points of the batch into the current frame.
+    /// Writers that can consume the columns directly override this.
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        for row in batch.iter() {
+            self.write_row(row)?;
+        }
+        Ok(())
+    }
+    fn split_frame(&mut self) -> Result<()>;
+    fn write_attribute(&mut self, metadata: &SensorMetadata) -> Result<()>;
+    /// Called once after the last frame. Writers that keep a file open close it here.
+    fn finalize(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes a complete frame into its own file, independently of the other frames.
+/// Implemented by the formats with a file per frame so that `ParallelWriter` can write them concurrently.
+pub trait FrameEncoder: Send + Sync {
+    fn encode_frame(&self, file_index: u32, points: &PointBatch) -> Result<()>;
 }
@@ -1,56 +1,304 @@
-use std::{fs::{OpenOptions, self}, path::Path};
-use std::io::{BufWriter, Write};
+use std::{fs::{File, OpenOptions, self}, path::Path};
+use std::io::{self, BufWriter, Write};
+use std::str::FromStr;
 
-use crate::{velopoint::VeloPoint, framewriter::FrameWriter};
+use anyhow::{anyhow, Context, Error, Result};
+use flate2::write::GzEncoder;
 
-pub struct CsvWriter {
+use crate::{velopoint::VeloPoint, pointbatch::PointBatch, sensormetadata::SensorMetadata, framewriter::{FrameEncoder, FrameWriter}};
+
+/// A `VeloPoint` field written as a CSV column.
+#[derive(Clone, Copy, Debug)]
+pub enum CsvColumn {
+    Intensity,
+    Channel,
+    Timestamp,
+    Azimuth,
+    Altitude,
+    DistanceM,
+    X,
+    Y,
+    Z,
+}
+
+impl CsvColumn {
+    pub const ALL: [CsvColumn; 9] = [
+        CsvColumn::Intensity,
+        CsvColumn::Channel,
+        CsvColumn::Timestamp,
+        CsvColumn::Azimuth,
+        CsvColumn::Altitude,
+        CsvColumn::DistanceM,
+        CsvColumn::X,
+        CsvColumn::Y,
+        CsvColumn::Z,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            CsvColumn::Intensity => "intensity",
+            CsvColumn::Channel => "channel",
+            CsvColumn::Timestamp => "timestamp",
+            CsvColumn::Azimuth => "azimuth",
+            CsvColumn::Altitude => "altitude",
+            CsvColumn::DistanceM => "distance_m",
+            CsvColumn::X => "x",
+            CsvColumn::Y => "y",
+            CsvColumn::Z => "z",
+        }
+    }
+
+    /// Writes the value without an intermediate string. `precision` only applies to the float columns.
+    fn write_value(&self, out: &mut dyn Write, p: &VeloPoint, precision: Option<usize>) -> io::Result<()> {
+        match self {
+            CsvColumn::Intensity => write!(out, "{}", p.intensity),
+            CsvColumn::Channel => write!(out, "{}", p.channel),
+            CsvColumn::Timestamp => write!(out, "{}", p.timestamp),
+            CsvColumn::Azimuth => write!(out, "{}", p.azimuth),
+            CsvColumn::Altitude => write!(out, "{}", p.altitude),
+            CsvColumn::DistanceM => write_float(out, p.distance_m, precision),
+            CsvColumn::X => write_float(out, p.x, precision),
+            CsvColumn::Y => write_float(out, p.y, precision),
+            CsvColumn::Z => write_float(out, p.z, precision),
+        }
+    }
+
+    /// Parses a comma separated list such as `x,y,z,intensity`.
+    pub fn parse_list(s: &str) -> Result<Vec<CsvColumn>> {
+        s.split(',').map(|c| c.trim().parse()).collect()
+    }
+}
+
+fn write_float(out: &mut dyn Write, v: f32, precision: Option<usize>) -> io::Result<()> {
+    match precision {
+        Some(precision) => write!(out, "{:.*}", precision, v),
+        None => write!(out, "{}", v),
+    }
+}
+
+impl FromStr for CsvColumn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        CsvColumn::ALL.iter()
+            .find(|c| c.name() == s)
+            .copied()
+            .ok_or_else(|| anyhow!("unknown csv column: {}", s))
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CsvDelimiter {
+    Comma,
+    Tab,
+    Space,
+}
+
+impl CsvDelimiter {
+    fn as_str(&self) -> &'static str {
+        match self {
+            CsvDelimiter::Comma => ",",
+            CsvDelimiter::Tab => "\t",
+            CsvDelimiter::Space => " ",
+        }
+    }
+}
+
+impl FromStr for CsvDelimiter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "comma" => Ok(CsvDelimiter::Comma),
+            "tab" => Ok(CsvDelimiter::Tab),
+            "space" => Ok(CsvDelimiter::Space),
+            _ => Err(anyhow!("unknown csv delimiter: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum CsvCompression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl CsvCompression {
+    fn extension(&self) -> &'static str {
+        match self {
+            CsvCompression::None => "csv",
+            CsvCompression::Gzip => "csv.gz",
+            CsvCompression::Zstd => "csv.zst",
+        }
+    }
+}
+
+impl FromStr for CsvCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(CsvCompression::None),
+            "gzip" => Ok(CsvCompression::Gzip),
+            "zstd" => Ok(CsvCompression::Zstd),
+            _ => Err(anyhow!("unknown csv compression: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct CsvOptions {
+    pub columns: Vec<CsvColumn>,
+    /// decimal places of the float columns. `None` writes the shortest exact representation
+    pub precision: Option<usize>,
+    pub delimiter: CsvDelimiter,
+    pub header: bool,
+    pub compression: CsvCompression,
+    /// zero padded width of the frame number in the file name
+    pub frame_digits: usize,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            columns: CsvColumn::ALL.to_vec(),
+            precision: None,
+            delimiter: CsvDelimiter::Comma,
+            header: true,
+            compression: CsvCompression::None,
+            frame_digits: 4,
+        }
+    }
+}
+
+/// An open output file. Rows are written through as they arrive.
+enum CsvOutput {
+    Plain(BufWriter<File>),
+    Gzip(GzEncoder<BufWriter<File>>),
+    Zstd(zstd::Encoder<'static, BufWriter<File>>),
+}
+
+impl CsvOutput {
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            CsvOutput::Plain(out) => out,
+            CsvOutput::Gzip(out) => out,
+            CsvOutput::Zstd(out) => out,
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            CsvOutput::Plain(mut out) => out.flush()?,
+            CsvOutput::Gzip(out) => out.finish()?.flush()?,
+            CsvOutput::Zstd(out) => out.finish()?.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Writes the file of a frame. Shared by `CsvWriter` and `ParallelWriter`.
+pub struct CsvEncoder {
     base_dir: String,
     dir: String,
     file_prefix: String,
-    file_index: u32,
-    buffer: Vec<VeloPoint>,
+    options: CsvOptions,
 }
 
-impl CsvWriter {
-    pub fn create(base_dir: String, dir: String, file_prefix: String) -> CsvWriter {
-        fs::create_dir(format!("{}/{}", base_dir, dir)).unwrap();
-        CsvWriter { 
+impl CsvEncoder {
+    pub fn create(base_dir: String, dir: String, file_prefix: String, options: CsvOptions) -> Result<CsvEncoder> {
+        let out_dir = format!("{}/{}", base_dir, dir);
+        fs::create_dir(&out_dir).with_context(|| format!("failed to create output directory {}", out_dir))?;
+        Ok(CsvEncoder {
             base_dir,
-            dir, 
-            file_prefix, 
-            file_index: 0,
-            buffer: Vec::new(), 
-        }
+            dir,
+            file_prefix,
+            options,
+        })
     }
 
-    fn write_to_file(&mut self) {
-        let current_filename = format!("{0}/{1}/{2}_{3:>04}.csv", self.base_dir, self.dir, self.file_prefix, self.file_index);
+    /// Opens the file of the frame and writes the header.
+    fn open_file(&self, file_index: u32) -> Result<CsvOutput> {
+        let current_filename = format!("{0}/{1}/{2}_{3:0>4$}.{5}",
+            self.base_dir, self.dir, self.file_prefix, file_index, self.options.frame_digits, self.options.compression.extension());
         let path = Path::new(&current_filename);
-        let mut new_file = BufWriter::with_capacity(262144, OpenOptions::new()
+        let new_file = BufWriter::with_capacity(262144, OpenOptions::new()
             .create(true)
             .write(true)
+            .truncate(true)
             .open(path)
-            .unwrap());
-        new_file.write(VeloPoint::get_csv_header().as_bytes()).unwrap();
-        new_file.write("\n".as_bytes()).unwrap();
-        
-        new_file.write(self.buffer.iter().map(|x| x.to_csv_string()).collect::<Vec<String>>().join("\n").as_bytes()).unwrap();
+            .with_context(|| format!("failed to open {}", current_filename))?);
 
-        self.file_index += 1;
+        let mut out = match self.options.compression {
+            CsvCompression::None => CsvOutput::Plain(new_file),
+            CsvCompression::Gzip => CsvOutput::Gzip(GzEncoder::new(new_file, flate2::Compression::default())),
+            CsvCompression::Zstd => CsvOutput::Zstd(zstd::Encoder::new(new_file, 0)?),
+        };
+        if self.options.header {
+            let names: Vec<&str> = self.options.columns.iter().map(|c| c.name()).collect();
+            writeln!(out.writer(), "{}", names.join(self.options.delimiter.as_str()))?;
+        }
+        Ok(out)
+    }
+
+    /// Called for every point, so the columns are written straight into the output.
+    fn write_line(&self, out: &mut CsvOutput, row: &VeloPoint) -> Result<()> {
+        let out = out.writer();
+        for (i, c) in self.options.columns.iter().enumerate() {
+            if i > 0 {
+                out.write_all(self.options.delimiter.as_str().as_bytes())?;
+            }
+            c.write_value(out, row, self.options.precision)?;
+        }
+        out.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+impl FrameEncoder for CsvEncoder {
+    fn encode_frame(&self, file_index: u32, points: &PointBatch) -> Result<()> {
+        let mut out = self.open_file(file_index)?;
+        for row in points.iter() {
+            self.write_line(&mut out, &row)?;
+        }
+        out.finish()
+    }
+}
+
+pub struct CsvWriter {
+    encoder: CsvEncoder,
+    file_index: u32,
+    current: Option<CsvOutput>,
+}
+
+impl CsvWriter {
+    pub fn create(base_dir: String, dir: String, file_prefix: String, options: CsvOptions) -> Result<CsvWriter> {
+        Ok(CsvWriter {
+            encoder: CsvEncoder::create(base_dir, dir, file_prefix, options)?,
+            file_index: 0,
+            current: None,
+        })
     }
 }
 
 impl FrameWriter for CsvWriter {
-    fn write_row(&mut self, row: VeloPoint) {
-        self.buffer.push(row);
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        if self.current.is_none() {
+            self.current = Some(self.encoder.open_file(self.file_index)?);
+        }
+        self.encoder.write_line(self.current.as_mut().unwrap(), &row)
     }
 
-    fn split_frame(&mut self) { 
-        if self.buffer.len() > 0 {
-            self.write_to_file();
-            self.buffer.clear();
+    fn split_frame(&mut self) -> Result<()> {
+        if let Some(out) = self.current.take() {
+            out.finish()?;
+            self.file_index += 1;
         }
+        Ok(())
     }
 
-    fn write_attribute(&mut self, _laser_num: u32, _frequency: f32, _return_mode: u32, _manufacturer: &str, _model: &str) { }
-}
\ No newline at end of file
+    fn write_attribute(&mut self, _metadata: &SensorMetadata) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,429 @@
+use std::collections::hash_map::RandomState;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::hash::{BuildHasher, Hash, Hasher};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+
+use crate::{velopoint::VeloPoint, sensormetadata::SensorMetadata, framewriter::FrameWriter};
+
+const PAGE_SIZE: usize = 1024;
+/// each physical page ends with a CRC-32C checksum
+const LOGICAL_PAGE_SIZE: usize = PAGE_SIZE - 4;
+const FILE_HEADER_LEN: usize = 48;
+const SECTION_HEADER_LEN: usize = 32;
+const COMPRESSED_VECTOR_SECTION: u8 = 1;
+const DATA_PACKET: u8 = 1;
+/// data packets must not exceed 64KiB
+const POINTS_PER_PACKET: usize = 1500;
+
+/// (prototype element, bytes per point)
+const PROTOTYPE: [(&str, usize); 8] = [
+    ("<cartesianX type=\"Float\" precision=\"single\"/>", 4),
+    ("<cartesianY type=\"Float\" precision=\"single\"/>", 4),
+    ("<cartesianZ type=\"Float\" precision=\"single\"/>", 4),
+    ("<sphericalRange type=\"Float\" precision=\"single\"/>", 4),
+    ("<sphericalAzimuth type=\"Float\"/>", 8),
+    ("<sphericalElevation type=\"Float\"/>", 8),
+    ("<intensity type=\"Integer\" minimum=\"0\" maximum=\"255\"/>", 1),
+    ("<timeStamp type=\"Float\"/>", 8),
+];
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0x82F6_3B78 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn physical_offset(logical: u64) -> u64 {
+    logical / LOGICAL_PAGE_SIZE as u64 * PAGE_SIZE as u64 + logical % LOGICAL_PAGE_SIZE as u64
+}
+
+/// Writes a logical byte stream split into checksummed physical pages.
+struct PagedWriter {
+    out: BufWriter<File>,
+    page: Vec<u8>,
+    logical_pos: u64,
+}
+
+impl PagedWriter {
+    fn write(&mut self, mut bytes: &[u8]) -> Result<()> {
+        self.logical_pos += bytes.len() as u64;
+        while !bytes.is_empty() {
+            let n = bytes.len().min(LOGICAL_PAGE_SIZE - self.page.len());
+            self.page.extend_from_slice(&bytes[..n]);
+            bytes = &bytes[n..];
+            if self.page.len() == LOGICAL_PAGE_SIZE {
+                self.flush_page()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush_page(&mut self) -> Result<()> {
+        self.page.resize(LOGICAL_PAGE_SIZE, 0);
+        self.out.write_all(&self.page)?;
+        self.out.write_all(&crc32c(&self.page).to_be_bytes())?;
+        self.page.clear();
+        Ok(())
+    }
+
+    fn align(&mut self, n: u64) -> Result<()> {
+        let padding = (n - self.logical_pos % n) % n;
+        self.write(&vec![0u8; padding as usize])
+    }
+}
+
+struct ScanInfo {
+    guid: String,
+    file_offset: u64,
+    record_count: usize,
+    start_time: f64,
+    end_time: f64,
+}
+
+/// Random (version 4) GUID. The bits come from the randomly keyed std hasher, so that GUIDs
+/// created in the same nanosecond still differ.
+fn guid() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let state = RandomState::new();
+    let half = |part: u64| {
+        let mut hasher = state.build_hasher();
+        (nanos, std::process::id(), count, part).hash(&mut hasher);
+        hasher.finish()
+    };
+    let id = ((half(0) as u128) << 64 | half(1) as u128) & !(0xF000u128 << 64) & !(0xC000u128 << 48);
+    let id = id | (0x4000u128 << 64) | (0x8000u128 << 48);
+    format!("{{{:08X}-{:04X}-{:04X}-{:04X}-{:012X}}}",
+        (id >> 96) as u32, (id >> 80) as u16, (id >> 64) as u16, (id >> 48) as u16, id as u64 & 0xFFFF_FFFF_FFFF)
+}
+
+/// Writes all frames into `[base_dir]/[filename].e57`, one Data3D scan per frame.
+///
+/// Each scan carries cartesian and spherical coordinates, intensity and per point timestamps
+/// (seconds since `acquisitionStart`). Spherical coordinates follow E57: azimuth is measured
+/// counterclockwise from the x axis, so `sphericalAzimuth = PI/2 - azimuth`.
+pub struct E57Writer {
+    out: PagedWriter,
+    file_guid: String,
+    scans: Vec<ScanInfo>,
+    manufacturer: String,
+    model: String,
+    serial: Option<String>,
+    buffer: Vec<VeloPoint>,
+}
+
+impl E57Writer {
+    pub fn create(base_dir: String, filename: String) -> Result<E57Writer> {
+        let filename = format!("{0}/{1}.e57", base_dir, filename);
+        let file = File::create(&filename).with_context(|| format!("failed to create {}", filename))?;
+        let mut out = PagedWriter {
+            out: BufWriter::with_capacity(262144, file),
+            page: Vec::with_capacity(LOGICAL_PAGE_SIZE),
+            logical_pos: 0,
+        };
+        // the first page holds only the file header, which is rewritten in finalize
+        out.write(&[0u8; LOGICAL_PAGE_SIZE])?;
+        Ok(E57Writer {
+            out,
+            file_guid: guid(),
+            scans: Vec::new(),
+            manufacturer: String::new(),
+            model: String::new(),
+            serial: None,
+            buffer: Vec::new(),
+        })
+    }
+
+    fn data_packet(points: &[VeloPoint], start_time: u64) -> Vec<u8> {
+        let mut streams: Vec<Vec<u8>> = PROTOTYPE.iter().map(|(_, size)| Vec::with_capacity(points.len() * size)).collect();
+        for p in points {
+            let azimuth = (PI / 2.0 - p.azimuth as f64 * PI / 18000.0 + PI).rem_euclid(2.0 * PI) - PI;
+            let elevation = p.altitude as f64 * PI / 18000.0;
+            streams[0].extend_from_slice(&p.x.to_le_bytes());
+            streams[1].extend_from_slice(&p.y.to_le_bytes());
+            streams[2].extend_from_slice(&p.z.to_le_bytes());
+            streams[3].extend_from_slice(&p.distance_m.to_le_bytes());
+            streams[4].extend_from_slice(&azimuth.to_le_bytes());
+            streams[5].extend_from_slice(&elevation.to_le_bytes());
+            // 8bit integers are bit packed into whole bytes
+            streams[6].push(p.intensity);
+            streams[7].extend_from_slice(&((p.timestamp as i64 - start_time as i64) as f64 / 1e9).to_le_bytes());
+        }
+
+        let mut packet = vec![DATA_PACKET, 0, 0, 0];
+        packet.extend_from_slice(&(streams.len() as u16).to_le_bytes());
+        for s in streams.iter() {
+            packet.extend_from_slice(&(s.len() as u16).to_le_bytes());
+        }
+        for s in streams.iter() {
+            packet.extend_from_slice(s);
+        }
+        packet.resize((packet.len() + 3) / 4 * 4, 0);
+        let length_minus_1 = (packet.len() - 1) as u16;
+        packet[2..4].copy_from_slice(&length_minus_1.to_le_bytes());
+        packet
+    }
+
+    fn write_to_file(&mut self) -> Result<()> {
+        let start_time = self.buffer.iter().map(|p| p.timestamp).min().unwrap_or(0);
+        let end_time = self.buffer.iter().map(|p| p.timestamp).max().unwrap_or(0);
+        let packets: Vec<Vec<u8>> = self.buffer.chunks(POINTS_PER_PACKET)
+            .map(|points| Self::data_packet(points, start_time))
+            .collect();
+
+        self.out.align(8)?;
+        let section_start = self.out.logical_pos;
+        let section_length = SECTION_HEADER_LEN + packets.iter().map(|p| p.len()).sum::<usize>();
+        let mut header = vec![COMPRESSED_VECTOR_SECTION, 0, 0, 0, 0, 0, 0, 0];
+        header.extend_from_slice(&(section_length as u64).to_le_bytes());
+        header.extend_from_slice(&physical_offset(section_start + SECTION_HEADER_LEN as u64).to_le_bytes());
+        // no index packets
+        header.extend_from_slice(&0u64.to_le_bytes());
+        self.out.write(&header)?;
+        for packet in packets.iter() {
+            self.out.write(packet)?;
+        }
+
+        self.scans.push(ScanInfo {
+            guid: guid(),
+            file_offset: physical_offset(section_start),
+            record_count: self.buffer.len(),
+            start_time: start_time as f64 / 1e9,
+            end_time: end_time as f64 / 1e9,
+        });
+        Ok(())
+    }
+
+    fn xml(&self) -> String {
+        let mut xml = String::new();
+        xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        xml.push_str("<e57Root type=\"Structure\" xmlns=\"http://www.astm.org/COMMIT/E57/2010-e57-v1.0\">\n");
+        xml.push_str("<formatName type=\"String\"><![CDATA[ASTM E57 3D Imaging Data File]]></formatName>\n");
+        xml.push_str(&format!("<guid type=\"String\"><![CDATA[{}]]></guid>\n", self.file_guid));
+        xml.push_str("<versionMajor type=\"Integer\">1</versionMajor>\n");
+        xml.push_str("<versionMinor type=\"Integer\">0</versionMinor>\n");
+        xml.push_str("<data3D type=\"Vector\" allowHeterogeneousChildren=\"1\">\n");
+        for (i, scan) in self.scans.iter().enumerate() {
+            xml.push_str("<vectorChild type=\"Structure\">\n");
+            xml.push_str(&format!("<guid type=\"String\"><![CDATA[{}]]></guid>\n", scan.guid));
+            xml.push_str(&format!("<name type=\"String\"><![CDATA[frame{:0>5}]]></name>\n", i));
+            xml.push_str(&format!("<sensorVendor type=\"String\"><![CDATA[{}]]></sensorVendor>\n", self.manufacturer));
+            xml.push_str(&format!("<sensorModel type=\"String\"><![CDATA[{}]]></sensorModel>\n", self.model));
+            if let Some(serial) = self.serial.as_ref() {
+                xml.push_str(&format!("<sensorSerialNumber type=\"String\"><![CDATA[{}]]></sensorSerialNumber>\n", serial));
+            }
+            for (name, time) in [("acquisitionStart", scan.start_time), ("acquisitionEnd", scan.end_time)] {
+                xml.push_str(&format!("<{} type=\"Structure\">", name));
+                xml.push_str(&format!("<dateTimeValue type=\"Float\">{}</dateTimeValue>", time));
+                xml.push_str("<isAtomicClockReferenced type=\"Integer\">0</isAtomicClockReferenced>");
+                xml.push_str(&format!("</{}>\n", name));
+            }
+            xml.push_str("<intensityLimits type=\"Structure\">");
+            xml.push_str("<intensityMinimum type=\"Integer\">0</intensityMinimum>");
+            xml.push_str("<intensityMaximum type=\"Integer\">255</intensityMaximum>");
+            xml.push_str("</intensityLimits>\n");
+            xml.push_str(&format!("<points type=\"CompressedVector\" fileOffset=\"{}\" recordCount=\"{}\">\n", scan.file_offset, scan.record_count));
+            xml.push_str("<prototype type=\"Structure\">\n");
+            for (element, _) in PROTOTYPE {
+                xml.push_str(element);
+                xml.push('\n');
+            }
+            xml.push_str("</prototype>\n");
+            xml.push_str("<codecs type=\"Vector\" allowHeterogeneousChildren=\"1\"></codecs>\n");
+            xml.push_str("</points>\n");
+            xml.push_str("</vectorChild>\n");
+        }
+        xml.push_str("</data3D>\n");
+        xml.push_str("<images2D type=\"Vector\" allowHeterogeneousChildren=\"1\"></images2D>\n");
+        xml.push_str("</e57Root>\n");
+        xml
+    }
+}
+
+impl FrameWriter for E57Writer {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_to_file()?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, metadata: &SensorMetadata) -> Result<()> {
+        self.manufacturer = metadata.manufacturer.clone();
+        self.model = metadata.model.clone();
+        self.serial = metadata.serial.clone();
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let xml = self.xml();
+        self.out.align(8)?;
+        let xml_offset = physical_offset(self.out.logical_pos);
+        self.out.write(xml.as_bytes())?;
+        if !self.out.page.is_empty() {
+            self.out.flush_page()?;
+        }
+        let file_length = physical_offset(self.out.logical_pos).div_ceil(PAGE_SIZE as u64) * PAGE_SIZE as u64;
+
+        let mut page = Vec::with_capacity(LOGICAL_PAGE_SIZE);
+        page.extend_from_slice(b"ASTM-E57");
+        page.extend_from_slice(&1u32.to_le_bytes()); // major version
+        page.extend_from_slice(&0u32.to_le_bytes()); // minor version
+        page.extend_from_slice(&file_length.to_le_bytes());
+        page.extend_from_slice(&xml_offset.to_le_bytes());
+        page.extend_from_slice(&(xml.len() as u64).to_le_bytes());
+        page.extend_from_slice(&(PAGE_SIZE as u64).to_le_bytes());
+        debug_assert_eq!(page.len(), FILE_HEADER_LEN);
+        page.resize(LOGICAL_PAGE_SIZE, 0);
+        self.out.out.seek(SeekFrom::Start(0))?;
+        self.out.out.write_all(&page)?;
+        self.out.out.write_all(&crc32c(&page).to_be_bytes())?;
+        self.out.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{point, temp_dir};
+
+    fn u64_at(buf: &[u8], pos: usize) -> u64 {
+        u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+    }
+
+    fn u16_at(buf: &[u8], pos: usize) -> usize {
+        u16::from_le_bytes(buf[pos..pos + 2].try_into().unwrap()) as usize
+    }
+
+    fn logical_offset(physical: u64) -> usize {
+        (physical / PAGE_SIZE as u64 * LOGICAL_PAGE_SIZE as u64 + physical % PAGE_SIZE as u64) as usize
+    }
+
+    fn is_guid(s: &str) -> bool {
+        let groups: Vec<&str> = s.trim_start_matches('{').trim_end_matches('}').split('-').collect();
+        groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12])
+            && groups.iter().all(|g| g.chars().all(|c| c.is_ascii_hexdigit()))
+            && groups[2].starts_with('4')
+            && matches!(groups[3].chars().next(), Some('8' | '9' | 'A' | 'B'))
+    }
+
+    #[test]
+    fn crc32c_check_value() {
+        assert_eq!(crc32c(b"123456789"), 0xE306_9283);
+        assert_eq!(crc32c(b""), 0);
+    }
+
+    #[test]
+    fn physical_offsets_skip_page_checksums() {
+        assert_eq!(physical_offset(0), 0);
+        assert_eq!(physical_offset(LOGICAL_PAGE_SIZE as u64 - 1), LOGICAL_PAGE_SIZE as u64 - 1);
+        assert_eq!(physical_offset(LOGICAL_PAGE_SIZE as u64), PAGE_SIZE as u64);
+        assert_eq!(logical_offset(physical_offset(123_456)), 123_456);
+    }
+
+    #[test]
+    fn guids_are_random_v4() {
+        let a = guid();
+        let b = guid();
+        assert!(is_guid(&a), "{}", a);
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn file_header_pages_and_sections() {
+        let dir = temp_dir("e57");
+        let mut writer = E57Writer::create(dir.clone(), "test".to_string()).unwrap();
+        writer.write_attribute(&SensorMetadata::new("Hesai", "XT32")).unwrap();
+        let frame_sizes = [POINTS_PER_PACKET + 500, 10];
+        let mut n = 0;
+        for size in frame_sizes {
+            for _ in 0..size {
+                writer.write_row(point(n)).unwrap();
+                n += 1;
+            }
+            writer.split_frame().unwrap();
+        }
+        writer.finalize().unwrap();
+        drop(writer);
+        let file = std::fs::read(format!("{}/test.e57", dir)).unwrap();
+
+        assert_eq!(file.len() % PAGE_SIZE, 0);
+        let mut logical = Vec::new();
+        for page in file.chunks(PAGE_SIZE) {
+            let (data, crc) = page.split_at(LOGICAL_PAGE_SIZE);
+            assert_eq!(crc, crc32c(data).to_be_bytes());
+            logical.extend_from_slice(data);
+        }
+
+        assert_eq!(&file[0..8], b"ASTM-E57");
+        assert_eq!(u64_at(&file, 16), file.len() as u64);
+        assert_eq!(u64_at(&file, 40), PAGE_SIZE as u64);
+        let xml_start = logical_offset(u64_at(&file, 24));
+        let xml = std::str::from_utf8(&logical[xml_start..xml_start + u64_at(&file, 32) as usize]).unwrap();
+        assert!(xml.starts_with("<?xml") && xml.ends_with("</e57Root>\n"));
+        assert!(xml.contains("<sensorModel type=\"String\"><![CDATA[XT32]]></sensorModel>"));
+
+        let cdata = |tag: &str| -> Vec<String> {
+            xml.split(&format!("<{} type=\"String\"><![CDATA[", tag)).skip(1)
+                .map(|s| s[..s.find("]]>").unwrap()].to_string())
+                .collect()
+        };
+        let guids = cdata("guid");
+        assert_eq!(guids.len(), 1 + frame_sizes.len());
+        assert!(guids.iter().all(|g| is_guid(g)));
+        assert!(guids[1..].iter().all(|g| *g != guids[0]) && guids[1] != guids[2]);
+
+        let points: Vec<(u64, usize)> = xml.split("<points type=\"CompressedVector\" fileOffset=\"").skip(1)
+            .map(|s| {
+                let offset = s[..s.find('"').unwrap()].parse().unwrap();
+                let count = s.split("recordCount=\"").nth(1).unwrap();
+                (offset, count[..count.find('"').unwrap()].parse().unwrap())
+            })
+            .collect();
+        assert_eq!(points.len(), frame_sizes.len());
+        for ((offset, record_count), size) in points.into_iter().zip(frame_sizes) {
+            assert_eq!(record_count, size);
+            let section = logical_offset(offset);
+            assert_eq!(logical[section], COMPRESSED_VECTOR_SECTION);
+            let section_length = u64_at(&logical, section + 8) as usize;
+            let mut packet = logical_offset(u64_at(&logical, section + 16));
+            assert_eq!(packet, section + SECTION_HEADER_LEN);
+
+            let mut intensity_bytes = 0;
+            while packet < section + section_length {
+                assert_eq!(logical[packet], DATA_PACKET);
+                let packet_length = u16_at(&logical, packet + 2) + 1;
+                assert_eq!(packet_length % 4, 0);
+                assert_eq!(u16_at(&logical, packet + 4), PROTOTYPE.len());
+                let stream_bytes: Vec<usize> = (0..PROTOTYPE.len()).map(|i| u16_at(&logical, packet + 6 + i * 2)).collect();
+                let points = stream_bytes[0] / 4;
+                for (bytes, (_, size)) in stream_bytes.iter().zip(PROTOTYPE) {
+                    assert_eq!(*bytes, points * size);
+                }
+                assert!(6 + 2 * PROTOTYPE.len() + stream_bytes.iter().sum::<usize>() <= packet_length);
+                intensity_bytes += stream_bytes[6];
+                packet += packet_length;
+            }
+            assert_eq!(packet, section + section_length);
+            assert_eq!(intensity_bytes, size);
+        }
+    }
+}
@@ -0,0 +1,165 @@
+// foxglove.PointCloud protobuf messages and the descriptor set used as the MCAP schema
+
+use prost::Message;
+use prost_types::{DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use prost_types::field_descriptor_proto::{Label, Type};
+
+use crate::framewriter::pointcloud2;
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct PointCloud {
+    #[prost(message, optional, tag = "1")]
+    pub timestamp: Option<prost_types::Timestamp>,
+    #[prost(string, tag = "2")]
+    pub frame_id: String,
+    #[prost(message, optional, tag = "3")]
+    pub pose: Option<Pose>,
+    #[prost(fixed32, tag = "4")]
+    pub point_stride: u32,
+    #[prost(message, repeated, tag = "5")]
+    pub fields: Vec<PackedElementField>,
+    #[prost(bytes = "vec", tag = "6")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct PackedElementField {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(fixed32, tag = "2")]
+    pub offset: u32,
+    /// foxglove.PackedElementField.NumericType
+    #[prost(int32, tag = "3")]
+    pub r#type: i32,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Pose {
+    #[prost(message, optional, tag = "1")]
+    pub position: Option<Vector3>,
+    #[prost(message, optional, tag = "2")]
+    pub orientation: Option<Quaternion>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Vector3 {
+    #[prost(double, tag = "1")]
+    pub x: f64,
+    #[prost(double, tag = "2")]
+    pub y: f64,
+    #[prost(double, tag = "3")]
+    pub z: f64,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct Quaternion {
+    #[prost(double, tag = "1")]
+    pub x: f64,
+    #[prost(double, tag = "2")]
+    pub y: f64,
+    #[prost(double, tag = "3")]
+    pub z: f64,
+    #[prost(double, tag = "4")]
+    pub w: f64,
+}
+
+const NUMERIC_TYPES: [&str; 9] = ["UNKNOWN", "UINT8", "INT8", "UINT16", "INT16", "UINT32", "INT32", "FLOAT32", "FLOAT64"];
+
+/// Converts a PointCloud2 datatype to foxglove.PackedElementField.NumericType.
+pub(crate) fn numeric_type(datatype: u8) -> i32 {
+    match datatype {
+        pointcloud2::UINT8 => 1,
+        pointcloud2::INT16 => 4,
+        pointcloud2::UINT16 => 3,
+        pointcloud2::FLOAT32 => 7,
+        pointcloud2::FLOAT64 => 8,
+        _ => 0,
+    }
+}
+
+fn field(name: &str, number: i32, ty: Type, type_name: Option<&str>) -> FieldDescriptorProto {
+    FieldDescriptorProto {
+        name: Some(name.to_string()),
+        number: Some(number),
+        label: Some(Label::Optional as i32),
+        r#type: Some(ty as i32),
+        type_name: type_name.map(|t| t.to_string()),
+        ..Default::default()
+    }
+}
+
+fn message(name: &str, field: Vec<FieldDescriptorProto>) -> DescriptorProto {
+    DescriptorProto {
+        name: Some(name.to_string()),
+        field,
+        ..Default::default()
+    }
+}
+
+/// Serialized `FileDescriptorSet` for the `foxglove.PointCloud` schema.
+pub(crate) fn point_cloud_descriptor_set() -> Vec<u8> {
+    let timestamp = FileDescriptorProto {
+        name: Some("google/protobuf/timestamp.proto".to_string()),
+        package: Some("google.protobuf".to_string()),
+        message_type: vec![message("Timestamp", vec![
+            field("seconds", 1, Type::Int64, None),
+            field("nanos", 2, Type::Int32, None),
+        ])],
+        syntax: Some("proto3".to_string()),
+        ..Default::default()
+    };
+
+    let mut packed_element_field = message("PackedElementField", vec![
+        field("name", 1, Type::String, None),
+        field("offset", 2, Type::Fixed32, None),
+        field("type", 3, Type::Enum, Some(".foxglove.PackedElementField.NumericType")),
+    ]);
+    packed_element_field.enum_type.push(EnumDescriptorProto {
+        name: Some("NumericType".to_string()),
+        value: NUMERIC_TYPES.iter().enumerate().map(|(i, name)| EnumValueDescriptorProto {
+            name: Some(name.to_string()),
+            number: Some(i as i32),
+            ..Default::default()
+        }).collect(),
+        ..Default::default()
+    });
+
+    let mut fields = field("fields", 5, Type::Message, Some(".foxglove.PackedElementField"));
+    fields.label = Some(Label::Repeated as i32);
+
+    let foxglove = FileDescriptorProto {
+        name: Some("foxglove/PointCloud.proto".to_string()),
+        package: Some("foxglove".to_string()),
+        dependency: vec!["google/protobuf/timestamp.proto".to_string()],
+        message_type: vec![
+            message("PointCloud", vec![
+                field("timestamp", 1, Type::Message, Some(".google.protobuf.Timestamp")),
+                field("frame_id", 2, Type::String, None),
+                field("pose", 3, Type::Message, Some(".foxglove.Pose")),
+                field("point_stride", 4, Type::Fixed32, None),
+                fields,
+                field("data", 6, Type::Bytes, None),
+            ]),
+            packed_element_field,
+            message("Pose", vec![
+                field("position", 1, Type::Message, Some(".foxglove.Vector3")),
+                field("orientation", 2, Type::Message, Some(".foxglove.Quaternion")),
+            ]),
+            message("Vector3", vec![
+                field("x", 1, Type::Double, None),
+                field("y", 2, Type::Double, None),
+                field("z", 3, Type::Double, None),
+            ]),
+            message("Quaternion", vec![
+                field("x", 1, Type::Double, None),
+                field("y", 2, Type::Double, None),
+                field("z", 3, Type::Double, None),
+                field("w", 4, Type::Double, None),
+            ]),
+        ],
+        syntax: Some("proto3".to_string()),
+        ..Default::default()
+    };
+
+    FileDescriptorSet { file: vec![timestamp, foxglove] }.encode_to_vec()
+}
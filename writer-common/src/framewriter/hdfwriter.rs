@@ -1,50 +1,141 @@
 use std::path::Path;
-use hdf5::File;
+use std::str::FromStr;
+use anyhow::{anyhow, Context, Error, Result};
+use hdf5::{Dataset, File, H5Type};
 
 use crate::framewriter::FrameWriter;
-use crate::velopoint::VeloPoint;
+use crate::pointbatch::PointBatch;
+use crate::sensormetadata::{SensorMetadata, OUTPUT_FORMAT_VERSION};
+use crate::velopoint::{FrameIndex, VeloPoint};
+
+/// rows per chunk of the extendable datasets. the write buffer is flushed at this size.
+const CHUNK_SIZE: usize = 65536;
+/// entries per chunk of the `frames` index
+const FRAME_INDEX_CHUNK_SIZE: usize = 1024;
+
+#[derive(Clone, Copy, Debug)]
+pub enum HdfLayout {
+    /// one dataset `frameNNNNN` per frame
+    Frames,
+    /// a single extendable `points` dataset and a `frames` index
+    Table,
+}
+
+impl HdfLayout {
+    /// value of the `layout` attribute
+    pub fn name(&self) -> &'static str {
+        match self {
+            HdfLayout::Frames => "frames",
+            HdfLayout::Table => "table",
+        }
+    }
+}
+
+impl FromStr for HdfLayout {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "frames" => Ok(HdfLayout::Frames),
+            "table" => Ok(HdfLayout::Table),
+            _ => Err(anyhow!("unknown hdf layout: {}", s)),
+        }
+    }
+}
 
 pub struct HdfWriter {
     file: File,
+    layout: HdfLayout,
     dataset_index: u32,
-    buffer: Vec<VeloPoint>,
+    /// points are buffered as columns and only interleaved into compound rows when flushed
+    buffer: PointBatch,
+    rows: Vec<VeloPoint>,
     enable_compression: bool,
+    /// dataset the buffer is flushed to: `points` in the table layout, or the current frame
+    /// once it has outgrown the buffer in the frames layout
+    points: Option<Dataset>,
+    points_len: usize,
+    // table layout. the index is extended as each frame is closed
+    frames: Option<Dataset>,
+    frames_len: usize,
+    current_frame: Option<FrameIndex>,
 }
 
 impl HdfWriter {
-    pub fn create(base_dir: String, filename: String, enable_compression: bool) -> HdfWriter {
+    pub fn create(base_dir: String, filename: String, enable_compression: bool, layout: HdfLayout) -> Result<HdfWriter> {
         let filename = format!("{0}/{1}.h5", base_dir, filename);
         let path = Path::new(&filename);
-        let file = File::create(path).unwrap();
-        HdfWriter {
+        let file = File::create(path).with_context(|| format!("failed to create {}", filename))?;
+        let (points, frames) = match layout {
+            HdfLayout::Frames => (None, None),
+            HdfLayout::Table => (
+                Some(create_extendable::<VeloPoint>(&file, "points", CHUNK_SIZE, enable_compression)?),
+                Some(create_extendable::<FrameIndex>(&file, "frames", FRAME_INDEX_CHUNK_SIZE, false)?),
+            ),
+        };
+        let writer = HdfWriter {
             file,
+            layout,
             dataset_index: 0,
-            buffer: Vec::new(),
+            buffer: PointBatch::new(),
+            rows: Vec::new(),
             enable_compression,
-        }
+            points,
+            points_len: 0,
+            frames,
+            frames_len: 0,
+            current_frame: None,
+        };
+        writer.add_str_attribute("layout", layout.name())?;
+        Ok(writer)
+    }
+
+    fn add_str_attribute(&self, name: &str, value: &str) -> Result<()> {
+        let value: hdf5::types::VarLenUnicode = value.parse()?;
+        self.file.new_attr_builder()
+            .with_data(&[value])
+            .create(name)?;
+        Ok(())
     }
 
-    fn add_str_attribute(&self, name: &str, value: &str) {
-        let value: hdf5::types::VarLenUnicode = value.parse().unwrap();
+    fn add_u32_attribute(&self, name: &str, value: u32) -> Result<()> {
         self.file.new_attr_builder()
             .with_data(&[value])
-            .create(name).unwrap();
+            .create(name)?;
+        Ok(())
     }
 
-    fn add_u32_attribute(&self, name: &str, value: u32) {
+    fn add_u64_attribute(&self, name: &str, value: u64) -> Result<()> {
         self.file.new_attr_builder()
             .with_data(&[value])
-            .create(name).unwrap();
+            .create(name)?;
+        Ok(())
     }
 
-    fn add_f32_attribute(&self, name: &str, value: f32) {
+    fn add_f32_attribute(&self, name: &str, value: f32) -> Result<()> {
         self.file.new_attr_builder()
             .with_data(&[value])
-            .create(name).unwrap();
+            .create(name)?;
+        Ok(())
+    }
+
+    fn add_f32_array_attribute(&self, name: &str, values: &[f32]) -> Result<()> {
+        self.file.new_attr_builder()
+            .with_data(values)
+            .create(name)?;
+        Ok(())
+    }
+
+    /// Interleaves the buffered columns into `rows` for the compound dataset.
+    fn fill_rows(&mut self) {
+        self.rows.clear();
+        self.rows.extend(self.buffer.iter());
+        self.buffer.clear();
     }
 
-    fn write_to_file(&mut self) {
+    fn write_to_file(&mut self) -> Result<()> {
         let points_num = self.buffer.len();
+        self.fill_rows();
 
         let compression_level = if self.enable_compression { 1 } else { 0 };
         
@@ -52,30 +143,141 @@ impl HdfWriter {
         let dataset = self.file.new_dataset::<VeloPoint>()
             .shape([points_num])
             .deflate(compression_level)
-            .create(&*dataset_name).unwrap();
+            .create(&*dataset_name)?;
         
-        dataset.write(&self.buffer).unwrap();
+        dataset.write(&self.rows)?;
         self.dataset_index += 1;
+        Ok(())
+    }
+
+    /// Updates the `frames` entry of the current frame in the table layout.
+    fn track_frame(&mut self, count: u64, first_timestamp: u64, last_timestamp: u64) {
+        if let HdfLayout::Table = self.layout {
+            let start = (self.points_len + self.buffer.len()) as u64;
+            let frame = self.current_frame.get_or_insert(FrameIndex {
+                start,
+                count: 0,
+                first_timestamp,
+                last_timestamp,
+            });
+            frame.count += count;
+            frame.last_timestamp = last_timestamp;
+        }
+    }
+
+    fn flush_if_full(&mut self) -> Result<()> {
+        if self.buffer.len() < CHUNK_SIZE {
+            return Ok(());
+        }
+        if self.points.is_none() {
+            // the frame does not fit the buffer. switch to an extendable dataset
+            let dataset_name = format!("frame{:0>5}", self.dataset_index);
+            self.points = Some(create_extendable::<VeloPoint>(&self.file, &dataset_name, CHUNK_SIZE, self.enable_compression)?);
+            self.points_len = 0;
+        }
+        self.flush_points()
+    }
+
+    /// Appends the buffered points to `points`.
+    fn flush_points(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let start = self.points_len;
+        let end = start + self.buffer.len();
+        self.fill_rows();
+        let points = self.points.as_ref().unwrap();
+        points.resize(end)?;
+        points.write_slice(&self.rows, start..end)?;
+        self.points_len = end;
+        Ok(())
     }
 }
 
 impl FrameWriter for HdfWriter {
-    fn write_row(&mut self, row: VeloPoint) {
-        self.buffer.push(row);
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.track_frame(1, row.timestamp, row.timestamp);
+        self.buffer.push(row, 0);
+        self.flush_if_full()
+    }
+
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        self.track_frame(batch.len() as u64, batch.timestamp[0], batch.timestamp[batch.len() - 1]);
+        self.buffer.extend(batch);
+        self.flush_if_full()
     }
 
-    fn split_frame(&mut self) {
-        if self.buffer.len() > 0 {
-            self.write_to_file();
-            self.buffer.clear();
+    fn split_frame(&mut self) -> Result<()> {
+        match self.layout {
+            HdfLayout::Frames => {
+                if self.points.is_some() {
+                    self.flush_points()?;
+                    self.points = None;
+                    self.dataset_index += 1;
+                } else if !self.buffer.is_empty() {
+                    self.write_to_file()?;
+                }
+            },
+            HdfLayout::Table => {
+                self.flush_points()?;
+                if let Some(frame) = self.current_frame.take() {
+                    let frames = self.frames.as_ref().unwrap();
+                    frames.resize(self.frames_len + 1)?;
+                    frames.write_slice(std::slice::from_ref(&frame), self.frames_len..self.frames_len + 1)?;
+                    self.frames_len += 1;
+                    // keep the file readable up to the last closed frame if the run is interrupted
+                    self.file.flush()?;
+                }
+            },
         }
+        Ok(())
     }
 
-    fn write_attribute(&mut self, laser_num: u32, frequency: f32, return_mode: u32, manufacturer: &str, model: &str) {
-        self.add_u32_attribute("laser number", laser_num);
-        self.add_f32_attribute("frequency", frequency);
-        self.add_u32_attribute("return mode", return_mode);
-        self.add_str_attribute("manufacturer", manufacturer);
-        self.add_str_attribute("model", model);
+    fn write_attribute(&mut self, metadata: &SensorMetadata) -> Result<()> {
+        self.add_str_attribute("output format version", OUTPUT_FORMAT_VERSION)?;
+        self.add_u32_attribute("laser number", metadata.laser_num)?;
+        self.add_f32_attribute("frequency", metadata.frequency)?;
+        self.add_u32_attribute("return mode", metadata.return_mode)?;
+        self.add_str_attribute("manufacturer", &metadata.manufacturer)?;
+        self.add_str_attribute("model", &metadata.model)?;
+        self.add_str_attribute("parser name", &metadata.parser_name)?;
+        self.add_str_attribute("parser version", &metadata.parser_version)?;
+        self.add_str_attribute("split strategy", &metadata.split_strategy)?;
+        if let Some(serial) = metadata.serial.as_ref() {
+            self.add_str_attribute("serial", serial)?;
+        }
+        if let Some(address) = metadata.source_address {
+            self.add_str_attribute("source ip", &address.ip().to_string())?;
+            self.add_u32_attribute("source port", address.port() as u32)?;
+        }
+        if let Some(source) = metadata.source_file.as_ref() {
+            self.add_str_attribute("source file name", &source.name)?;
+            self.add_u64_attribute("source file size", source.size)?;
+            if let Some(sha256) = source.sha256.as_ref() {
+                self.add_str_attribute("source file sha256", sha256)?;
+            }
+        }
+        if let Some(calibration) = metadata.calibration.as_ref() {
+            self.add_f32_array_attribute("elevation", &calibration.elevation)?;
+            self.add_f32_array_attribute("azimuth offset", &calibration.azimuth_offset)?;
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+
+    fn finalize(&mut self) -> Result<()> {
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// A chunked dataset which is resized on every flush.
+fn create_extendable<T: H5Type>(file: &File, name: &str, chunk_size: usize, enable_compression: bool) -> Result<Dataset> {
+    Ok(file.new_dataset::<T>()
+        .chunk(chunk_size)
+        .shape(0..)
+        .deflate(if enable_compression { 1 } else { 0 })
+        .create(name)?)
+}
@@ -0,0 +1,77 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use anyhow::{Context, Result};
+
+use crate::{velopoint::VeloPoint, sensormetadata::SensorMetadata, framewriter::FrameWriter};
+
+/// Writes KITTI style `[base_dir]/[dir]/velodyne/NNNNNN.bin` (float32 x, y, z, reflectance)
+/// and `[base_dir]/[dir]/timestamps.txt` with the first timestamp of each frame in seconds.
+pub struct KittiWriter {
+    out_dir: String,
+    file_index: u32,
+    x_forward: bool,
+    timestamps: BufWriter<File>,
+    buffer: Vec<VeloPoint>,
+}
+
+impl KittiWriter {
+    /// `x_forward` converts the y-forward coordinates of `VeloPoint` to KITTI's x-forward, y-left frame.
+    pub fn create(base_dir: String, dir: String, x_forward: bool) -> Result<KittiWriter> {
+        let out_dir = format!("{}/{}", base_dir, dir);
+        let velodyne_dir = format!("{}/velodyne", out_dir);
+        fs::create_dir_all(&velodyne_dir).with_context(|| format!("failed to create output directory {}", velodyne_dir))?;
+        let timestamps_filename = format!("{}/timestamps.txt", out_dir);
+        let timestamps = File::create(&timestamps_filename).with_context(|| format!("failed to create {}", timestamps_filename))?;
+        Ok(KittiWriter {
+            out_dir,
+            file_index: 0,
+            x_forward,
+            timestamps: BufWriter::new(timestamps),
+            buffer: Vec::new(),
+        })
+    }
+
+    fn write_to_file(&mut self) -> Result<()> {
+        let current_filename = format!("{0}/velodyne/{1:>06}.bin", self.out_dir, self.file_index);
+        let file = File::create(&current_filename).with_context(|| format!("failed to open {}", current_filename))?;
+        let mut out = BufWriter::with_capacity(262144, file);
+        for p in self.buffer.iter() {
+            let (x, y) = if self.x_forward { (p.y, -p.x) } else { (p.x, p.y) };
+            out.write_all(&x.to_le_bytes())?;
+            out.write_all(&y.to_le_bytes())?;
+            out.write_all(&p.z.to_le_bytes())?;
+            out.write_all(&(p.intensity as f32 / 255.0).to_le_bytes())?;
+        }
+        out.flush()?;
+
+        let frame_start = self.buffer.iter().map(|p| p.timestamp).min().unwrap_or(0);
+        writeln!(self.timestamps, "{}.{:09}", frame_start / 1_000_000_000, frame_start % 1_000_000_000)?;
+        self.file_index += 1;
+        Ok(())
+    }
+}
+
+impl FrameWriter for KittiWriter {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_to_file()?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, _metadata: &SensorMetadata) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.timestamps.flush()?;
+        Ok(())
+    }
+}
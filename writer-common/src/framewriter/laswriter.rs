@@ -0,0 +1,190 @@
+use std::fs::{self, File};
+use std::io::BufWriter;
+
+use anyhow::{anyhow, Context, Result};
+use las::{Builder, GpsTimeType, Point, Transform, Vector, Write};
+use las::point::Format;
+
+use crate::{velopoint::VeloPoint, pointbatch::PointBatch, sensormetadata::SensorMetadata, framewriter::FrameWriter};
+
+/// Dual return mode as passed to `write_attribute`.
+const RETURN_MODE_DUAL: u32 = 2;
+/// GPS epoch (1980-01-06) in unix time. units: nanoseconds
+const GPS_EPOCH_UNIX_NS: u64 = 315_964_800_000_000_000;
+/// GPS time is ahead of UTC by the leap seconds since 1980. units: seconds
+const GPS_LEAP_SECONDS: f64 = 18.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct LasOptions {
+    /// coordinate scale in meters
+    pub scale: f64,
+    /// coordinate offset in meters (x, y, z)
+    pub offset: [f64; 3],
+    /// write the whole capture into `[base_dir]/[prefix].las` instead of one file per frame
+    pub single_file: bool,
+    /// write LAZ instead of LAS
+    pub compress: bool,
+}
+
+impl Default for LasOptions {
+    fn default() -> Self {
+        LasOptions {
+            scale: 0.001,
+            offset: [0.0; 3],
+            single_file: false,
+            compress: false,
+        }
+    }
+}
+
+/// Writes LAS 1.4 point data record format 6.
+///
+/// - intensity: raw sensor intensity
+/// - user data: channel
+/// - return number: `PointBatch::return_index` + 1
+/// - GPS time: unix timestamps as adjusted standard GPS time (global encoding bit 0 set), the
+///   timestamps relative to the hour or the day in seconds as is (GPS week time)
+/// - point source ID: frame index (single file mode only, up to 65536 frames)
+pub struct LasWriter {
+    base_dir: String,
+    dir: String,
+    file_prefix: String,
+    file_index: u32,
+    options: LasOptions,
+    system_identifier: String,
+    dual_return: bool,
+    /// the GPS time type of the open file, decided from its first point
+    standard_gps_time: bool,
+    buffer: PointBatch,
+    single_writer: Option<las::Writer<BufWriter<File>>>,
+}
+
+impl LasWriter {
+    pub fn create(base_dir: String, dir: String, file_prefix: String, options: LasOptions) -> Result<LasWriter> {
+        if !options.single_file {
+            let out_dir = format!("{}/{}", base_dir, dir);
+            fs::create_dir(&out_dir).with_context(|| format!("failed to create output directory {}", out_dir))?;
+        }
+        Ok(LasWriter {
+            base_dir,
+            dir,
+            file_prefix,
+            file_index: 0,
+            options,
+            system_identifier: String::new(),
+            dual_return: false,
+            standard_gps_time: false,
+            buffer: PointBatch::new(),
+            single_writer: None,
+        })
+    }
+
+    fn extension(&self) -> &'static str {
+        if self.options.compress { "laz" } else { "las" }
+    }
+
+    fn open(&mut self, filename: &str) -> Result<las::Writer<BufWriter<File>>> {
+        self.standard_gps_time = self.buffer.timestamp.first().map_or(false, |&t| t >= GPS_EPOCH_UNIX_NS);
+        let transform = |offset| Transform { scale: self.options.scale, offset };
+        let mut builder = Builder::from((1, 4));
+        builder.point_format = Format::new(6)?;
+        builder.transforms = Vector {
+            x: transform(self.options.offset[0]),
+            y: transform(self.options.offset[1]),
+            z: transform(self.options.offset[2]),
+        };
+        builder.gps_time_type = if self.standard_gps_time { GpsTimeType::Standard } else { GpsTimeType::Week };
+        // LAS limits this field to 32 bytes
+        builder.system_identifier = self.system_identifier.chars().take(32).collect();
+        builder.generating_software = "lidar-parser-rs".to_string();
+        let header = builder.into_header()?;
+        // the .laz extension switches the writer to LAZ compression
+        let writer = las::Writer::from_path(filename, header).with_context(|| format!("failed to open {}", filename))?;
+        Ok(writer)
+    }
+
+    fn gps_time(&self, timestamp: u64) -> f64 {
+        if self.standard_gps_time {
+            // adjusted standard GPS time is the GPS time minus 1e9 seconds
+            timestamp.saturating_sub(GPS_EPOCH_UNIX_NS) as f64 / 1e9 + GPS_LEAP_SECONDS - 1e9
+        } else {
+            timestamp as f64 / 1e9
+        }
+    }
+
+    fn write_points(&self, writer: &mut las::Writer<BufWriter<File>>, point_source_id: u16) -> Result<()> {
+        let points = &self.buffer;
+        for i in 0..points.len() {
+            writer.write(Point {
+                x: points.x[i] as f64,
+                y: points.y[i] as f64,
+                z: points.z[i] as f64,
+                intensity: points.intensity[i] as u16,
+                return_number: points.return_index[i] + 1,
+                number_of_returns: if self.dual_return { 2 } else { 1 },
+                user_data: points.channel[i],
+                point_source_id,
+                gps_time: Some(self.gps_time(points.timestamp[i])),
+                ..Default::default()
+            })?;
+        }
+        Ok(())
+    }
+
+    fn write_to_file(&mut self) -> Result<()> {
+        if self.options.single_file {
+            let point_source_id = u16::try_from(self.file_index)
+                .map_err(|_| anyhow!("single file LAS output is limited to 65536 frames (point source ID)"))?;
+            let mut writer = match self.single_writer.take() {
+                Some(writer) => writer,
+                None => {
+                    let filename = format!("{0}/{1}.{2}", self.base_dir, self.file_prefix, self.extension());
+                    self.open(&filename)?
+                },
+            };
+            let result = self.write_points(&mut writer, point_source_id);
+            self.single_writer = Some(writer);
+            result?;
+        } else {
+            let filename = format!("{0}/{1}/{2}_{3:>04}.{4}", self.base_dir, self.dir, self.file_prefix, self.file_index, self.extension());
+            let mut writer = self.open(&filename)?;
+            self.write_points(&mut writer, 0)?;
+            writer.close()?;
+        }
+        self.file_index += 1;
+        Ok(())
+    }
+}
+
+impl FrameWriter for LasWriter {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row, 0);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        self.buffer.extend(batch);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_to_file()?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, metadata: &SensorMetadata) -> Result<()> {
+        self.system_identifier = format!("{} {}", metadata.manufacturer, metadata.model);
+        self.dual_return = metadata.return_mode == RETURN_MODE_DUAL;
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        if let Some(mut writer) = self.single_writer.take() {
+            writer.close()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+//! Minimal LZF compressor compatible with liblzf, as used by PCD `binary_compressed`.
+
+const HASH_LOG: u32 = 14;
+const MAX_LITERAL: usize = 1 << 5;
+const MAX_OFFSET: usize = 1 << 13;
+const MAX_REFERENCE: usize = (1 << 8) + (1 << 3);
+
+fn hash(data: &[u8]) -> usize {
+    let v = ((data[0] as u32) << 16) | ((data[1] as u32) << 8) | data[2] as u32;
+    (v.wrapping_mul(2654435761) >> (32 - HASH_LOG)) as usize
+}
+
+fn push_literals(out: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERAL) {
+        out.push((chunk.len() - 1) as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+pub(crate) fn compress(input: &[u8]) -> Vec<u8> {
+    let mut table = vec![0usize; 1 << HASH_LOG]; // position + 1, 0 means empty
+    let mut out = Vec::with_capacity(input.len() + input.len() / MAX_LITERAL + 1);
+    let mut literal_start = 0;
+    let mut i = 0;
+    while i + 2 < input.len() {
+        let h = hash(&input[i..]);
+        let candidate = table[h];
+        table[h] = i + 1;
+        if candidate > 0 {
+            let reference = candidate - 1;
+            let offset = i - reference - 1;
+            if offset < MAX_OFFSET && input[reference..reference + 3] == input[i..i + 3] {
+                let max_len = (input.len() - i).min(MAX_REFERENCE);
+                let mut match_len = 3;
+                while match_len < max_len && input[reference + match_len] == input[i + match_len] {
+                    match_len += 1;
+                }
+                push_literals(&mut out, &input[literal_start..i]);
+                let len = match_len - 2;
+                if len < 7 {
+                    out.push(((len << 5) + (offset >> 8)) as u8);
+                } else {
+                    out.push(((7 << 5) + (offset >> 8)) as u8);
+                    out.push((len - 7) as u8);
+                }
+                out.push(offset as u8);
+                i += match_len;
+                literal_start = i;
+                continue;
+            }
+        }
+        i += 1;
+    }
+    push_literals(&mut out, &input[literal_start..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// liblzf `lzf_decompress`
+    fn decompress(input: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut i = 0;
+        while i < input.len() {
+            let ctrl = input[i] as usize;
+            i += 1;
+            if ctrl < 32 {
+                out.extend_from_slice(&input[i..i + ctrl + 1]);
+                i += ctrl + 1;
+            } else {
+                let mut len = ctrl >> 5;
+                if len == 7 {
+                    len += input[i] as usize;
+                    i += 1;
+                }
+                let reference = out.len() - ((ctrl & 0x1f) << 8) - input[i] as usize - 1;
+                i += 1;
+                for k in 0..len + 2 {
+                    out.push(out[reference + k]);
+                }
+            }
+        }
+        out
+    }
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let compressed = compress(input);
+        assert_eq!(decompress(&compressed), input);
+        compressed
+    }
+
+    #[test]
+    fn round_trip_short_inputs() {
+        for len in 0..8 {
+            let input: Vec<u8> = (0..len as u8).collect();
+            assert_eq!(round_trip(&input).len(), if len == 0 { 0 } else { len + 1 });
+        }
+    }
+
+    #[test]
+    fn round_trip_repeated_data() {
+        let input = vec![0x5a; 10000];
+        let compressed = round_trip(&input);
+        assert!(compressed.len() < input.len() / 50);
+
+        let input: Vec<u8> = b"x y z intensity ".iter().cycle().take(5000).copied().collect();
+        round_trip(&input);
+    }
+
+    #[test]
+    fn round_trip_random_data() {
+        let mut state = 0x2545_f491_u32;
+        let input: Vec<u8> = (0..100000).map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        }).collect();
+        let compressed = round_trip(&input);
+        // one control byte per 32 literals
+        assert!(compressed.len() <= input.len() + input.len() / MAX_LITERAL + 1);
+    }
+
+    #[test]
+    fn round_trip_far_references() {
+        let block: Vec<u8> = (0..MAX_OFFSET as u32).map(|i| (i * 7 % 251) as u8).collect();
+        let mut input = block.clone();
+        input.extend_from_slice(&block);
+        input.extend_from_slice(&block[..MAX_REFERENCE * 3]);
+        round_trip(&input);
+    }
+}
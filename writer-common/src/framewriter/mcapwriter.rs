@@ -0,0 +1,572 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Error, Result};
+use prost::Message;
+
+use crate::{velopoint::VeloPoint, sensormetadata::SensorMetadata, framewriter::FrameWriter};
+use crate::framewriter::foxglove;
+use crate::framewriter::pointcloud2::{pack_points, to_ros_time, POINT_FIELDS, POINT_STEP};
+
+const MCAP_MAGIC: &[u8] = b"\x89MCAP0\r\n";
+
+const OP_HEADER: u8 = 0x01;
+const OP_FOOTER: u8 = 0x02;
+const OP_SCHEMA: u8 = 0x03;
+const OP_CHANNEL: u8 = 0x04;
+const OP_MESSAGE: u8 = 0x05;
+const OP_CHUNK: u8 = 0x06;
+const OP_MESSAGE_INDEX: u8 = 0x07;
+const OP_CHUNK_INDEX: u8 = 0x08;
+const OP_STATISTICS: u8 = 0x0B;
+const OP_METADATA: u8 = 0x0C;
+const OP_METADATA_INDEX: u8 = 0x0D;
+const OP_SUMMARY_OFFSET: u8 = 0x0E;
+const OP_DATA_END: u8 = 0x0F;
+
+const SCHEMA_ID: u16 = 1;
+const CHANNEL_ID: u16 = 1;
+
+const ROS2_POINTCLOUD2_DEFINITION: &str = "\
+std_msgs/Header header
+uint32 height
+uint32 width
+PointField[] fields
+bool    is_bigendian
+uint32  point_step
+uint32  row_step
+uint8[] data
+bool is_dense
+
+================================================================================
+MSG: std_msgs/Header
+builtin_interfaces/Time stamp
+string frame_id
+
+================================================================================
+MSG: builtin_interfaces/Time
+int32 sec
+uint32 nanosec
+
+================================================================================
+MSG: sensor_msgs/PointField
+uint8 INT8    = 1
+uint8 UINT8   = 2
+uint8 INT16   = 3
+uint8 UINT16  = 4
+uint8 INT32   = 5
+uint8 UINT32  = 6
+uint8 FLOAT32 = 7
+uint8 FLOAT64 = 8
+
+string name
+uint32 offset
+uint8  datatype
+uint32 count
+";
+
+#[derive(Clone, Copy, Debug)]
+pub enum McapEncoding {
+    /// CDR encoded `sensor_msgs/msg/PointCloud2`
+    Ros2,
+    /// protobuf encoded `foxglove.PointCloud`
+    Foxglove,
+}
+
+impl FromStr for McapEncoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ros2" => Ok(McapEncoding::Ros2),
+            "foxglove" => Ok(McapEncoding::Foxglove),
+            _ => Err(anyhow!("unknown mcap encoding: {}", s)),
+        }
+    }
+}
+
+struct ChunkIndex {
+    time: u64,
+    chunk_start: u64,
+    chunk_length: u64,
+    message_index_offset: u64,
+    message_index_length: u64,
+    compressed_size: u64,
+    uncompressed_size: u64,
+}
+
+struct MetadataIndex {
+    offset: u64,
+    length: u64,
+    name: String,
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn put_bytes(buf: &mut Vec<u8>, bytes: &[u8]) {
+    buf.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    buf.extend_from_slice(bytes);
+}
+
+fn put_string_map(buf: &mut Vec<u8>, map: &[(String, String)]) {
+    let mut entries = Vec::new();
+    for (key, value) in map {
+        put_string(&mut entries, key);
+        put_string(&mut entries, value);
+    }
+    put_bytes(buf, &entries);
+}
+
+fn put_record(buf: &mut Vec<u8>, op: u8, content: &[u8]) {
+    buf.push(op);
+    buf.extend_from_slice(&(content.len() as u64).to_le_bytes());
+    buf.extend_from_slice(content);
+}
+
+/// CDR (XCDR1 little endian) serializer. Alignment is relative to the end of the encapsulation header.
+struct CdrWriter {
+    buf: Vec<u8>,
+}
+
+impl CdrWriter {
+    fn new(capacity: usize) -> CdrWriter {
+        let mut buf = Vec::with_capacity(capacity + 4);
+        buf.extend_from_slice(&[0x00, 0x01, 0x00, 0x00]);
+        CdrWriter { buf }
+    }
+
+    fn align(&mut self, n: usize) {
+        while (self.buf.len() - 4) % n != 0 {
+            self.buf.push(0);
+        }
+    }
+
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.align(4);
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32 + 1);
+        self.buf.extend_from_slice(s.as_bytes());
+        self.buf.push(0);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len() as u32);
+        self.buf.extend_from_slice(bytes);
+    }
+}
+
+/// Writes an MCAP file with one point cloud message per frame.
+/// Each frame is stored in its own chunk and the file ends with a summary section so that readers can seek.
+/// Sensor attributes are stored as a metadata record named `sensor`.
+pub struct McapWriter {
+    out: BufWriter<File>,
+    pos: u64,
+    /// CRC-32 of the data section written so far
+    data_crc: crc32fast::Hasher,
+    encoding: McapEncoding,
+    frame_id: String,
+    topic: String,
+    enable_compression: bool,
+    sequence: u32,
+    start_time: u64,
+    end_time: u64,
+    chunks: Vec<ChunkIndex>,
+    metadata: Vec<MetadataIndex>,
+    buffer: Vec<VeloPoint>,
+}
+
+impl McapWriter {
+    pub fn create(base_dir: String, filename: String, encoding: McapEncoding, topic: String, frame_id: String, enable_compression: bool) -> Result<McapWriter> {
+        let filename = format!("{0}/{1}.mcap", base_dir, filename);
+        let file = File::create(&filename).with_context(|| format!("failed to create {}", filename))?;
+        let mut writer = McapWriter {
+            out: BufWriter::with_capacity(262144, file),
+            pos: 0,
+            data_crc: crc32fast::Hasher::new(),
+            encoding,
+            frame_id,
+            topic,
+            enable_compression,
+            sequence: 0,
+            start_time: u64::MAX,
+            end_time: 0,
+            chunks: Vec::new(),
+            metadata: Vec::new(),
+            buffer: Vec::new(),
+        };
+        let mut head = MCAP_MAGIC.to_vec();
+        let mut header = Vec::new();
+        put_string(&mut header, match encoding {
+            McapEncoding::Ros2 => "ros2",
+            McapEncoding::Foxglove => "",
+        });
+        put_string(&mut header, "lidar-parser-rs");
+        put_record(&mut head, OP_HEADER, &header);
+        head.extend(writer.schema_record());
+        head.extend(writer.channel_record());
+        writer.write(&head)?;
+        Ok(writer)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        self.data_crc.update(bytes);
+        self.pos += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn schema_record(&self) -> Vec<u8> {
+        let (name, encoding, data) = match self.encoding {
+            McapEncoding::Ros2 => ("sensor_msgs/msg/PointCloud2", "ros2msg", ROS2_POINTCLOUD2_DEFINITION.as_bytes().to_vec()),
+            McapEncoding::Foxglove => ("foxglove.PointCloud", "protobuf", foxglove::point_cloud_descriptor_set()),
+        };
+        let mut schema = SCHEMA_ID.to_le_bytes().to_vec();
+        put_string(&mut schema, name);
+        put_string(&mut schema, encoding);
+        put_bytes(&mut schema, &data);
+        let mut record = Vec::new();
+        put_record(&mut record, OP_SCHEMA, &schema);
+        record
+    }
+
+    fn channel_record(&self) -> Vec<u8> {
+        let message_encoding = match self.encoding {
+            McapEncoding::Ros2 => "cdr",
+            McapEncoding::Foxglove => "protobuf",
+        };
+        let mut channel = CHANNEL_ID.to_le_bytes().to_vec();
+        channel.extend_from_slice(&SCHEMA_ID.to_le_bytes());
+        put_string(&mut channel, &self.topic);
+        put_string(&mut channel, message_encoding);
+        put_string_map(&mut channel, &[]);
+        let mut record = Vec::new();
+        put_record(&mut record, OP_CHANNEL, &channel);
+        record
+    }
+
+    fn serialize_ros2(&self, timestamp: u64) -> Vec<u8> {
+        let data = pack_points(&self.buffer);
+        let (sec, nanosec) = to_ros_time(timestamp);
+        let mut cdr = CdrWriter::new(data.len() + 512);
+        cdr.u32(sec);
+        cdr.u32(nanosec);
+        cdr.string(&self.frame_id);
+        cdr.u32(1); // height
+        cdr.u32(self.buffer.len() as u32); // width
+        cdr.u32(POINT_FIELDS.len() as u32);
+        for (name, offset, datatype) in POINT_FIELDS {
+            cdr.string(name);
+            cdr.u32(offset);
+            cdr.u8(datatype);
+            cdr.u32(1);
+        }
+        cdr.u8(0); // is_bigendian
+        cdr.u32(POINT_STEP);
+        cdr.u32(data.len() as u32); // row_step
+        cdr.bytes(&data);
+        cdr.u8(1); // is_dense
+        cdr.buf
+    }
+
+    fn serialize_foxglove(&self, timestamp: u64) -> Vec<u8> {
+        let (seconds, nanos) = to_ros_time(timestamp);
+        foxglove::PointCloud {
+            timestamp: Some(prost_types::Timestamp { seconds: seconds as i64, nanos: nanos as i32 }),
+            frame_id: self.frame_id.clone(),
+            pose: Some(foxglove::Pose {
+                position: Some(foxglove::Vector3 { x: 0.0, y: 0.0, z: 0.0 }),
+                orientation: Some(foxglove::Quaternion { x: 0.0, y: 0.0, z: 0.0, w: 1.0 }),
+            }),
+            point_stride: POINT_STEP,
+            fields: POINT_FIELDS.iter().map(|(name, offset, datatype)| foxglove::PackedElementField {
+                name: name.to_string(),
+                offset: *offset,
+                r#type: foxglove::numeric_type(*datatype),
+            }).collect(),
+            data: pack_points(&self.buffer),
+        }.encode_to_vec()
+    }
+
+    fn write_to_file(&mut self) -> Result<()> {
+        let timestamp = self.buffer[0].timestamp;
+        let data = match self.encoding {
+            McapEncoding::Ros2 => self.serialize_ros2(timestamp),
+            McapEncoding::Foxglove => self.serialize_foxglove(timestamp),
+        };
+
+        let mut message = CHANNEL_ID.to_le_bytes().to_vec();
+        message.extend_from_slice(&self.sequence.to_le_bytes());
+        message.extend_from_slice(&timestamp.to_le_bytes()); // log_time
+        message.extend_from_slice(&timestamp.to_le_bytes()); // publish_time
+        message.extend_from_slice(&data);
+        let mut records = Vec::with_capacity(message.len() + 9);
+        put_record(&mut records, OP_MESSAGE, &message);
+
+        let uncompressed_size = records.len() as u64;
+        let uncompressed_crc = crc32fast::hash(&records);
+        let (compression, records) = if self.enable_compression {
+            ("zstd", zstd::bulk::compress(&records, 0)?)
+        } else {
+            ("", records)
+        };
+        let mut chunk = Vec::with_capacity(records.len() + 64);
+        chunk.extend_from_slice(&timestamp.to_le_bytes()); // message_start_time
+        chunk.extend_from_slice(&timestamp.to_le_bytes()); // message_end_time
+        chunk.extend_from_slice(&uncompressed_size.to_le_bytes());
+        chunk.extend_from_slice(&uncompressed_crc.to_le_bytes());
+        put_string(&mut chunk, compression);
+        chunk.extend_from_slice(&(records.len() as u64).to_le_bytes());
+        chunk.extend_from_slice(&records);
+        let mut chunk_record = Vec::with_capacity(chunk.len() + 9);
+        put_record(&mut chunk_record, OP_CHUNK, &chunk);
+
+        // the only message starts at offset 0 of the uncompressed chunk
+        let mut message_index = CHANNEL_ID.to_le_bytes().to_vec();
+        let mut entries = timestamp.to_le_bytes().to_vec();
+        entries.extend_from_slice(&0u64.to_le_bytes());
+        put_bytes(&mut message_index, &entries);
+        let mut message_index_record = Vec::new();
+        put_record(&mut message_index_record, OP_MESSAGE_INDEX, &message_index);
+
+        let chunk_start = self.pos;
+        self.write(&chunk_record)?;
+        let message_index_offset = self.pos;
+        self.write(&message_index_record)?;
+
+        self.chunks.push(ChunkIndex {
+            time: timestamp,
+            chunk_start,
+            chunk_length: chunk_record.len() as u64,
+            message_index_offset,
+            message_index_length: message_index_record.len() as u64,
+            compressed_size: records.len() as u64,
+            uncompressed_size,
+        });
+        self.start_time = self.start_time.min(timestamp);
+        self.end_time = self.end_time.max(timestamp);
+        self.sequence += 1;
+        Ok(())
+    }
+
+    fn summary(&self) -> (Vec<u8>, Vec<(u8, u64, u64)>) {
+        let mut summary = Vec::new();
+        let mut groups = Vec::new();
+
+        for (op, record) in [(OP_SCHEMA, self.schema_record()), (OP_CHANNEL, self.channel_record())] {
+            groups.push((op, summary.len() as u64, record.len() as u64));
+            summary.extend(record);
+        }
+
+        let start = summary.len();
+        let (start_time, end_time) = if self.chunks.is_empty() { (0, 0) } else { (self.start_time, self.end_time) };
+        let mut statistics = (self.sequence as u64).to_le_bytes().to_vec(); // message_count
+        statistics.extend_from_slice(&1u16.to_le_bytes()); // schema_count
+        statistics.extend_from_slice(&1u32.to_le_bytes()); // channel_count
+        statistics.extend_from_slice(&0u32.to_le_bytes()); // attachment_count
+        statistics.extend_from_slice(&(self.metadata.len() as u32).to_le_bytes());
+        statistics.extend_from_slice(&(self.chunks.len() as u32).to_le_bytes());
+        statistics.extend_from_slice(&start_time.to_le_bytes());
+        statistics.extend_from_slice(&end_time.to_le_bytes());
+        let mut channel_message_counts = CHANNEL_ID.to_le_bytes().to_vec();
+        channel_message_counts.extend_from_slice(&(self.sequence as u64).to_le_bytes());
+        put_bytes(&mut statistics, &channel_message_counts);
+        put_record(&mut summary, OP_STATISTICS, &statistics);
+        groups.push((OP_STATISTICS, start as u64, (summary.len() - start) as u64));
+
+        if !self.chunks.is_empty() {
+            let start = summary.len();
+            for chunk in self.chunks.iter() {
+                let mut index = Vec::new();
+                index.extend_from_slice(&chunk.time.to_le_bytes());
+                index.extend_from_slice(&chunk.time.to_le_bytes());
+                index.extend_from_slice(&chunk.chunk_start.to_le_bytes());
+                index.extend_from_slice(&chunk.chunk_length.to_le_bytes());
+                let mut offsets = CHANNEL_ID.to_le_bytes().to_vec();
+                offsets.extend_from_slice(&chunk.message_index_offset.to_le_bytes());
+                put_bytes(&mut index, &offsets);
+                index.extend_from_slice(&chunk.message_index_length.to_le_bytes());
+                put_string(&mut index, if self.enable_compression { "zstd" } else { "" });
+                index.extend_from_slice(&chunk.compressed_size.to_le_bytes());
+                index.extend_from_slice(&chunk.uncompressed_size.to_le_bytes());
+                put_record(&mut summary, OP_CHUNK_INDEX, &index);
+            }
+            groups.push((OP_CHUNK_INDEX, start as u64, (summary.len() - start) as u64));
+        }
+
+        if !self.metadata.is_empty() {
+            let start = summary.len();
+            for metadata in self.metadata.iter() {
+                let mut index = Vec::new();
+                index.extend_from_slice(&metadata.offset.to_le_bytes());
+                index.extend_from_slice(&metadata.length.to_le_bytes());
+                put_string(&mut index, &metadata.name);
+                put_record(&mut summary, OP_METADATA_INDEX, &index);
+            }
+            groups.push((OP_METADATA_INDEX, start as u64, (summary.len() - start) as u64));
+        }
+        (summary, groups)
+    }
+}
+
+impl FrameWriter for McapWriter {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_to_file()?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, metadata: &SensorMetadata) -> Result<()> {
+        let name = "sensor".to_string();
+        let mut body = Vec::new();
+        put_string(&mut body, &name);
+        put_string_map(&mut body, &metadata.to_pairs());
+        let mut record = Vec::new();
+        put_record(&mut record, OP_METADATA, &body);
+        self.metadata.push(MetadataIndex { offset: self.pos, length: record.len() as u64, name });
+        self.write(&record)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let mut tail = Vec::new();
+        put_record(&mut tail, OP_DATA_END, &self.data_crc.clone().finalize().to_le_bytes());
+        let summary_start_in_tail = tail.len();
+        let summary_start = self.pos + tail.len() as u64;
+        let (summary, groups) = self.summary();
+        tail.extend(summary);
+        let summary_offset_start = self.pos + tail.len() as u64;
+        for (op, start, length) in groups {
+            let mut offset = vec![op];
+            offset.extend_from_slice(&(summary_start + start).to_le_bytes());
+            offset.extend_from_slice(&length.to_le_bytes());
+            put_record(&mut tail, OP_SUMMARY_OFFSET, &offset);
+        }
+        let mut footer = summary_start.to_le_bytes().to_vec();
+        footer.extend_from_slice(&summary_offset_start.to_le_bytes());
+        footer.extend_from_slice(&0u32.to_le_bytes());
+        put_record(&mut tail, OP_FOOTER, &footer);
+        // the summary crc covers the summary and summary offset sections and the footer up to the crc field
+        let crc_end = tail.len() - 4;
+        let summary_crc = crc32fast::hash(&tail[summary_start_in_tail..crc_end]);
+        tail[crc_end..].copy_from_slice(&summary_crc.to_le_bytes());
+        tail.extend_from_slice(MCAP_MAGIC);
+        self.write(&tail)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{point, temp_dir};
+
+    fn u32_at(buf: &[u8], pos: usize) -> u32 {
+        u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn u64_at(buf: &[u8], pos: usize) -> u64 {
+        u64::from_le_bytes(buf[pos..pos + 8].try_into().unwrap())
+    }
+
+    /// Returns (op, content) of the records in `buf`.
+    fn records(buf: &[u8]) -> Vec<(u8, &[u8])> {
+        let mut records = Vec::new();
+        let mut pos = 0;
+        while pos < buf.len() {
+            let len = u64_at(buf, pos + 1) as usize;
+            records.push((buf[pos], &buf[pos + 9..pos + 9 + len]));
+            pos += 9 + len;
+        }
+        records
+    }
+
+    fn write_mcap(name: &str, encoding: McapEncoding, enable_compression: bool) -> Vec<u8> {
+        let dir = temp_dir(name);
+        let mut writer = McapWriter::create(dir.clone(), "test".to_string(), encoding, "/points".to_string(), "lidar".to_string(), enable_compression).unwrap();
+        writer.write_attribute(&SensorMetadata::new("Velodyne", "VLP-16")).unwrap();
+        for frame in 0..3 {
+            for i in 0..10 {
+                writer.write_row(point(frame * 10 + i)).unwrap();
+            }
+            writer.split_frame().unwrap();
+        }
+        writer.finalize().unwrap();
+        drop(writer);
+        std::fs::read(format!("{}/test.mcap", dir)).unwrap()
+    }
+
+    fn check_mcap(mcap: &[u8], profile: &str, compression: &str) {
+        assert_eq!(&mcap[..8], MCAP_MAGIC);
+        assert_eq!(&mcap[mcap.len() - 8..], MCAP_MAGIC);
+
+        // footer: summary_start, summary_offset_start, summary_crc
+        let footer = mcap.len() - 8 - 29;
+        assert_eq!(mcap[footer], OP_FOOTER);
+        let summary_start = u64_at(mcap, footer + 9) as usize;
+        let summary_offset_start = u64_at(mcap, footer + 17) as usize;
+        assert_eq!(u32_at(mcap, footer + 25), crc32fast::hash(&mcap[summary_start..footer + 25]));
+
+        // the data section ends with the DataEnd record holding its crc
+        let data_end = summary_start - 13;
+        assert_eq!(mcap[data_end], OP_DATA_END);
+        assert_eq!(u32_at(mcap, data_end + 9), crc32fast::hash(&mcap[..data_end]));
+
+        let data = records(&mcap[8..data_end]);
+        assert_eq!(data[0].0, OP_HEADER);
+        let header = data[0].1;
+        assert_eq!(&header[4..4 + u32_at(header, 0) as usize], profile.as_bytes());
+        let ops: Vec<u8> = data.iter().map(|(op, _)| *op).collect();
+        assert_eq!(ops[1..], [OP_SCHEMA, OP_CHANNEL, OP_METADATA, OP_CHUNK, OP_MESSAGE_INDEX, OP_CHUNK, OP_MESSAGE_INDEX, OP_CHUNK, OP_MESSAGE_INDEX]);
+
+        for (_, chunk) in data.iter().filter(|(op, _)| *op == OP_CHUNK) {
+            let uncompressed_size = u64_at(chunk, 16) as usize;
+            let uncompressed_crc = u32_at(chunk, 24);
+            let compression_len = u32_at(chunk, 28) as usize;
+            assert_eq!(&chunk[32..32 + compression_len], compression.as_bytes());
+            let records_start = 32 + compression_len + 8;
+            let chunk_records = match compression {
+                "zstd" => zstd::bulk::decompress(&chunk[records_start..], uncompressed_size).unwrap(),
+                _ => chunk[records_start..].to_vec(),
+            };
+            assert_eq!(chunk_records.len(), uncompressed_size);
+            assert_eq!(crc32fast::hash(&chunk_records), uncompressed_crc);
+            assert_eq!(records(&chunk_records)[0].0, OP_MESSAGE);
+        }
+
+        let summary_ops: Vec<u8> = records(&mcap[summary_start..summary_offset_start]).iter().map(|(op, _)| *op).collect();
+        assert_eq!(summary_ops, [OP_SCHEMA, OP_CHANNEL, OP_STATISTICS, OP_CHUNK_INDEX, OP_CHUNK_INDEX, OP_CHUNK_INDEX, OP_METADATA_INDEX]);
+        for (op, offset) in records(&mcap[summary_offset_start..footer]) {
+            assert_eq!(op, OP_SUMMARY_OFFSET);
+            let group_start = u64_at(offset, 1) as usize;
+            assert_eq!(mcap[group_start], offset[0]);
+        }
+    }
+
+    #[test]
+    fn ros2_layout_and_crcs() {
+        check_mcap(&write_mcap("mcap-ros2", McapEncoding::Ros2, false), "ros2", "");
+    }
+
+    #[test]
+    fn compressed_foxglove_layout_and_crcs() {
+        check_mcap(&write_mcap("mcap-foxglove", McapEncoding::Foxglove, true), "", "zstd");
+    }
+}
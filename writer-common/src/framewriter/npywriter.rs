@@ -0,0 +1,221 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Error, Result};
+use zip::{CompressionMethod, ZipWriter};
+use zip::write::SimpleFileOptions;
+
+use crate::{velopoint::VeloPoint, sensormetadata::SensorMetadata, framewriter::FrameWriter};
+
+/// A column of the `(N, C)` float32 array. Values keep the units of `VeloPoint`.
+#[derive(Clone, Copy, Debug)]
+pub enum NpyColumn {
+    X,
+    Y,
+    Z,
+    Intensity,
+    Channel,
+    Azimuth,
+    Altitude,
+    DistanceM,
+    /// seconds since the first point of the frame
+    TimestampOffset,
+}
+
+impl NpyColumn {
+    fn value(&self, p: &VeloPoint, frame_start: u64) -> f32 {
+        match self {
+            NpyColumn::X => p.x,
+            NpyColumn::Y => p.y,
+            NpyColumn::Z => p.z,
+            NpyColumn::Intensity => p.intensity as f32,
+            NpyColumn::Channel => p.channel as f32,
+            NpyColumn::Azimuth => p.azimuth as f32,
+            NpyColumn::Altitude => p.altitude as f32,
+            NpyColumn::DistanceM => p.distance_m,
+            NpyColumn::TimestampOffset => (p.timestamp as i64 - frame_start as i64) as f32 / 1e9,
+        }
+    }
+
+    /// Parses a comma separated list such as `x,y,z,intensity`.
+    pub fn parse_list(s: &str) -> Result<Vec<NpyColumn>> {
+        s.split(',').map(|c| c.trim().parse()).collect()
+    }
+}
+
+impl FromStr for NpyColumn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x" => Ok(NpyColumn::X),
+            "y" => Ok(NpyColumn::Y),
+            "z" => Ok(NpyColumn::Z),
+            "intensity" => Ok(NpyColumn::Intensity),
+            "channel" => Ok(NpyColumn::Channel),
+            "azimuth" => Ok(NpyColumn::Azimuth),
+            "altitude" => Ok(NpyColumn::Altitude),
+            "distance_m" => Ok(NpyColumn::DistanceM),
+            "timestamp_offset" => Ok(NpyColumn::TimestampOffset),
+            _ => Err(anyhow!("unknown npy column: {}", s)),
+        }
+    }
+}
+
+/// Writes an array in .npy format version 1.0.
+fn write_npy<W: Write>(out: &mut W, descr: &str, shape: &str, data: &[u8]) -> Result<()> {
+    let mut header = format!("{{'descr': {}, 'fortran_order': False, 'shape': {}, }}", descr, shape);
+    // magic(6) + version(2) + header length(2) + header must be a multiple of 64
+    let total = 10 + header.len() + 1;
+    header.push_str(&" ".repeat((64 - total % 64) % 64));
+    header.push('\n');
+    out.write_all(b"\x93NUMPY\x01\x00")?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())?;
+    out.write_all(data)?;
+    Ok(())
+}
+
+/// Writes frames as `(N, C)` float32 arrays, either one `[base_dir]/[dir]/[prefix]_NNNN.npy` per frame
+/// or a single `[base_dir]/[prefix].npz` with `frameNNNNN` entries, a `meta` entry and a `metadata`
+/// entry holding `SensorMetadata::to_pairs` as an `(M, 2)` unicode array.
+pub struct NpyWriter {
+    base_dir: String,
+    dir: String,
+    file_prefix: String,
+    file_index: u32,
+    columns: Vec<NpyColumn>,
+    npz: Option<ZipWriter<BufWriter<File>>>,
+    npz_options: SimpleFileOptions,
+    meta: Option<Vec<u8>>,
+    metadata: Vec<(String, String)>,
+    buffer: Vec<VeloPoint>,
+}
+
+impl NpyWriter {
+    pub fn create(base_dir: String, dir: String, file_prefix: String, columns: Vec<NpyColumn>, npz: bool, enable_compression: bool) -> Result<NpyWriter> {
+        let npz = if npz {
+            let filename = format!("{0}/{1}.npz", base_dir, file_prefix);
+            let file = File::create(&filename).with_context(|| format!("failed to create {}", filename))?;
+            Some(ZipWriter::new(BufWriter::with_capacity(262144, file)))
+        } else {
+            let out_dir = format!("{}/{}", base_dir, dir);
+            fs::create_dir(&out_dir).with_context(|| format!("failed to create output directory {}", out_dir))?;
+            None
+        };
+        let method = if enable_compression { CompressionMethod::Deflated } else { CompressionMethod::Stored };
+        Ok(NpyWriter {
+            base_dir,
+            dir,
+            file_prefix,
+            file_index: 0,
+            columns,
+            npz,
+            npz_options: SimpleFileOptions::default().compression_method(method).large_file(true),
+            meta: None,
+            metadata: Vec::new(),
+            buffer: Vec::new(),
+        })
+    }
+
+    fn frame_data(&self) -> Vec<u8> {
+        let frame_start = self.buffer.first().map_or(0, |p| p.timestamp);
+        let mut data = Vec::with_capacity(self.buffer.len() * self.columns.len() * 4);
+        for p in self.buffer.iter() {
+            for c in self.columns.iter() {
+                data.extend_from_slice(&c.value(p, frame_start).to_le_bytes());
+            }
+        }
+        data
+    }
+
+    fn write_to_file(&mut self) -> Result<()> {
+        let data = self.frame_data();
+        let shape = format!("({}, {})", self.buffer.len(), self.columns.len());
+        match self.npz.as_mut() {
+            Some(npz) => {
+                npz.start_file(format!("frame{:0>5}.npy", self.file_index), self.npz_options)?;
+                write_npy(npz, "'<f4'", &shape, &data)?;
+            },
+            None => {
+                let current_filename = format!("{0}/{1}/{2}_{3:>04}.npy", self.base_dir, self.dir, self.file_prefix, self.file_index);
+                let file = File::create(&current_filename).with_context(|| format!("failed to open {}", current_filename))?;
+                let mut out = BufWriter::with_capacity(262144, file);
+                write_npy(&mut out, "'<f4'", &shape, &data)?;
+                out.flush()?;
+            },
+        }
+        self.file_index += 1;
+        Ok(())
+    }
+}
+
+/// fixed length string field of the `meta` record
+fn fixed_str(s: &str, len: usize) -> Vec<u8> {
+    let mut bytes = s.as_bytes().to_vec();
+    bytes.resize(len, 0);
+    bytes
+}
+
+/// `(M, 2)` array of key-value pairs as numpy unicode strings (UTF-32), so that
+/// `dict(npz['metadata'])` gives the metadata back.
+fn write_pairs_npy<W: Write>(out: &mut W, pairs: &[(String, String)]) -> Result<()> {
+    let width = pairs.iter()
+        .flat_map(|(key, value)| [key.chars().count(), value.chars().count()])
+        .max()
+        .unwrap_or(0)
+        .max(1);
+    let mut data = Vec::with_capacity(pairs.len() * 2 * width * 4);
+    for (key, value) in pairs.iter() {
+        for s in [key, value] {
+            let len = s.chars().count();
+            for c in s.chars() {
+                data.extend_from_slice(&(c as u32).to_le_bytes());
+            }
+            data.resize(data.len() + (width - len) * 4, 0);
+        }
+    }
+    write_npy(out, &format!("'<U{}'", width), &format!("({}, 2)", pairs.len()), &data)
+}
+
+impl FrameWriter for NpyWriter {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_to_file()?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, metadata: &SensorMetadata) -> Result<()> {
+        let mut meta = Vec::new();
+        meta.extend_from_slice(&metadata.laser_num.to_le_bytes());
+        meta.extend_from_slice(&metadata.frequency.to_le_bytes());
+        meta.extend_from_slice(&metadata.return_mode.to_le_bytes());
+        meta.extend(fixed_str(&metadata.manufacturer, 32));
+        meta.extend(fixed_str(&metadata.model, 32));
+        self.meta = Some(meta);
+        self.metadata = metadata.to_pairs();
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        if let Some(mut npz) = self.npz.take() {
+            if let Some(meta) = self.meta.as_ref() {
+                npz.start_file("meta.npy", self.npz_options)?;
+                let descr = "[('laser_number', '<u4'), ('frequency', '<f4'), ('return_mode', '<u4'), ('manufacturer', 'S32'), ('model', 'S32')]";
+                write_npy(&mut npz, descr, "(1,)", meta)?;
+                npz.start_file("metadata.npy", self.npz_options)?;
+                write_pairs_npy(&mut npz, &self.metadata)?;
+            }
+            npz.finish()?.flush()?;
+        }
+        Ok(())
+    }
+}
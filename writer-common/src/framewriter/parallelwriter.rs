@@ -0,0 +1,110 @@
+use std::mem;
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Error, Result};
+
+use crate::{velopoint::VeloPoint, pointbatch::PointBatch, sensormetadata::SensorMetadata, framewriter::{FrameEncoder, FrameWriter}};
+
+/// Buffers each frame and hands the completed frames to a pool of threads which encode them
+/// into their own files.
+///
+/// Frame numbers are assigned in the order the frames complete, so the file names are the same
+/// as with the single threaded writers. At most `2 * threads` frames are held in memory.
+pub struct ParallelWriter {
+    buffer: PointBatch,
+    file_index: u32,
+    sender: Option<SyncSender<(u32, PointBatch)>>,
+    workers: Vec<JoinHandle<()>>,
+    /// the first error of the workers
+    error: Arc<Mutex<Option<Error>>>,
+}
+
+impl ParallelWriter {
+    pub fn new(encoder: Arc<dyn FrameEncoder>, threads: usize) -> ParallelWriter {
+        let (sender, receiver) = mpsc::sync_channel::<(u32, PointBatch)>(threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let error = Arc::new(Mutex::new(None));
+        let workers = (0..threads).map(|_| {
+            let encoder = encoder.clone();
+            let receiver = receiver.clone();
+            let error = error.clone();
+            thread::spawn(move || loop {
+                let frame = receiver.lock().unwrap().recv();
+                let Ok((file_index, points)) = frame else {
+                    break;
+                };
+                if let Err(e) = encoder.encode_frame(file_index, &points) {
+                    error.lock().unwrap().get_or_insert(e);
+                    break;
+                }
+            })
+        }).collect();
+        ParallelWriter {
+            buffer: PointBatch::new(),
+            file_index: 0,
+            sender: Some(sender),
+            workers,
+            error,
+        }
+    }
+
+    fn take_error(&self) -> Result<()> {
+        match self.error.lock().unwrap().take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Waits until all queued frames are written.
+    fn join(&mut self) -> Result<()> {
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            worker.join().map_err(|_| anyhow!("writer thread panicked"))?;
+        }
+        self.take_error()
+    }
+}
+
+impl FrameWriter for ParallelWriter {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row, 0);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        self.buffer.extend(batch);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        self.take_error()?;
+        let sender = self.sender.as_ref().ok_or_else(|| anyhow!("writer is already finalized"))?;
+        if sender.send((self.file_index, mem::take(&mut self.buffer))).is_err() {
+            // every worker has stopped on an error
+            self.join()?;
+            return Err(anyhow!("writer threads stopped"));
+        }
+        self.file_index += 1;
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, _metadata: &SensorMetadata) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.join()
+    }
+}
+
+impl Drop for ParallelWriter {
+    fn drop(&mut self) {
+        // let the queued frames finish when the parser stops on an error
+        let _ = self.join();
+    }
+}
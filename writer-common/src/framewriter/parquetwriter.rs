@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use arrow::array::{ArrayRef, Float32Array, Int16Array, UInt16Array, UInt32Array, UInt64Array, UInt8Array};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use arrow::ipc::writer::StreamWriter;
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+
+use crate::{velopoint::VeloPoint, pointbatch::PointBatch, sensormetadata::SensorMetadata, framewriter::FrameWriter};
+
+fn velopoint_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("frame", DataType::UInt32, false),
+        Field::new("intensity", DataType::UInt8, false),
+        Field::new("channel", DataType::UInt8, false),
+        Field::new("timestamp", DataType::UInt64, false),
+        Field::new("azimuth", DataType::UInt16, false),
+        Field::new("altitude", DataType::Int16, false),
+        Field::new("distance_m", DataType::Float32, false),
+        Field::new("x", DataType::Float32, false),
+        Field::new("y", DataType::Float32, false),
+        Field::new("z", DataType::Float32, false),
+    ])
+}
+
+/// The columns of the batch are moved into the arrays without copying.
+fn to_record_batch(schema: SchemaRef, frame: u32, points: PointBatch) -> Result<RecordBatch> {
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt32Array::from(vec![frame; points.len()])),
+        Arc::new(UInt8Array::from(points.intensity)),
+        Arc::new(UInt8Array::from(points.channel)),
+        Arc::new(UInt64Array::from(points.timestamp)),
+        Arc::new(UInt16Array::from(points.azimuth)),
+        Arc::new(Int16Array::from(points.altitude)),
+        Arc::new(Float32Array::from(points.distance_m)),
+        Arc::new(Float32Array::from(points.x)),
+        Arc::new(Float32Array::from(points.y)),
+        Arc::new(Float32Array::from(points.z)),
+    ];
+    Ok(RecordBatch::try_new(schema, columns)?)
+}
+
+/// Writes the whole capture into `[base_dir]/[filename].parquet`.
+/// Each row carries its frame number, and a row group is closed every `frames_per_row_group` frames.
+pub struct ParquetWriter {
+    writer: ArrowWriter<File>,
+    schema: SchemaRef,
+    frame_index: u32,
+    frames_per_row_group: u32,
+    buffer: PointBatch,
+}
+
+impl ParquetWriter {
+    pub fn create(base_dir: String, filename: String, frames_per_row_group: u32, enable_compression: bool) -> Result<ParquetWriter> {
+        let filename = format!("{0}/{1}.parquet", base_dir, filename);
+        let file = File::create(&filename).with_context(|| format!("failed to create {}", filename))?;
+        let schema = Arc::new(velopoint_schema());
+        let compression = if enable_compression {
+            Compression::ZSTD(ZstdLevel::default())
+        } else {
+            Compression::UNCOMPRESSED
+        };
+        let props = WriterProperties::builder()
+            .set_compression(compression)
+            // row groups are closed explicitly in split_frame
+            .set_max_row_group_size(usize::MAX)
+            .build();
+        let writer = ArrowWriter::try_new(file, schema.clone(), Some(props))?;
+        Ok(ParquetWriter {
+            writer,
+            schema,
+            frame_index: 0,
+            frames_per_row_group: frames_per_row_group.max(1),
+            buffer: PointBatch::new(),
+        })
+    }
+}
+
+impl FrameWriter for ParquetWriter {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row, 0);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        self.buffer.extend(batch);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            let points = std::mem::take(&mut self.buffer);
+            let batch = to_record_batch(self.schema.clone(), self.frame_index, points)?;
+            self.writer.write(&batch)?;
+            self.frame_index += 1;
+            if self.frame_index % self.frames_per_row_group == 0 {
+                self.writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, metadata: &SensorMetadata) -> Result<()> {
+        for (key, value) in metadata.to_pairs() {
+            self.writer.append_key_value_metadata(KeyValue::new(key, value));
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.writer.finish()?;
+        Ok(())
+    }
+}
+
+/// Writes the whole capture as an Arrow IPC stream (`[base_dir]/[filename].arrows`), one record batch per frame.
+pub struct ArrowIpcWriter {
+    file: Option<File>,
+    writer: Option<StreamWriter<BufWriter<File>>>,
+    metadata: HashMap<String, String>,
+    frame_index: u32,
+    buffer: PointBatch,
+}
+
+impl ArrowIpcWriter {
+    pub fn create(base_dir: String, filename: String) -> Result<ArrowIpcWriter> {
+        let filename = format!("{0}/{1}.arrows", base_dir, filename);
+        let file = File::create(&filename).with_context(|| format!("failed to create {}", filename))?;
+        Ok(ArrowIpcWriter {
+            file: Some(file),
+            writer: None,
+            metadata: HashMap::new(),
+            frame_index: 0,
+            buffer: PointBatch::new(),
+        })
+    }
+
+    /// The stream header carries the schema metadata, so the writer is created on the first frame
+    /// after `write_attribute` has been called.
+    fn stream_writer(&mut self) -> Result<&mut StreamWriter<BufWriter<File>>> {
+        if let Some(file) = self.file.take() {
+            let schema = velopoint_schema().with_metadata(self.metadata.clone());
+            self.writer = Some(StreamWriter::try_new(BufWriter::with_capacity(262144, file), &schema)?);
+        }
+        Ok(self.writer.as_mut().unwrap())
+    }
+}
+
+impl FrameWriter for ArrowIpcWriter {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row, 0);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        self.buffer.extend(batch);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            let frame_index = self.frame_index;
+            let points = std::mem::take(&mut self.buffer);
+            let writer = self.stream_writer()?;
+            // the stream header already carries the schema metadata
+            let batch = to_record_batch(Arc::new(velopoint_schema()), frame_index, points)?;
+            writer.write(&batch)?;
+            self.frame_index += 1;
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, metadata: &SensorMetadata) -> Result<()> {
+        self.metadata.extend(metadata.to_pairs());
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let writer = self.stream_writer()?;
+        writer.finish()?;
+        writer.get_mut().flush()?;
+        Ok(())
+    }
+}
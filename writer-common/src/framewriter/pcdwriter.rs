@@ -1,78 +1,284 @@
-use std::fs;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Cursor, Read, Seek, SeekFrom, Write};
+use std::str::FromStr;
 
-use pcd_rs::{DynRecord, ValueKind, DynWriter, WriterInit, Schema, Field};
+use anyhow::{anyhow, ensure, Context, Error, Result};
 
-use crate::{velopoint::VeloPoint, framewriter::FrameWriter};
+use crate::{velopoint::VeloPoint, pointbatch::PointBatch, sensormetadata::SensorMetadata, framewriter::{FrameEncoder, FrameWriter, lzf}};
 
-pub struct PcdWriter {
+#[derive(Clone, Copy, Debug)]
+pub enum PcdDataKind {
+    Ascii,
+    Binary,
+    BinaryCompressed,
+}
+
+impl FromStr for PcdDataKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(PcdDataKind::Ascii),
+            "binary" => Ok(PcdDataKind::Binary),
+            "binary_compressed" => Ok(PcdDataKind::BinaryCompressed),
+            _ => Err(anyhow!("unknown pcd data kind: {}", s)),
+        }
+    }
+}
+
+impl PcdDataKind {
+    fn header_name(&self) -> &'static str {
+        match self {
+            PcdDataKind::Ascii => "ascii",
+            PcdDataKind::Binary => "binary",
+            PcdDataKind::BinaryCompressed => "binary_compressed",
+        }
+    }
+}
+
+// (name, size, type)
+const PCD_FIELDS: [(&str, usize, char); 9] = [
+    ("x", 4, 'F'),
+    ("y", 4, 'F'),
+    ("z", 4, 'F'),
+    ("intensity", 1, 'U'),
+    ("channel", 1, 'U'),
+    ("azimuth", 2, 'U'),
+    ("altitude", 2, 'I'),
+    ("distance_m", 4, 'F'),
+    ("timestamp", 8, 'U'), // nanoseconds, kept as uint64 to stay lossless
+];
+
+/// bytes of one point in the binary layouts
+const POINT_SIZE: usize = 30;
+/// points compressed at once in binary_compressed. the LZF outputs of consecutive chunks
+/// form a valid LZF stream of the whole column.
+const COMPRESS_CHUNK_POINTS: usize = 65536;
+
+/// The frame being written.
+struct PcdFrame {
+    filename: String,
+    out: BufWriter<File>,
+    points_num: usize,
+    /// positions of the WIDTH and POINTS values, rewritten in `split_frame`
+    count_positions: [u64; 2],
+}
+
+/// Writes the file of a frame. Shared by `PcdWriter` and `ParallelWriter`.
+pub struct PcdEncoder {
     base_dir: String,
     dir: String,
     file_prefix: String,
-    file_index: u32,
-    buffer: Vec<DynRecord>,
+    data_kind: PcdDataKind,
 }
 
-impl PcdWriter {
-    pub fn create(base_dir: String, dir: String, file_prefix: String) -> PcdWriter {
-        fs::create_dir(format!("{}/{}", base_dir, dir)).unwrap();
-        PcdWriter { 
+impl PcdEncoder {
+    pub fn create(base_dir: String, dir: String, file_prefix: String, data_kind: PcdDataKind) -> Result<PcdEncoder> {
+        let out_dir = format!("{}/{}", base_dir, dir);
+        fs::create_dir(&out_dir).with_context(|| format!("failed to create output directory {}", out_dir))?;
+        Ok(PcdEncoder {
             base_dir,
-            dir, 
-            file_prefix, 
-            file_index: 0,
-            buffer: Vec::new(), 
+            dir,
+            file_prefix,
+            data_kind,
+        })
+    }
+
+    fn filename(&self, file_index: u32) -> String {
+        format!("{0}/{1}/{2}_{3:>04}.pcd", self.base_dir, self.dir, self.file_prefix, file_index)
+    }
+
+    /// Writes the header with zero padded point counts so that they can be patched later.
+    /// Returns the positions of the WIDTH and POINTS values.
+    fn write_header<W: Write>(&self, out: &mut W, points_num: usize) -> Result<[u64; 2]> {
+        let mut header = String::new();
+        header.push_str("# .PCD v0.7 - Point Cloud Data file format\n");
+        header.push_str("VERSION 0.7\n");
+        header.push_str(&format!("FIELDS {}\n", PCD_FIELDS.iter().map(|f| f.0).collect::<Vec<_>>().join(" ")));
+        header.push_str(&format!("SIZE {}\n", PCD_FIELDS.iter().map(|f| f.1.to_string()).collect::<Vec<_>>().join(" ")));
+        header.push_str(&format!("TYPE {}\n", PCD_FIELDS.iter().map(|f| f.2.to_string()).collect::<Vec<_>>().join(" ")));
+        header.push_str(&format!("COUNT {}\n", PCD_FIELDS.iter().map(|_| "1").collect::<Vec<_>>().join(" ")));
+        header.push_str("WIDTH ");
+        let width_position = header.len() as u64;
+        header.push_str(&format!("{:010}\n", points_num));
+        header.push_str("HEIGHT 1\n");
+        header.push_str("VIEWPOINT 0 0 0 1 0 0 0\n");
+        header.push_str("POINTS ");
+        let points_position = header.len() as u64;
+        header.push_str(&format!("{:010}\n", points_num));
+        header.push_str(&format!("DATA {}\n", self.data_kind.header_name()));
+        out.write_all(header.as_bytes())?;
+        Ok([width_position, points_position])
+    }
+
+    /// Opens the file of the frame. binary_compressed stores columns, so its points
+    /// go to a row major temporary file first.
+    fn open_frame(&self, file_index: u32) -> Result<PcdFrame> {
+        let filename = self.filename(file_index);
+        let out_filename = match self.data_kind {
+            PcdDataKind::BinaryCompressed => format!("{}.tmp", filename),
+            _ => filename.clone(),
+        };
+        // the temporary file is read back in `split_frame`
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&out_filename)
+            .with_context(|| format!("failed to open {}", out_filename))?;
+        let mut out = BufWriter::with_capacity(262144, file);
+        let count_positions = match self.data_kind {
+            PcdDataKind::BinaryCompressed => [0, 0],
+            _ => self.write_header(&mut out, 0)?,
+        };
+        Ok(PcdFrame { filename, out, points_num: 0, count_positions })
+    }
+
+    fn finish_frame(&self, frame: PcdFrame) -> Result<()> {
+        let PcdFrame { filename, out, points_num, count_positions } = frame;
+        let mut file = out.into_inner().map_err(|e| e.into_error())?;
+        match self.data_kind {
+            PcdDataKind::Ascii | PcdDataKind::Binary => {
+                for position in count_positions {
+                    file.seek(SeekFrom::Start(position))?;
+                    file.write_all(format!("{:010}", points_num).as_bytes())?;
+                }
+            },
+            PcdDataKind::BinaryCompressed => {
+                let tmp_filename = format!("{}.tmp", filename);
+                self.write_binary_compressed(&filename, file, points_num)?;
+                fs::remove_file(&tmp_filename)?;
+            },
         }
+        Ok(())
+    }
+
+    /// Compresses the row major points column by column into `filename`.
+    fn write_binary_compressed<R: Read + Seek>(&self, filename: &str, mut rows: R, points_num: usize) -> Result<()> {
+        let data_size = points_num * POINT_SIZE;
+        ensure!(data_size <= u32::MAX as usize, "too many points for binary_compressed: {}", points_num);
+        let file = File::create(filename).with_context(|| format!("failed to open {}", filename))?;
+        let mut out = BufWriter::with_capacity(262144, file);
+        self.write_header(&mut out, points_num)?;
+        let sizes_position = out.stream_position()?;
+        out.write_all(&[0u8; 8])?;
+
+        let mut compressed_size = 0;
+        let mut field_offset = 0;
+        let mut chunk = vec![0u8; COMPRESS_CHUNK_POINTS * POINT_SIZE];
+        let mut column = Vec::with_capacity(COMPRESS_CHUNK_POINTS * 8);
+        for (_, field_size, _) in PCD_FIELDS.iter() {
+            rows.seek(SeekFrom::Start(0))?;
+            let mut remaining = points_num;
+            while remaining > 0 {
+                let n = remaining.min(COMPRESS_CHUNK_POINTS);
+                rows.read_exact(&mut chunk[..n * POINT_SIZE])?;
+                column.clear();
+                for row in chunk[..n * POINT_SIZE].chunks_exact(POINT_SIZE) {
+                    column.extend_from_slice(&row[field_offset..field_offset + field_size]);
+                }
+                let compressed = lzf::compress(&column);
+                out.write_all(&compressed)?;
+                compressed_size += compressed.len();
+                remaining -= n;
+            }
+            field_offset += field_size;
+        }
+        ensure!(compressed_size <= u32::MAX as usize, "compressed frame is too large: {}", compressed_size);
+
+        out.seek(SeekFrom::Start(sizes_position))?;
+        out.write_all(&(compressed_size as u32).to_le_bytes())?;
+        out.write_all(&(data_size as u32).to_le_bytes())?;
+        out.flush()?;
+        Ok(())
     }
 
-    fn write_to_file(&mut self) {
-        let current_filename = format!("{0}/{1}/{2}_{3:>04}.pcd", self.base_dir, self.dir, self.file_prefix, self.file_index);
-        let schema = vec![
-            ("x", ValueKind::F32, 1),
-            ("y", ValueKind::F32, 1),
-            ("z", ValueKind::F32, 1),
-            ("intensity", ValueKind::U8, 1),
-            ("channel", ValueKind::U8, 1),
-            ("azimuth", ValueKind::U16, 1),
-            ("altitude", ValueKind::I16, 1),
-            ("distance_m", ValueKind::F32, 1),
-            ("timestamp", ValueKind::F64, 1),
-        ];
-        let mut writer: DynWriter<_> = WriterInit {
-            width: self.buffer.len() as u64,
-            height: 1,
-            viewpoint: Default::default(),
-            data_kind: pcd_rs::DataKind::Ascii,
-            schema: Some(Schema::from_iter(schema)),
-        }.create(current_filename).unwrap();
-        for point in self.buffer.iter() {
-            writer.push(point).unwrap();
+    fn write_point<W: Write>(&self, out: &mut W, p: &VeloPoint) -> Result<()> {
+        match self.data_kind {
+            PcdDataKind::Ascii => {
+                writeln!(out, "{} {} {} {} {} {} {} {} {}",
+                    p.x, p.y, p.z, p.intensity, p.channel, p.azimuth, p.altitude, p.distance_m, p.timestamp)?;
+                Ok(())
+            },
+            PcdDataKind::Binary | PcdDataKind::BinaryCompressed => write_binary_row(out, p),
         }
-        writer.finish().unwrap();
-        self.file_index += 1;
     }
 }
 
+impl FrameEncoder for PcdEncoder {
+    fn encode_frame(&self, file_index: u32, points: &PointBatch) -> Result<()> {
+        let filename = self.filename(file_index);
+        match self.data_kind {
+            PcdDataKind::Ascii | PcdDataKind::Binary => {
+                let file = File::create(&filename).with_context(|| format!("failed to open {}", filename))?;
+                let mut out = BufWriter::with_capacity(262144, file);
+                self.write_header(&mut out, points.len())?;
+                for p in points.iter() {
+                    self.write_point(&mut out, &p)?;
+                }
+                out.flush()?;
+            },
+            PcdDataKind::BinaryCompressed => {
+                let mut rows = Vec::with_capacity(points.len() * POINT_SIZE);
+                for p in points.iter() {
+                    write_binary_row(&mut rows, &p)?;
+                }
+                self.write_binary_compressed(&filename, Cursor::new(rows), points.len())?;
+            },
+        }
+        Ok(())
+    }
+}
+
+pub struct PcdWriter {
+    encoder: PcdEncoder,
+    file_index: u32,
+    current: Option<PcdFrame>,
+}
+
+impl PcdWriter {
+    pub fn create(base_dir: String, dir: String, file_prefix: String, data_kind: PcdDataKind) -> Result<PcdWriter> {
+        Ok(PcdWriter {
+            encoder: PcdEncoder::create(base_dir, dir, file_prefix, data_kind)?,
+            file_index: 0,
+            current: None,
+        })
+    }
+}
+
+fn write_binary_row<W: Write>(out: &mut W, p: &VeloPoint) -> Result<()> {
+    out.write_all(&p.x.to_le_bytes())?;
+    out.write_all(&p.y.to_le_bytes())?;
+    out.write_all(&p.z.to_le_bytes())?;
+    out.write_all(&[p.intensity, p.channel])?;
+    out.write_all(&p.azimuth.to_le_bytes())?;
+    out.write_all(&p.altitude.to_le_bytes())?;
+    out.write_all(&p.distance_m.to_le_bytes())?;
+    out.write_all(&p.timestamp.to_le_bytes())?;
+    Ok(())
+}
+
 impl FrameWriter for PcdWriter {
-    fn write_row(&mut self, row: VeloPoint) {
-        self.buffer.push(DynRecord(vec![
-            Field::F32(vec![row.x]),
-            Field::F32(vec![row.y]),
-            Field::F32(vec![row.z]),
-            Field::U8(vec![row.intensity]),
-            Field::U8(vec![row.channel]),
-            Field::U16(vec![row.azimuth]),
-            Field::I16(vec![row.altitude]),
-            Field::F32(vec![row.distance_m]),
-            Field::F64(vec![row.timestamp as f64]),
-        ]));
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        if self.current.is_none() {
+            self.current = Some(self.encoder.open_frame(self.file_index)?);
+        }
+        let frame = self.current.as_mut().unwrap();
+        self.encoder.write_point(&mut frame.out, &row)?;
+        frame.points_num += 1;
+        Ok(())
     }
 
-    fn split_frame(&mut self) { 
-        if self.buffer.len() > 0 {
-            self.write_to_file();
-            self.buffer.clear();
+    fn split_frame(&mut self) -> Result<()> {
+        if let Some(frame) = self.current.take() {
+            self.encoder.finish_frame(frame)?;
+            self.file_index += 1;
         }
+        Ok(())
     }
 
-    fn write_attribute(&mut self, _laser_num: u32, _frequency: f32, _return_mode: u32, _manufacturer: &str, _model: &str) { }
-}
\ No newline at end of file
+    fn write_attribute(&mut self, _metadata: &SensorMetadata) -> Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+
+use anyhow::{Context, Result};
+
+use crate::{velopoint::VeloPoint, pointbatch::PointBatch, sensormetadata::SensorMetadata, framewriter::{FrameEncoder, FrameWriter}};
+
+/// Writes the file of a frame. Shared by `PlyWriter` and `ParallelWriter`.
+pub struct PlyEncoder {
+    base_dir: String,
+    dir: String,
+    file_prefix: String,
+}
+
+impl PlyEncoder {
+    pub fn create(base_dir: String, dir: String, file_prefix: String) -> Result<PlyEncoder> {
+        let out_dir = format!("{}/{}", base_dir, dir);
+        fs::create_dir(&out_dir).with_context(|| format!("failed to create output directory {}", out_dir))?;
+        Ok(PlyEncoder {
+            base_dir,
+            dir,
+            file_prefix,
+        })
+    }
+
+    fn write_header<W: Write>(&self, out: &mut W, points_num: usize) -> Result<()> {
+        writeln!(out, "ply")?;
+        writeln!(out, "format binary_little_endian 1.0")?;
+        writeln!(out, "element vertex {}", points_num)?;
+        writeln!(out, "property float x")?;
+        writeln!(out, "property float y")?;
+        writeln!(out, "property float z")?;
+        writeln!(out, "property uchar intensity")?;
+        writeln!(out, "property uchar channel")?;
+        writeln!(out, "property ushort azimuth")?;
+        writeln!(out, "property short altitude")?;
+        writeln!(out, "property float distance_m")?;
+        // PLY has no 64-bit integer type, so the nanoseconds are split into seconds and the rest
+        writeln!(out, "property uint timestamp_sec")?;
+        writeln!(out, "property uint timestamp_nsec")?;
+        writeln!(out, "end_header")?;
+        Ok(())
+    }
+}
+
+impl FrameEncoder for PlyEncoder {
+    fn encode_frame(&self, file_index: u32, points: &PointBatch) -> Result<()> {
+        let current_filename = format!("{0}/{1}/{2}_{3:>04}.ply", self.base_dir, self.dir, self.file_prefix, file_index);
+        let file = File::create(&current_filename).with_context(|| format!("failed to open {}", current_filename))?;
+        let mut out = BufWriter::with_capacity(262144, file);
+        self.write_header(&mut out, points.len())?;
+        for p in points.iter() {
+            out.write_all(&p.x.to_le_bytes())?;
+            out.write_all(&p.y.to_le_bytes())?;
+            out.write_all(&p.z.to_le_bytes())?;
+            out.write_all(&[p.intensity, p.channel])?;
+            out.write_all(&p.azimuth.to_le_bytes())?;
+            out.write_all(&p.altitude.to_le_bytes())?;
+            out.write_all(&p.distance_m.to_le_bytes())?;
+            out.write_all(&((p.timestamp / 1_000_000_000) as u32).to_le_bytes())?;
+            out.write_all(&((p.timestamp % 1_000_000_000) as u32).to_le_bytes())?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+}
+
+pub struct PlyWriter {
+    encoder: PlyEncoder,
+    file_index: u32,
+    buffer: PointBatch,
+}
+
+impl PlyWriter {
+    pub fn create(base_dir: String, dir: String, file_prefix: String) -> Result<PlyWriter> {
+        Ok(PlyWriter {
+            encoder: PlyEncoder::create(base_dir, dir, file_prefix)?,
+            file_index: 0,
+            buffer: PointBatch::new(),
+        })
+    }
+}
+
+impl FrameWriter for PlyWriter {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row, 0);
+        Ok(())
+    }
+
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        self.buffer.extend(batch);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.encoder.encode_frame(self.file_index, &self.buffer)?;
+            self.file_index += 1;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, _metadata: &SensorMetadata) -> Result<()> {
+        Ok(())
+    }
+}
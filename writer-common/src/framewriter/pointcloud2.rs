@@ -0,0 +1,48 @@
+// sensor_msgs/PointCloud2 point layout shared by the ROS writers
+
+use crate::velopoint::VeloPoint;
+
+pub(crate) const UINT8: u8 = 2;
+pub(crate) const INT16: u8 = 3;
+pub(crate) const UINT16: u8 = 4;
+pub(crate) const FLOAT32: u8 = 7;
+pub(crate) const FLOAT64: u8 = 8;
+
+/// (name, offset, datatype). `ring` aliases `channel` for tools that expect the velodyne driver layout.
+/// PointCloud2 has no 64-bit integer type, so `timestamp` is stored as float64 seconds.
+pub(crate) const POINT_FIELDS: [(&str, u32, u8); 10] = [
+    ("x", 0, FLOAT32),
+    ("y", 4, FLOAT32),
+    ("z", 8, FLOAT32),
+    ("intensity", 12, UINT8),
+    ("channel", 13, UINT8),
+    ("ring", 13, UINT8),
+    ("azimuth", 14, UINT16),
+    ("altitude", 16, INT16),
+    ("distance_m", 20, FLOAT32),
+    ("timestamp", 24, FLOAT64),
+];
+
+pub(crate) const POINT_STEP: u32 = 32;
+
+/// Packs points into the little endian `data` array of a PointCloud2 message.
+pub(crate) fn pack_points(points: &[VeloPoint]) -> Vec<u8> {
+    let mut data = vec![0u8; points.len() * POINT_STEP as usize];
+    for (p, d) in points.iter().zip(data.chunks_exact_mut(POINT_STEP as usize)) {
+        d[0..4].copy_from_slice(&p.x.to_le_bytes());
+        d[4..8].copy_from_slice(&p.y.to_le_bytes());
+        d[8..12].copy_from_slice(&p.z.to_le_bytes());
+        d[12] = p.intensity;
+        d[13] = p.channel;
+        d[14..16].copy_from_slice(&p.azimuth.to_le_bytes());
+        d[16..18].copy_from_slice(&p.altitude.to_le_bytes());
+        d[20..24].copy_from_slice(&p.distance_m.to_le_bytes());
+        d[24..32].copy_from_slice(&(p.timestamp as f64 / 1e9).to_le_bytes());
+    }
+    data
+}
+
+/// Splits nanoseconds into ROS time (sec, nsec).
+pub(crate) fn to_ros_time(timestamp_ns: u64) -> (u32, u32) {
+    ((timestamp_ns / 1_000_000_000) as u32, (timestamp_ns % 1_000_000_000) as u32)
+}
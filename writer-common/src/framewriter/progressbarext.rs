@@ -2,6 +2,7 @@ use indicatif::ProgressBar;
 
 pub trait ProgressBarExt {
     fn new_frame_progress_bar(frame_num: u64) -> ProgressBar;
+    fn new_bytes_progress_bar(total_bytes: u64) -> ProgressBar;
 }
 
 impl ProgressBarExt for ProgressBar {
@@ -13,4 +14,13 @@ impl ProgressBarExt for ProgressBar {
         );
         progress_bar
     }
+
+    fn new_bytes_progress_bar(total_bytes: u64) -> ProgressBar {
+        let progress_bar = ProgressBar::new(total_bytes);
+        progress_bar.set_style(
+            indicatif::ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] {bar:40.cyan/blue} {bytes:>10}/{total_bytes:10} {msg}").unwrap(),
+        );
+        progress_bar
+    }
 }
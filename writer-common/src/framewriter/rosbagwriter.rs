@@ -0,0 +1,351 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+
+use anyhow::{Context, Result};
+
+use crate::{velopoint::VeloPoint, sensormetadata::SensorMetadata, framewriter::FrameWriter};
+use crate::framewriter::pointcloud2::{pack_points, to_ros_time, POINT_FIELDS, POINT_STEP};
+
+const BAG_MAGIC: &[u8] = b"#ROSBAG V2.0\n";
+/// the bag header record is padded to this size so that it can be rewritten in place
+const BAG_HEADER_LEN: usize = 4096;
+
+const OP_MSG_DATA: u8 = 0x02;
+const OP_BAG_HEADER: u8 = 0x03;
+const OP_INDEX_DATA: u8 = 0x04;
+const OP_CHUNK: u8 = 0x05;
+const OP_CHUNK_INFO: u8 = 0x06;
+const OP_CONNECTION: u8 = 0x07;
+
+const CONNECTION_ID: u32 = 0;
+
+const POINTCLOUD2_MD5: &str = "1158d486dd51d683ce2f1be655c3c181";
+const POINTCLOUD2_DEFINITION: &str = "\
+std_msgs/Header header
+uint32 height
+uint32 width
+sensor_msgs/PointField[] fields
+bool    is_bigendian
+uint32  point_step
+uint32  row_step
+uint8[] data
+bool is_dense
+
+================================================================================
+MSG: std_msgs/Header
+uint32 seq
+time stamp
+string frame_id
+
+================================================================================
+MSG: sensor_msgs/PointField
+uint8 INT8    = 1
+uint8 UINT8   = 2
+uint8 INT16   = 3
+uint8 UINT16  = 4
+uint8 INT32   = 5
+uint8 UINT32  = 6
+uint8 FLOAT32 = 7
+uint8 FLOAT64 = 8
+
+string name
+uint32 offset
+uint8  datatype
+uint32 count
+";
+
+struct ChunkInfo {
+    pos: u64,
+    time: (u32, u32),
+}
+
+fn put_field(header: &mut Vec<u8>, name: &str, value: &[u8]) {
+    header.extend_from_slice(&((name.len() + 1 + value.len()) as u32).to_le_bytes());
+    header.extend_from_slice(name.as_bytes());
+    header.push(b'=');
+    header.extend_from_slice(value);
+}
+
+fn time_bytes(time: (u32, u32)) -> [u8; 8] {
+    let mut bytes = [0u8; 8];
+    bytes[0..4].copy_from_slice(&time.0.to_le_bytes());
+    bytes[4..8].copy_from_slice(&time.1.to_le_bytes());
+    bytes
+}
+
+fn put_record(buf: &mut Vec<u8>, header: &[u8], data: &[u8]) {
+    buf.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    buf.extend_from_slice(header);
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+}
+
+/// Writes a ROS 1 bag (format 2.0) with one `sensor_msgs/PointCloud2` message per frame.
+/// Each frame is stored in its own uncompressed chunk.
+pub struct RosbagWriter {
+    out: BufWriter<File>,
+    pos: u64,
+    topic: String,
+    frame_id: String,
+    seq: u32,
+    chunks: Vec<ChunkInfo>,
+    buffer: Vec<VeloPoint>,
+}
+
+impl RosbagWriter {
+    pub fn create(base_dir: String, filename: String, topic: String, frame_id: String) -> Result<RosbagWriter> {
+        let filename = format!("{0}/{1}.bag", base_dir, filename);
+        let file = File::create(&filename).with_context(|| format!("failed to create {}", filename))?;
+        let mut writer = RosbagWriter {
+            out: BufWriter::with_capacity(262144, file),
+            pos: 0,
+            topic,
+            frame_id,
+            seq: 0,
+            chunks: Vec::new(),
+            buffer: Vec::new(),
+        };
+        writer.write(BAG_MAGIC)?;
+        // placeholder, rewritten in finalize
+        let bag_header = writer.bag_header_record(0);
+        writer.write(&bag_header)?;
+        Ok(writer)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        self.pos += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn bag_header_record(&self, index_pos: u64) -> Vec<u8> {
+        let conn_count: u32 = if self.chunks.is_empty() { 0 } else { 1 };
+        let mut header = Vec::new();
+        put_field(&mut header, "op", &[OP_BAG_HEADER]);
+        put_field(&mut header, "index_pos", &index_pos.to_le_bytes());
+        put_field(&mut header, "conn_count", &conn_count.to_le_bytes());
+        put_field(&mut header, "chunk_count", &(self.chunks.len() as u32).to_le_bytes());
+        let padding = vec![b' '; BAG_HEADER_LEN - 8 - header.len()];
+        let mut record = Vec::with_capacity(BAG_HEADER_LEN);
+        put_record(&mut record, &header, &padding);
+        record
+    }
+
+    fn connection_record(&self) -> Vec<u8> {
+        let mut header = Vec::new();
+        put_field(&mut header, "op", &[OP_CONNECTION]);
+        put_field(&mut header, "topic", self.topic.as_bytes());
+        put_field(&mut header, "conn", &CONNECTION_ID.to_le_bytes());
+        let mut data = Vec::new();
+        put_field(&mut data, "topic", self.topic.as_bytes());
+        put_field(&mut data, "type", b"sensor_msgs/PointCloud2");
+        put_field(&mut data, "md5sum", POINTCLOUD2_MD5.as_bytes());
+        put_field(&mut data, "message_definition", POINTCLOUD2_DEFINITION.as_bytes());
+        let mut record = Vec::new();
+        put_record(&mut record, &header, &data);
+        record
+    }
+
+    /// Serializes the buffered frame as a ROS 1 `sensor_msgs/PointCloud2`.
+    fn serialize_message(&self, stamp: (u32, u32)) -> Vec<u8> {
+        let data = pack_points(&self.buffer);
+        let mut msg = Vec::with_capacity(data.len() + 512);
+        // header
+        msg.extend_from_slice(&self.seq.to_le_bytes());
+        msg.extend_from_slice(&time_bytes(stamp));
+        put_string(&mut msg, &self.frame_id);
+        // height, width
+        msg.extend_from_slice(&1u32.to_le_bytes());
+        msg.extend_from_slice(&(self.buffer.len() as u32).to_le_bytes());
+        msg.extend_from_slice(&(POINT_FIELDS.len() as u32).to_le_bytes());
+        for (name, offset, datatype) in POINT_FIELDS {
+            put_string(&mut msg, name);
+            msg.extend_from_slice(&offset.to_le_bytes());
+            msg.push(datatype);
+            msg.extend_from_slice(&1u32.to_le_bytes());
+        }
+        msg.push(0); // is_bigendian
+        msg.extend_from_slice(&POINT_STEP.to_le_bytes());
+        msg.extend_from_slice(&(data.len() as u32).to_le_bytes()); // row_step
+        msg.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        msg.extend_from_slice(&data);
+        msg.push(1); // is_dense
+        msg
+    }
+
+    fn write_to_file(&mut self) -> Result<()> {
+        let stamp = to_ros_time(self.buffer[0].timestamp);
+
+        let mut chunk_data = Vec::new();
+        if self.chunks.is_empty() {
+            chunk_data.extend(self.connection_record());
+        }
+        let msg_offset = chunk_data.len() as u32;
+        let mut header = Vec::new();
+        put_field(&mut header, "op", &[OP_MSG_DATA]);
+        put_field(&mut header, "conn", &CONNECTION_ID.to_le_bytes());
+        put_field(&mut header, "time", &time_bytes(stamp));
+        put_record(&mut chunk_data, &header, &self.serialize_message(stamp));
+
+        let chunk_pos = self.pos;
+        let mut header = Vec::new();
+        put_field(&mut header, "op", &[OP_CHUNK]);
+        put_field(&mut header, "compression", b"none");
+        put_field(&mut header, "size", &(chunk_data.len() as u32).to_le_bytes());
+        let mut record = Vec::new();
+        put_record(&mut record, &header, &chunk_data);
+        self.write(&record)?;
+
+        let mut header = Vec::new();
+        put_field(&mut header, "op", &[OP_INDEX_DATA]);
+        put_field(&mut header, "ver", &1u32.to_le_bytes());
+        put_field(&mut header, "conn", &CONNECTION_ID.to_le_bytes());
+        put_field(&mut header, "count", &1u32.to_le_bytes());
+        let mut index = time_bytes(stamp).to_vec();
+        index.extend_from_slice(&msg_offset.to_le_bytes());
+        let mut record = Vec::new();
+        put_record(&mut record, &header, &index);
+        self.write(&record)?;
+
+        self.chunks.push(ChunkInfo { pos: chunk_pos, time: stamp });
+        self.seq += 1;
+        Ok(())
+    }
+}
+
+impl FrameWriter for RosbagWriter {
+    fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+        self.buffer.push(row);
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        if !self.buffer.is_empty() {
+            self.write_to_file()?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+
+    fn write_attribute(&mut self, _metadata: &SensorMetadata) -> Result<()> {
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let index_pos = self.pos;
+        let mut records = Vec::new();
+        if !self.chunks.is_empty() {
+            records.extend(self.connection_record());
+        }
+        for chunk in self.chunks.iter() {
+            let mut header = Vec::new();
+            put_field(&mut header, "op", &[OP_CHUNK_INFO]);
+            put_field(&mut header, "ver", &1u32.to_le_bytes());
+            put_field(&mut header, "chunk_pos", &chunk.pos.to_le_bytes());
+            put_field(&mut header, "start_time", &time_bytes(chunk.time));
+            put_field(&mut header, "end_time", &time_bytes(chunk.time));
+            put_field(&mut header, "count", &1u32.to_le_bytes());
+            let mut data = CONNECTION_ID.to_le_bytes().to_vec();
+            data.extend_from_slice(&1u32.to_le_bytes());
+            put_record(&mut records, &header, &data);
+        }
+        self.write(&records)?;
+
+        let bag_header = self.bag_header_record(index_pos);
+        self.out.seek(SeekFrom::Start(BAG_MAGIC.len() as u64))?;
+        self.out.write_all(&bag_header)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::testutil::{point, temp_dir};
+
+    fn u32_at(buf: &[u8], pos: usize) -> usize {
+        u32::from_le_bytes(buf[pos..pos + 4].try_into().unwrap()) as usize
+    }
+
+    /// Parses `name=value` fields stored back to back.
+    fn read_fields(mut buf: &[u8]) -> HashMap<String, Vec<u8>> {
+        let mut fields = HashMap::new();
+        while !buf.is_empty() {
+            let field = &buf[4..4 + u32_at(buf, 0)];
+            let eq = field.iter().position(|&b| b == b'=').unwrap();
+            fields.insert(String::from_utf8(field[..eq].to_vec()).unwrap(), field[eq + 1..].to_vec());
+            buf = &buf[4 + field.len()..];
+        }
+        fields
+    }
+
+    /// Reads the record at `pos`. Returns the header fields, the data and the next position.
+    fn read_record(bag: &[u8], pos: usize) -> (HashMap<String, Vec<u8>>, &[u8], usize) {
+        let header_end = pos + 4 + u32_at(bag, pos);
+        let data_end = header_end + 4 + u32_at(bag, header_end);
+        (read_fields(&bag[pos + 4..header_end]), &bag[header_end + 4..data_end], data_end)
+    }
+
+    fn u32_field(fields: &HashMap<String, Vec<u8>>, name: &str) -> u32 {
+        u32::from_le_bytes(fields[name][..].try_into().unwrap())
+    }
+
+    #[test]
+    fn bag_header_and_index() {
+        let dir = temp_dir("rosbag");
+        let mut writer = RosbagWriter::create(dir.clone(), "test".to_string(), "/points".to_string(), "lidar".to_string()).unwrap();
+        for frame in 0..2 {
+            for i in 0..10 {
+                writer.write_row(point(frame * 10 + i)).unwrap();
+            }
+            writer.split_frame().unwrap();
+        }
+        writer.finalize().unwrap();
+        drop(writer);
+        let bag = std::fs::read(format!("{}/test.bag", dir)).unwrap();
+
+        assert_eq!(&bag[..BAG_MAGIC.len()], b"#ROSBAG V2.0\n");
+        let (fields, _, next) = read_record(&bag, BAG_MAGIC.len());
+        assert_eq!(next, BAG_MAGIC.len() + BAG_HEADER_LEN);
+        assert_eq!(fields["op"], [OP_BAG_HEADER]);
+        assert_eq!(u32_field(&fields, "conn_count"), 1);
+        assert_eq!(u32_field(&fields, "chunk_count"), 2);
+        let index_pos = u64::from_le_bytes(fields["index_pos"][..].try_into().unwrap()) as usize;
+
+        let (fields, data, mut pos) = read_record(&bag, index_pos);
+        assert_eq!(fields["op"], [OP_CONNECTION]);
+        assert_eq!(fields["topic"], b"/points");
+        let connection = read_fields(data);
+        assert_eq!(connection["type"], b"sensor_msgs/PointCloud2");
+        assert_eq!(connection["md5sum"], POINTCLOUD2_MD5.as_bytes());
+
+        for frame in 0..2 {
+            let (fields, _, next) = read_record(&bag, pos);
+            pos = next;
+            assert_eq!(fields["op"], [OP_CHUNK_INFO]);
+            assert_eq!(u32_field(&fields, "count"), 1);
+            let chunk_pos = u64::from_le_bytes(fields["chunk_pos"][..].try_into().unwrap()) as usize;
+            let (chunk, chunk_data, _) = read_record(&bag, chunk_pos);
+            assert_eq!(chunk["op"], [OP_CHUNK]);
+            assert_eq!(chunk["compression"], b"none");
+            assert_eq!(u32_field(&chunk, "size") as usize, chunk_data.len());
+            // the connection record is stored in the first chunk only
+            let mut message_pos = 0;
+            if frame == 0 {
+                message_pos = read_record(chunk_data, 0).2;
+            }
+            let (message, body, _) = read_record(chunk_data, message_pos);
+            assert_eq!(message["op"], [OP_MSG_DATA]);
+            assert_eq!(u32::from_le_bytes(body[0..4].try_into().unwrap()), frame); // seq
+        }
+        assert_eq!(pos, bag.len());
+    }
+}
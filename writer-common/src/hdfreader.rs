@@ -0,0 +1,231 @@
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+
+use anyhow::{anyhow, ensure, Context, Result};
+use hdf5::types::VarLenUnicode;
+use hdf5::{Dataset, File, H5Type};
+
+use crate::framewriter::HdfLayout;
+use crate::sensormetadata::{Calibration, SensorMetadata, SourceFile};
+use crate::velopoint::{FrameIndex, VeloPoint};
+
+/// Root attributes of a file written by `HdfWriter`.
+#[derive(Clone, Debug)]
+pub struct HdfAttributes {
+    pub layout: HdfLayout,
+    /// `output format version`. `None` for files written before v1.1
+    pub output_format_version: Option<String>,
+    pub metadata: SensorMetadata,
+}
+
+enum FrameSource {
+    Frames { count: usize },
+    Table { points: Dataset, frames: Vec<FrameIndex> },
+}
+
+/// Reads frames back from a `.h5` file written by `HdfWriter`, in either layout.
+pub struct HdfReader {
+    file: File,
+    attributes: HdfAttributes,
+    source: FrameSource,
+}
+
+impl HdfReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<HdfReader> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let attributes = read_attributes(&file)?;
+        let source = match attributes.layout {
+            HdfLayout::Frames => {
+                // empty frames are not written, so the dataset names are contiguous
+                let mut count = 0;
+                while file.link_exists(&frame_dataset_name(count)) {
+                    count += 1;
+                }
+                FrameSource::Frames { count }
+            },
+            HdfLayout::Table => FrameSource::Table {
+                points: file.dataset("points")?,
+                frames: file.dataset("frames")?.read_raw::<FrameIndex>()?,
+            },
+        };
+        Ok(HdfReader { file, attributes, source })
+    }
+
+    pub fn attributes(&self) -> &HdfAttributes {
+        &self.attributes
+    }
+
+    pub fn frame_count(&self) -> usize {
+        match &self.source {
+            FrameSource::Frames { count } => *count,
+            FrameSource::Table { frames, .. } => frames.len(),
+        }
+    }
+
+    pub fn frame(&self, index: usize) -> Result<Vec<VeloPoint>> {
+        ensure!(index < self.frame_count(), "frame {} is out of range (0..{})", index, self.frame_count());
+        match &self.source {
+            FrameSource::Frames { .. } => {
+                Ok(self.file.dataset(&frame_dataset_name(index))?.read_raw::<VeloPoint>()?)
+            },
+            FrameSource::Table { points, frames } => {
+                let start = frames[index].start as usize;
+                let end = start + frames[index].count as usize;
+                Ok(points.read_slice_1d::<VeloPoint, _>(start..end)?.to_vec())
+            },
+        }
+    }
+
+    /// Reads the frames one by one in order.
+    pub fn frames(&self) -> impl Iterator<Item = Result<Vec<VeloPoint>>> + '_ {
+        (0..self.frame_count()).map(move |index| self.frame(index))
+    }
+}
+
+fn frame_dataset_name(index: usize) -> String {
+    format!("frame{:0>5}", index)
+}
+
+fn read_attributes(file: &File) -> Result<HdfAttributes> {
+    let names = file.attr_names()?;
+    let has = |name: &str| names.iter().any(|n| n == name);
+
+    // files written before the table layout have no layout attribute
+    let layout = if has("layout") {
+        read_str(file, "layout")?.parse()?
+    } else {
+        HdfLayout::Frames
+    };
+
+    // the sensor attributes are missing when the parser could not determine them
+    let mut metadata = SensorMetadata::default();
+    if has("manufacturer") {
+        metadata.manufacturer = read_str(file, "manufacturer")?;
+        metadata.model = read_str(file, "model")?;
+        metadata.laser_num = read_value(file, "laser number")?;
+        metadata.frequency = read_value(file, "frequency")?;
+        metadata.return_mode = read_value(file, "return mode")?;
+    }
+
+    let output_format_version = if has("output format version") {
+        Some(read_str(file, "output format version")?)
+    } else {
+        None
+    };
+    if has("parser name") {
+        metadata.parser_name = read_str(file, "parser name")?;
+        metadata.parser_version = read_str(file, "parser version")?;
+    }
+    if has("split strategy") {
+        metadata.split_strategy = read_str(file, "split strategy")?;
+    }
+    if has("serial") {
+        metadata.serial = Some(read_str(file, "serial")?);
+    }
+    if has("source ip") {
+        let ip: IpAddr = read_str(file, "source ip")?.parse()?;
+        let port = read_value::<u32>(file, "source port")?;
+        metadata.source_address = Some(SocketAddr::new(ip, port as u16));
+    }
+    if has("source file name") {
+        metadata.source_file = Some(SourceFile {
+            name: read_str(file, "source file name")?,
+            size: read_value(file, "source file size")?,
+            sha256: if has("source file sha256") { Some(read_str(file, "source file sha256")?) } else { None },
+        });
+    }
+    if has("elevation") {
+        metadata.calibration = Some(Calibration {
+            elevation: file.attr("elevation")?.read_raw::<f32>()?,
+            azimuth_offset: file.attr("azimuth offset")?.read_raw::<f32>()?,
+        });
+    }
+
+    Ok(HdfAttributes { layout, output_format_version, metadata })
+}
+
+/// Attributes are written as 1 element arrays.
+fn read_value<T: H5Type + Copy>(file: &File, name: &str) -> Result<T> {
+    let values = file.attr(name)?.read_raw::<T>()?;
+    values.first().copied().ok_or_else(|| anyhow!("attribute {} is empty", name))
+}
+
+fn read_str(file: &File, name: &str) -> Result<String> {
+    let values = file.attr(name)?.read_raw::<VarLenUnicode>()?;
+    values.first().map(|v| v.as_str().to_string()).ok_or_else(|| anyhow!("attribute {} is empty", name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{point, temp_dir};
+    use crate::framewriter::{FrameWriter, HdfWriter};
+    use crate::pointbatch::PointBatch;
+
+    /// the third frame is larger than the write buffer, so it is flushed in pieces
+    const FRAME_SIZES: [u32; 4] = [5, 0, 70_000, 3];
+
+    fn metadata() -> SensorMetadata {
+        let mut metadata = SensorMetadata::new("Velodyne", "VLP-16");
+        metadata.laser_num = 16;
+        metadata.frequency = 10.0;
+        metadata.return_mode = 2;
+        metadata.serial = Some("1234".to_string());
+        metadata.source_address = Some("192.168.1.201:2368".parse().unwrap());
+        metadata.calibration = Some(Calibration { elevation: vec![-15.0, 1.0], azimuth_offset: vec![0.0, 0.5] });
+        metadata.parser_name = "velo-pcap-parser-rs".to_string();
+        metadata.parser_version = "0.1.0".to_string();
+        metadata.source_file = Some(SourceFile { name: "a.pcap".to_string(), size: 42, sha256: None });
+        metadata.split_strategy = "slope".to_string();
+        metadata
+    }
+
+    fn round_trip(layout: HdfLayout) {
+        let dir = temp_dir(&format!("hdf-{}", layout.name()));
+        let mut writer = HdfWriter::create(dir.clone(), "test".to_string(), true, layout).unwrap();
+        writer.write_attribute(&metadata()).unwrap();
+        let mut frames = Vec::new();
+        let mut n = 0;
+        for size in FRAME_SIZES {
+            let points: Vec<VeloPoint> = (n..n + size).map(point).collect();
+            n += size;
+            // the first half row by row, the rest as a batch
+            let half = points.len() / 2;
+            for p in points[..half].iter() {
+                writer.write_row(p.clone()).unwrap();
+            }
+            let mut batch = PointBatch::new();
+            for p in points[half..].iter() {
+                batch.push(p.clone(), 0);
+            }
+            writer.write_batch(&batch).unwrap();
+            writer.split_frame().unwrap();
+            if !points.is_empty() {
+                frames.push(points);
+            }
+        }
+        writer.finalize().unwrap();
+        drop(writer);
+
+        let reader = HdfReader::open(format!("{}/test.h5", dir)).unwrap();
+        assert_eq!(reader.attributes().layout.name(), layout.name());
+        assert_eq!(reader.attributes().output_format_version.as_deref(), Some(crate::sensormetadata::OUTPUT_FORMAT_VERSION));
+        assert_eq!(reader.attributes().metadata, metadata());
+        assert_eq!(reader.frame_count(), frames.len());
+        for (read, written) in reader.frames().zip(frames.iter()) {
+            assert_eq!(&read.unwrap(), written);
+        }
+        assert!(reader.frame(frames.len()).is_err());
+    }
+
+    #[test]
+    fn round_trip_frames_layout() {
+        round_trip(HdfLayout::Frames);
+    }
+
+    #[test]
+    fn round_trip_table_layout() {
+        round_trip(HdfLayout::Table);
+    }
+}
@@ -0,0 +1,189 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+use std::rc::Rc;
+
+use anyhow::{Context, Result};
+use flate2::bufread::MultiGzDecoder;
+use xz2::bufread::XzDecoder;
+
+const READ_BUFFER_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// Detects the compression from the first bytes of a file.
+    pub fn detect(magic: &[u8]) -> Compression {
+        if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else if magic.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Strips the `.gz`, `.zst` or `.xz` extension: `hoge.pcap.zst` -> `hoge.pcap`.
+pub fn strip_compression_extension(path: &str) -> &str {
+    [".gz", ".zst", ".xz"].iter()
+        .find_map(|extension| path.strip_suffix(extension))
+        .unwrap_or(path)
+}
+
+/// Counts the bytes read from the file, before decompression.
+struct CountingReader {
+    file: File,
+    position: Rc<Cell<u64>>,
+}
+
+impl Read for CountingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = self.file.read(buf)?;
+        self.position.set(self.position.get() + len as u64);
+        Ok(len)
+    }
+}
+
+/// A file read sequentially, decompressed on the fly when it is gzip, zstd or xz compressed.
+/// The compression is detected from the content, not from the extension.
+pub struct InputFile {
+    reader: Box<dyn BufRead>,
+    compression: Compression,
+    len: u64,
+    position: Rc<Cell<u64>>,
+}
+
+impl InputFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<InputFile> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+        let len = file.metadata()?.len();
+        let position = Rc::new(Cell::new(0));
+        let mut file = BufReader::with_capacity(READ_BUFFER_SIZE, CountingReader { file, position: position.clone() });
+        let compression = Compression::detect(file.fill_buf()?);
+        let reader: Box<dyn BufRead> = match compression {
+            Compression::None => Box::new(file),
+            Compression::Gzip => Box::new(BufReader::with_capacity(READ_BUFFER_SIZE, MultiGzDecoder::new(file))),
+            Compression::Zstd => Box::new(BufReader::with_capacity(READ_BUFFER_SIZE, zstd::Decoder::with_buffer(file)?)),
+            Compression::Xz => Box::new(BufReader::with_capacity(READ_BUFFER_SIZE, XzDecoder::new_multi_decoder(file))),
+        };
+        Ok(InputFile { reader, compression, len, position })
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    /// Size of the file on disk.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Bytes of the file read so far, for progress reporting. Compressed files are read ahead of
+    /// the decompressed data by up to a buffer.
+    pub fn position(&self) -> u64 {
+        self.position.get()
+    }
+}
+
+impl Read for InputFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+impl BufRead for InputFile {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.reader.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.reader.consume(amt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use xz2::write::XzEncoder;
+
+    use super::*;
+    use crate::testutil::temp_dir;
+
+    fn content() -> Vec<u8> {
+        (0..100_000u32).flat_map(|i| (i % 251).to_le_bytes()).collect()
+    }
+
+    fn compress(compression: Compression, data: &[u8]) -> Vec<u8> {
+        match compression {
+            Compression::None => data.to_vec(),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            },
+            Compression::Zstd => zstd::encode_all(data, 0).unwrap(),
+            Compression::Xz => {
+                let mut encoder = XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            },
+        }
+    }
+
+    #[test]
+    fn compressed_input_round_trip() {
+        let dir = temp_dir("inputfile");
+        let data = content();
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd, Compression::Xz] {
+            let bytes = compress(compression, &data);
+            assert_eq!(Compression::detect(&bytes), compression);
+            // the extension does not matter
+            let path = format!("{}/{:?}.pcap", dir, compression);
+            std::fs::write(&path, &bytes).unwrap();
+
+            let mut input = InputFile::open(&path).unwrap();
+            assert_eq!(input.compression(), compression);
+            assert_eq!(input.len(), bytes.len() as u64);
+            let mut read = Vec::new();
+            input.read_to_end(&mut read).unwrap();
+            assert!(read == data, "{:?} content differs", compression);
+            assert_eq!(input.position(), bytes.len() as u64);
+        }
+    }
+
+    #[test]
+    fn concatenated_gzip_members_are_read() {
+        let mut bytes = compress(Compression::Gzip, b"first ");
+        bytes.extend(compress(Compression::Gzip, b"second"));
+        let path = format!("{}/multi.pcap.gz", temp_dir("inputfile-multi"));
+        std::fs::write(&path, &bytes).unwrap();
+        let mut read = String::new();
+        InputFile::open(&path).unwrap().read_to_string(&mut read).unwrap();
+        assert_eq!(read, "first second");
+    }
+
+    #[test]
+    fn compression_extension_is_stripped() {
+        assert_eq!(strip_compression_extension("dir/hoge.pcap.gz"), "dir/hoge.pcap");
+        assert_eq!(strip_compression_extension("hoge.pcap.zst"), "hoge.pcap");
+        assert_eq!(strip_compression_extension("hoge.pcap.xz"), "hoge.pcap");
+        assert_eq!(strip_compression_extension("hoge.pcap"), "hoge.pcap");
+    }
+}
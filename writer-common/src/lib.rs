@@ -1,6 +1,18 @@
 
 pub mod velopoint;
+pub mod capture;
+pub mod convertargs;
 pub mod framewriter;
-pub mod azimuthsplitwriter;
-pub mod timesplitwriter;
-pub mod valueslopesplitwriter;
\ No newline at end of file
+pub mod hdfreader;
+pub mod inputfile;
+pub mod framesplitter;
+pub mod packetfilter;
+pub mod splittingwriter;
+pub mod outputoptions;
+pub mod paralleldecoder;
+pub mod pointbatch;
+pub mod sensormetadata;
+pub mod udpdecoder;
+
+#[cfg(test)]
+mod testutil;
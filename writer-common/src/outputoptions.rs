@@ -0,0 +1,204 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, ensure, Error, Result};
+use getopts::{Matches, Options};
+
+use crate::inputfile::strip_compression_extension;
+use crate::framewriter::{ArrowIpcWriter, CsvColumn, CsvCompression, CsvDelimiter, CsvEncoder, CsvOptions, CsvWriter, E57Writer, FrameWriter, HdfLayout, HdfWriter, KittiWriter, LasOptions, LasWriter, McapEncoding, McapWriter, NpyColumn, NpyWriter, ParallelWriter, ParquetWriter, PcdDataKind, PcdEncoder, PcdWriter, PlyEncoder, PlyWriter, RosbagWriter};
+
+#[derive(Clone, Copy, Debug)]
+pub enum OutType {
+    Csv,
+    Hdf,
+    Pcd,
+    Ply,
+    Las,
+    Laz,
+    Parquet,
+    Arrow,
+    Npy,
+    Npz,
+    Kitti,
+    Bag,
+    Mcap,
+    E57,
+}
+
+impl FromStr for OutType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(OutType::Csv),
+            "hdf" => Ok(OutType::Hdf),
+            "pcd" => Ok(OutType::Pcd),
+            "ply" => Ok(OutType::Ply),
+            "las" => Ok(OutType::Las),
+            "laz" => Ok(OutType::Laz),
+            "parquet" => Ok(OutType::Parquet),
+            "arrow" => Ok(OutType::Arrow),
+            "npy" => Ok(OutType::Npy),
+            "npz" => Ok(OutType::Npz),
+            "kitti" => Ok(OutType::Kitti),
+            "bag" => Ok(OutType::Bag),
+            "mcap" => Ok(OutType::Mcap),
+            "e57" => Ok(OutType::E57),
+            _ => Err(anyhow!("unknown output type: {}", s)),
+        }
+    }
+}
+
+/// Output related command line options shared by all parsers.
+pub struct OutputOptions {
+    pub out_type: OutType,
+    pub compression: bool,
+    pub csv: CsvOptions,
+    pub hdf_layout: HdfLayout,
+    pub pcd_data_kind: PcdDataKind,
+    pub las: LasOptions,
+    pub row_group_frames: u32,
+    pub npy_columns: Vec<NpyColumn>,
+    pub kitti_x_forward: bool,
+    pub topic: String,
+    pub frame_id: String,
+    pub mcap_encoding: McapEncoding,
+    /// threads writing csv, pcd and ply frames. 1 writes on the parsing thread
+    pub threads: usize,
+}
+
+impl OutputOptions {
+    pub fn register(opts: &mut Options) {
+        opts.optopt("o", "output", "output type", "csv|hdf|pcd|ply|las|laz|parquet|arrow|npy|npz|kitti|bag|mcap|e57");
+        opts.optflag("c", "compression", "enable compression");
+        opts.optopt("", "csv-columns", "csv columns (default all)", "intensity,channel,timestamp,azimuth,altitude,distance_m,x,y,z");
+        opts.optopt("", "csv-precision", "decimal places of csv float columns", "N");
+        opts.optopt("", "csv-delimiter", "csv delimiter (default comma)", "comma|tab|space");
+        opts.optflag("", "csv-no-header", "omit the csv header line");
+        opts.optopt("", "csv-compression", "csv file compression (default none)", "none|gzip|zstd");
+        opts.optopt("", "csv-frame-digits", "digits of the frame number in csv file names (default 4)", "N");
+        opts.optopt("", "hdf-layout", "hdf layout (default frames)", "frames|table");
+        opts.optopt("p", "pcd-format", "pcd data format", "ascii|binary|binary_compressed");
+        opts.optopt("", "las-scale", "las coordinate scale in meters (default 0.001)", "SCALE");
+        opts.optopt("", "las-offset", "las coordinate offset in meters", "X,Y,Z");
+        opts.optflag("", "las-single", "write all frames into a single las file");
+        opts.optopt("", "row-group-frames", "frames per parquet row group (default 1)", "N");
+        opts.optopt("", "npy-columns", "npy columns (default x,y,z,intensity)", "x,y,z,intensity,timestamp_offset,...");
+        opts.optflag("", "kitti-x-forward", "convert kitti output to x-forward coordinates");
+        opts.optopt("", "topic", "ros topic name (default /points)", "TOPIC");
+        opts.optopt("", "frame-id", "ros frame_id (default lidar)", "FRAME_ID");
+        opts.optopt("", "mcap-encoding", "mcap message encoding (default ros2)", "ros2|foxglove");
+        opts.optopt("", "threads", "threads writing csv, pcd and ply frames (default 1)", "N");
+    }
+
+    pub fn from_matches(matches: &Matches) -> Result<OutputOptions> {
+        let out_type = matches.opt_get::<OutType>("o")?.unwrap_or(OutType::Csv);
+        let mut csv = CsvOptions {
+            precision: matches.opt_get::<usize>("csv-precision")?,
+            header: !matches.opt_present("csv-no-header"),
+            ..Default::default()
+        };
+        if let Some(columns) = matches.opt_str("csv-columns") {
+            csv.columns = CsvColumn::parse_list(&columns)?;
+        }
+        if let Some(delimiter) = matches.opt_get::<CsvDelimiter>("csv-delimiter")? {
+            csv.delimiter = delimiter;
+        }
+        if let Some(compression) = matches.opt_get::<CsvCompression>("csv-compression")? {
+            csv.compression = compression;
+        }
+        if let Some(digits) = matches.opt_get::<usize>("csv-frame-digits")? {
+            csv.frame_digits = digits;
+        }
+        let mut las = LasOptions {
+            single_file: matches.opt_present("las-single"),
+            compress: matches!(out_type, OutType::Laz),
+            ..Default::default()
+        };
+        if let Some(scale) = matches.opt_get::<f64>("las-scale")? {
+            las.scale = scale;
+        }
+        if let Some(offset) = matches.opt_str("las-offset") {
+            las.offset = parse_offset(&offset)?;
+        }
+        let threads = matches.opt_get::<usize>("threads")?.unwrap_or(1);
+        ensure!(threads >= 1, "threads must be at least 1");
+        Ok(OutputOptions {
+            out_type,
+            compression: matches.opt_present("c"),
+            csv,
+            hdf_layout: matches.opt_get::<HdfLayout>("hdf-layout")?.unwrap_or(HdfLayout::Frames),
+            pcd_data_kind: matches.opt_get::<PcdDataKind>("p")?.unwrap_or(PcdDataKind::Ascii),
+            las,
+            row_group_frames: matches.opt_get::<u32>("row-group-frames")?.unwrap_or(1),
+            npy_columns: NpyColumn::parse_list(&matches.opt_str("npy-columns").unwrap_or("x,y,z,intensity".to_string()))?,
+            kitti_x_forward: matches.opt_present("kitti-x-forward"),
+            topic: matches.opt_str("topic").unwrap_or("/points".to_string()),
+            frame_id: matches.opt_str("frame-id").unwrap_or("lidar".to_string()),
+            mcap_encoding: matches.opt_get::<McapEncoding>("mcap-encoding")?.unwrap_or(McapEncoding::Ros2),
+            threads,
+        })
+    }
+
+    /// Creates the writer next to the input file.
+    /// `[dir]/[stem].pcap` (or `[stem].pcap.{gz,zst,xz}`) is written to `[dir]/[stem].{h5,parquet,arrows,npz,bag,mcap,e57}` (or `.las` with `--las-single`) or `[dir]/[stem]/[stem]_NNNN.*`.
+    pub fn create_writer(&self, input: &str) -> Result<Box<dyn FrameWriter>> {
+        self.create_writer_with_suffix(input, "")
+    }
+
+    /// Creates the writer for the packets of one sensor, named `[stem]_[ip]_[port]`.
+    /// The colons of IPv6 addresses are replaced with `-`.
+    pub fn create_sensor_writer(&self, input: &str, source: SocketAddr) -> Result<Box<dyn FrameWriter>> {
+        let ip = source.ip().to_string().replace(':', "-");
+        self.create_writer_with_suffix(input, &format!("_{}_{}", ip, source.port()))
+    }
+
+    fn create_writer_with_suffix(&self, input: &str, suffix: &str) -> Result<Box<dyn FrameWriter>> {
+        let input_file_path = Path::new(strip_compression_extension(input));
+        let stem = input_file_path.file_stem()
+            .ok_or_else(|| anyhow!("invalid input path: {}", input))?
+            .to_string_lossy().to_string() + suffix;
+        let mut file_dir = input_file_path.parent()
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if file_dir.is_empty() {
+            file_dir = ".".to_string();
+        }
+        let dir = stem.clone();
+
+        let writer: Box<dyn FrameWriter> = match self.out_type {
+            OutType::Csv if self.threads > 1 => Box::new(ParallelWriter::new(Arc::new(CsvEncoder::create(file_dir, dir, stem, self.csv.clone())?), self.threads)),
+            OutType::Csv => Box::new(CsvWriter::create(file_dir, dir, stem, self.csv.clone())?),
+            OutType::Hdf => Box::new(HdfWriter::create(file_dir, stem, self.compression, self.hdf_layout)?),
+            OutType::Pcd if self.threads > 1 => Box::new(ParallelWriter::new(Arc::new(PcdEncoder::create(file_dir, dir, stem, self.pcd_data_kind)?), self.threads)),
+            OutType::Pcd => Box::new(PcdWriter::create(file_dir, dir, stem, self.pcd_data_kind)?),
+            OutType::Ply if self.threads > 1 => Box::new(ParallelWriter::new(Arc::new(PlyEncoder::create(file_dir, dir, stem)?), self.threads)),
+            OutType::Ply => Box::new(PlyWriter::create(file_dir, dir, stem)?),
+            OutType::Las | OutType::Laz => Box::new(LasWriter::create(file_dir, dir, stem, self.las)?),
+            OutType::Parquet => Box::new(ParquetWriter::create(file_dir, stem, self.row_group_frames, self.compression)?),
+            OutType::Arrow => Box::new(ArrowIpcWriter::create(file_dir, stem)?),
+            OutType::Npy | OutType::Npz => {
+                let npz = matches!(self.out_type, OutType::Npz);
+                Box::new(NpyWriter::create(file_dir, dir, stem, self.npy_columns.clone(), npz, self.compression)?)
+            },
+            OutType::Kitti => Box::new(KittiWriter::create(file_dir, dir, self.kitti_x_forward)?),
+            OutType::Bag => Box::new(RosbagWriter::create(file_dir, stem, self.topic.clone(), self.frame_id.clone())?),
+            OutType::Mcap => Box::new(McapWriter::create(file_dir, stem, self.mcap_encoding, self.topic.clone(), self.frame_id.clone(), self.compression)?),
+            OutType::E57 => Box::new(E57Writer::create(file_dir, stem)?),
+        };
+        Ok(writer)
+    }
+}
+
+fn parse_offset(s: &str) -> Result<[f64; 3]> {
+    let values = s.split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| anyhow!("invalid offset {}: {}", s, e))?;
+    match values[..] {
+        [x, y, z] => Ok([x, y, z]),
+        _ => Err(anyhow!("offset must be X,Y,Z: {}", s)),
+    }
+}
@@ -0,0 +1,234 @@
+use std::collections::hash_map::{Entry, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Instant;
+
+use anyhow::{ensure, Result};
+use getopts::{Matches, Options};
+
+use crate::{
+    capture::CaptureFile,
+    framewriter::FrameWriter,
+    outputoptions::OutputOptions,
+    udpdecoder::{UdpDecoder, UdpPacket},
+};
+
+/// Selects the UDP packets converted by a parser.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PacketFilter {
+    pub src_ip: Option<IpAddr>,
+    /// matches either the source or the destination port
+    pub port: Option<u16>,
+    /// the sensor selected by `--demux`
+    pub source: Option<SocketAddr>,
+}
+
+impl PacketFilter {
+    pub fn matches(&self, packet: &UdpPacket) -> bool {
+        self.matches_ip(packet.source.ip())
+            && self.port.map_or(true, |port| packet.source.port() == port || packet.destination.port() == port)
+            && self.source.map_or(true, |source| packet.source == source)
+    }
+
+    /// Ignores the ports, for the packets which a sensor sends from another port than its points.
+    pub fn matches_ip(&self, ip: IpAddr) -> bool {
+        self.src_ip.map_or(true, |src_ip| ip == src_ip)
+            && self.source.map_or(true, |source| ip == source.ip())
+    }
+
+    fn with_source(&self, source: SocketAddr) -> PacketFilter {
+        PacketFilter { source: Some(source), ..*self }
+    }
+}
+
+/// Converts the data packets of one output of `FilterOptions::convert_outputs`.
+pub trait PacketConverter {
+    /// `position` is the end offset of the packet in the input, for the progress bar
+    fn push(&mut self, packet: &UdpPacket, position: u64) -> Result<()>;
+    fn finish(self) -> Result<()>;
+}
+
+/// Packet filtering related command line options shared by the pcap parsers.
+pub struct FilterOptions {
+    pub filter: PacketFilter,
+    /// writes one output per sensor
+    pub demux: bool,
+}
+
+impl FilterOptions {
+    pub fn register(opts: &mut Options) {
+        opts.optopt("", "src-ip", "convert only the packets sent from this address", "IP");
+        opts.optopt("", "port", "convert only the packets sent from or to this UDP port", "PORT");
+        opts.optflag("", "demux", "write one output per sensor, named [input]_[ip]_[port]");
+    }
+
+    pub fn from_matches(matches: &Matches) -> Result<FilterOptions> {
+        let filter = PacketFilter {
+            src_ip: matches.opt_get::<IpAddr>("src-ip")?,
+            port: matches.opt_get::<u16>("port")?,
+            source: None,
+        };
+        Ok(FilterOptions { filter, demux: matches.opt_present("demux") })
+    }
+
+    /// Reads the capture once and passes the packets passing the filter and `is_data` to the
+    /// converter of the input, or with `--demux` to the converter of their source. The converters
+    /// are created with `create` when the first packet of a sensor is found.
+    pub fn convert_outputs<C, F>(&self, capture: &CaptureFile, input: &str, output: &OutputOptions, is_data: fn(&[u8]) -> bool, mut create: F) -> Result<()>
+    where
+        C: PacketConverter,
+        F: FnMut(&PacketFilter, Box<dyn FrameWriter>) -> Result<C>,
+    {
+        let mut single = if self.demux {
+            None
+        } else {
+            Some(create(&self.filter, output.create_writer(input)?)?)
+        };
+        let mut converters = HashMap::new();
+        let mut sources = Vec::new();
+
+        let mut decoder = UdpDecoder::new();
+        let mut num_packets = 0;
+        let time_start = Instant::now();
+        let mut packets = capture.packets()?;
+        while let Some(packet) = packets.next_packet()? {
+            num_packets += 1;
+            let Some(udp) = decoder.decode(&packet) else {
+                continue;
+            };
+            if !self.filter.matches(&udp) || !is_data(udp.payload) {
+                // ignore the packets of other devices
+                continue;
+            }
+            let converter = match single.as_mut() {
+                Some(converter) => converter,
+                None => match converters.entry(udp.source) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => {
+                        println!("converting the packets from {}", udp.source);
+                        sources.push(udp.source);
+                        let writer = output.create_sensor_writer(input, udp.source)?;
+                        entry.insert(create(&self.filter.with_source(udp.source), writer)?)
+                    },
+                },
+            };
+            converter.push(&udp, packet.end_offset)?;
+        }
+
+        match single {
+            Some(converter) => converter.finish()?,
+            None => {
+                ensure!(!sources.is_empty(), "no sensor packets found");
+                for source in sources {
+                    converters.remove(&source).unwrap().finish()?;
+                }
+            },
+        }
+        let duration = time_start.elapsed();
+
+        println!("{} packets have been processed in {:?}", num_packets, duration);
+        decoder.warn_malformed();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::net::SocketAddrV4;
+    use std::path::Path;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::testutil::{pcap, temp_dir, udp_frame};
+
+    /// source selected by the filter and payloads of a finished converter
+    type Output = (Option<SocketAddr>, Vec<Vec<u8>>);
+
+    struct Recorder {
+        source: Option<SocketAddr>,
+        payloads: Vec<Vec<u8>>,
+        writer: Box<dyn FrameWriter>,
+        finished: Rc<RefCell<Vec<Output>>>,
+    }
+
+    impl PacketConverter for Recorder {
+        fn push(&mut self, packet: &UdpPacket, _position: u64) -> Result<()> {
+            self.payloads.push(packet.payload.to_vec());
+            Ok(())
+        }
+
+        fn finish(mut self) -> Result<()> {
+            self.writer.finalize()?;
+            self.finished.borrow_mut().push((self.source, self.payloads));
+            Ok(())
+        }
+    }
+
+    /// data packets start with 0xAA
+    fn is_data(payload: &[u8]) -> bool {
+        payload.first() == Some(&0xAA)
+    }
+
+    fn sensor(last: u8, port: u16) -> SocketAddrV4 {
+        SocketAddrV4::new([192, 168, 1, last].into(), port)
+    }
+
+    /// Two interleaved sensors and a status packet of the first one.
+    fn write_capture(name: &str) -> String {
+        let frames = [
+            udp_frame(sensor(201, 2368), &[0xAA, 1]),
+            udp_frame(sensor(202, 2369), &[0xAA, 2]),
+            udp_frame(sensor(201, 8308), &[0x00, 3]),
+            udp_frame(sensor(201, 2368), &[0xAA, 4]),
+            udp_frame(sensor(202, 2369), &[0xAA, 5]),
+        ];
+        let packets: Vec<(u32, &[u8])> = frames.iter().enumerate().map(|(i, frame)| (i as u32, &frame[..])).collect();
+        let path = format!("{}/{}.pcap", temp_dir(name), name);
+        std::fs::write(&path, pcap(false, false, &packets)).unwrap();
+        path
+    }
+
+    fn convert(input: &str, args: &[&str]) -> Result<Vec<Output>> {
+        let mut opts = Options::new();
+        FilterOptions::register(&mut opts);
+        OutputOptions::register(&mut opts);
+        let matches = opts.parse(args)?;
+        let filter = FilterOptions::from_matches(&matches)?;
+        let output = OutputOptions::from_matches(&matches)?;
+        let finished = Rc::new(RefCell::new(Vec::new()));
+        filter.convert_outputs(&CaptureFile::open(input)?, input, &output, is_data, |filter, writer| {
+            Ok(Recorder { source: filter.source, payloads: Vec::new(), writer, finished: finished.clone() })
+        })?;
+        let finished = finished.take();
+        Ok(finished)
+    }
+
+    #[test]
+    fn demux_writes_one_output_per_sensor() {
+        let input = write_capture("demux");
+        let finished = convert(&input, &["--demux"]).unwrap();
+        assert_eq!(finished, [
+            (Some(sensor(201, 2368).into()), vec![vec![0xAA, 1], vec![0xAA, 4]]),
+            (Some(sensor(202, 2369).into()), vec![vec![0xAA, 2], vec![0xAA, 5]]),
+        ]);
+        let dir = Path::new(&input).parent().unwrap();
+        assert!(dir.join("demux_192.168.1.201_2368").is_dir());
+        assert!(dir.join("demux_192.168.1.202_2369").is_dir());
+        assert!(!dir.join("demux").exists());
+    }
+
+    #[test]
+    fn filter_writes_one_output() {
+        let input = write_capture("filter");
+        let finished = convert(&input, &["--src-ip", "192.168.1.202"]).unwrap();
+        assert_eq!(finished, [(None, vec![vec![0xAA, 2], vec![0xAA, 5]])]);
+        assert!(Path::new(&input).with_extension("").is_dir());
+    }
+
+    #[test]
+    fn demux_without_sensor_packets_fails() {
+        let input = write_capture("nosensor");
+        let error = convert(&input, &["--demux", "--port", "9999"]).unwrap_err();
+        assert_eq!(error.to_string(), "no sensor packets found");
+    }
+}
@@ -0,0 +1,350 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, ensure, Error, Result};
+use getopts::{Matches, Options};
+
+use crate::{
+    framesplitter::FrameSplitter,
+    pointbatch::PointBatch,
+    splittingwriter::SplittingWriter,
+};
+
+/// packets decoded by a worker at once
+const CHUNK_PACKETS: usize = 1024;
+
+/// Receives the points of decoded packets.
+///
+/// Decoders write to a `PointSink` instead of `SplittingWriter` so that they can also run on
+/// the worker threads of `ParallelDecoder`.
+pub trait PointSink {
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()>;
+    /// notifies a frame boundary marker found by the decoder
+    fn signal_frame(&mut self) -> Result<()>;
+}
+
+impl<S: FrameSplitter> PointSink for SplittingWriter<S> {
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        SplittingWriter::write_batch(self, batch)
+    }
+
+    fn signal_frame(&mut self) -> Result<()> {
+        SplittingWriter::signal_frame(self)
+    }
+}
+
+/// Points of a chunk of packets, recorded on a worker thread to be replayed in order.
+#[derive(Default)]
+pub struct DecodedChunk {
+    points: PointBatch,
+    /// number of points written before each frame marker
+    signals: Vec<usize>,
+    /// the error which stopped the decoding. the points before it are still replayed
+    error: Option<Error>,
+}
+
+impl PointSink for DecodedChunk {
+    fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        self.points.extend(batch);
+        Ok(())
+    }
+
+    fn signal_frame(&mut self) -> Result<()> {
+        self.signals.push(self.points.len());
+        Ok(())
+    }
+}
+
+impl DecodedChunk {
+    /// Writes the points and frame markers into `sink` in the order they were decoded.
+    fn replay<W: PointSink>(self, sink: &mut W) -> Result<()> {
+        let mut start = 0;
+        for &end in self.signals.iter() {
+            if end > start {
+                sink.write_batch(&self.points.slice(start..end))?;
+                start = end;
+            }
+            sink.signal_frame()?;
+        }
+        if start == 0 {
+            sink.write_batch(&self.points)?;
+        } else if start < self.points.len() {
+            sink.write_batch(&self.points.slice(start..self.points.len()))?;
+        }
+        match self.error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Packets stored back to back.
+#[derive(Default)]
+struct PacketChunk {
+    data: Vec<u8>,
+    ends: Vec<usize>,
+}
+
+impl PacketChunk {
+    fn push(&mut self, packet: &[u8]) {
+        self.data.extend_from_slice(packet);
+        self.ends.push(self.data.len());
+    }
+
+    fn len(&self) -> usize {
+        self.ends.len()
+    }
+
+    fn packets(&self) -> impl Iterator<Item = &[u8]> {
+        let starts = std::iter::once(0).chain(self.ends.iter().copied());
+        starts.zip(self.ends.iter().copied()).map(|(start, end)| &self.data[start..end])
+    }
+}
+
+/// Decodes packets on a pool of threads and replays the points into the writer in capture order.
+///
+/// Packets are queued in chunks of a fixed `CHUNK_PACKETS` instead of being cut at frame
+/// boundaries found by a pre-scan. The splitter only runs on replay, so the frames are the same
+/// as with sequential decoding wherever the chunks are cut, and the capture is read only once.
+/// At most `2 * threads` chunks are held in memory.
+pub struct ParallelDecoder {
+    chunk: PacketChunk,
+    next_send: u64,
+    next_replay: u64,
+    max_in_flight: u64,
+    sender: Option<SyncSender<(u64, PacketChunk)>>,
+    results: Receiver<(u64, DecodedChunk)>,
+    /// decoded chunks which arrived before their predecessors
+    pending: BTreeMap<u64, DecodedChunk>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ParallelDecoder {
+    /// `decode` is called for each packet pushed, in no particular order across chunks.
+    pub fn new<F>(threads: usize, decode: F) -> ParallelDecoder
+    where
+        F: Fn(&[u8], &mut DecodedChunk) -> Result<()> + Send + Sync + 'static,
+    {
+        let decode = Arc::new(decode);
+        let (sender, receiver) = mpsc::sync_channel::<(u64, PacketChunk)>(threads);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let (result_sender, results) = mpsc::channel();
+        let workers = (0..threads).map(|_| {
+            let decode = decode.clone();
+            let receiver = receiver.clone();
+            let result_sender = result_sender.clone();
+            thread::spawn(move || loop {
+                let chunk = receiver.lock().unwrap().recv();
+                let Ok((sequence, chunk)) = chunk else {
+                    break;
+                };
+                let mut decoded = DecodedChunk::default();
+                // a panic must still send the chunk, or the replay waits for it forever
+                let result = panic::catch_unwind(AssertUnwindSafe(|| {
+                    for packet in chunk.packets() {
+                        decode(packet, &mut decoded)?;
+                    }
+                    Ok(())
+                }));
+                decoded.error = match result {
+                    Ok(result) => result.err(),
+                    Err(payload) => Some(anyhow!("packet decoder panicked: {}", panic_message(&*payload))),
+                };
+                if result_sender.send((sequence, decoded)).is_err() {
+                    break;
+                }
+            })
+        }).collect();
+        ParallelDecoder {
+            chunk: PacketChunk::default(),
+            next_send: 0,
+            next_replay: 0,
+            max_in_flight: threads as u64 * 2,
+            sender: Some(sender),
+            results,
+            pending: BTreeMap::new(),
+            workers,
+        }
+    }
+
+    /// Queues a packet. Chunks decoded so far are replayed into `sink`.
+    pub fn push<W: PointSink>(&mut self, packet: &[u8], sink: &mut W) -> Result<()> {
+        self.chunk.push(packet);
+        if self.chunk.len() >= CHUNK_PACKETS {
+            self.send_chunk()?;
+            self.replay(sink, self.max_in_flight)?;
+        }
+        Ok(())
+    }
+
+    /// Decodes the remaining packets and replays everything into `sink`.
+    pub fn finish<W: PointSink>(mut self, sink: &mut W) -> Result<()> {
+        if self.chunk.len() > 0 {
+            self.send_chunk()?;
+        }
+        self.replay(sink, 1)?;
+        self.join()
+    }
+
+    fn send_chunk(&mut self) -> Result<()> {
+        let chunk = std::mem::take(&mut self.chunk);
+        let sender = self.sender.as_ref().ok_or_else(|| anyhow!("decoder is already finished"))?;
+        sender.send((self.next_send, chunk)).map_err(|_| anyhow!("decoder threads stopped"))?;
+        self.next_send += 1;
+        Ok(())
+    }
+
+    /// Replays the chunks in order until fewer than `max_in_flight` chunks are outstanding.
+    fn replay<W: PointSink>(&mut self, sink: &mut W, max_in_flight: u64) -> Result<()> {
+        while self.next_send - self.next_replay >= max_in_flight {
+            let decoded = match self.pending.remove(&self.next_replay) {
+                Some(decoded) => decoded,
+                None => {
+                    let (sequence, decoded) = self.results.recv().map_err(|_| anyhow!("decoder threads stopped"))?;
+                    self.pending.insert(sequence, decoded);
+                    continue;
+                },
+            };
+            self.next_replay += 1;
+            decoded.replay(sink)?;
+        }
+        Ok(())
+    }
+
+    fn join(&mut self) -> Result<()> {
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            worker.join().map_err(|_| anyhow!("decoder thread panicked"))?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ParallelDecoder {
+    fn drop(&mut self) {
+        let _ = self.join();
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Packet decoding related command line options shared by the pcap parsers.
+pub struct DecodeOptions {
+    /// threads decoding packets. 1 decodes on the reading thread
+    pub threads: usize,
+}
+
+impl DecodeOptions {
+    pub fn register(opts: &mut Options) {
+        opts.optopt("", "decode-threads", "threads decoding packets (default 1)", "N");
+    }
+
+    pub fn from_matches(matches: &Matches) -> Result<DecodeOptions> {
+        let threads = matches.opt_get::<usize>("decode-threads")?.unwrap_or(1);
+        ensure!(threads >= 1, "decode threads must be at least 1");
+        Ok(DecodeOptions { threads })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testutil::point;
+
+    /// packets per frame marker, so that the markers fall inside the chunks
+    const FRAME_PACKETS: u32 = 1000;
+
+    #[derive(Default)]
+    struct Recorder {
+        timestamps: Vec<u64>,
+        /// number of points written before each frame marker
+        signals: Vec<usize>,
+    }
+
+    impl PointSink for Recorder {
+        fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+            self.timestamps.extend_from_slice(&batch.timestamp);
+            Ok(())
+        }
+
+        fn signal_frame(&mut self) -> Result<()> {
+            self.signals.push(self.timestamps.len());
+            Ok(())
+        }
+    }
+
+    /// Each packet is its index. A packet gives one point, a marker before every
+    /// `FRAME_PACKETS`th packet and a second point for odd indexes.
+    fn decode(packet: &[u8], sink: &mut impl PointSink) -> Result<()> {
+        let index = u32::from_le_bytes(packet.try_into()?);
+        if index % FRAME_PACKETS == 0 {
+            sink.signal_frame()?;
+        }
+        let mut batch = PointBatch::new();
+        batch.push(point(index), 0);
+        if index % 2 == 1 {
+            batch.push_return(point(index), 0, 1);
+        }
+        sink.write_batch(&batch)
+    }
+
+    #[test]
+    fn replays_chunks_in_capture_order() {
+        let packets = CHUNK_PACKETS as u32 * 5 + 10;
+        let mut expected = Recorder::default();
+        for index in 0..packets {
+            decode(&index.to_le_bytes(), &mut expected).unwrap();
+        }
+
+        let mut decoder = ParallelDecoder::new(3, |packet, decoded| {
+            // hold the first chunks back so that the later ones are decoded first
+            let index = u32::from_le_bytes(packet.try_into()?);
+            if index == 0 || index == CHUNK_PACKETS as u32 {
+                thread::sleep(Duration::from_millis(50));
+            }
+            decode(packet, decoded)
+        });
+        let mut recorder = Recorder::default();
+        for index in 0..packets {
+            decoder.push(&index.to_le_bytes(), &mut recorder).unwrap();
+        }
+        decoder.finish(&mut recorder).unwrap();
+        assert_eq!(recorder.timestamps, expected.timestamps);
+        assert_eq!(recorder.signals, expected.signals);
+    }
+
+    #[test]
+    fn decoder_panic_is_an_error() {
+        let mut decoder = ParallelDecoder::new(2, |packet, decoded| {
+            let index = u32::from_le_bytes(packet.try_into()?);
+            assert!(index != CHUNK_PACKETS as u32 + 5, "malformed packet");
+            decode(packet, decoded)
+        });
+        let mut recorder = Recorder::default();
+        let mut result = Ok(());
+        for index in 0..CHUNK_PACKETS as u32 * 4 {
+            result = decoder.push(&index.to_le_bytes(), &mut recorder);
+            if result.is_err() {
+                break;
+            }
+        }
+        let error = result.and_then(|_| decoder.finish(&mut recorder)).unwrap_err();
+        assert!(error.to_string().contains("malformed packet"), "{}", error);
+        // the points before the panic are still replayed
+        assert_eq!(recorder.timestamps.len() as u32, (CHUNK_PACKETS as u32 + 5) * 3 / 2);
+    }
+}
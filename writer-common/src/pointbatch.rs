@@ -0,0 +1,137 @@
+use std::ops::Range;
+
+use crate::velopoint::VeloPoint;
+
+/// Points in struct-of-arrays form. Decoders fill one per packet and hand it to the writer at once.
+///
+/// The columns have the same meaning and units as the fields of `VeloPoint`.
+/// `split_value` is the key passed to `FrameSplitter::read` and is not written.
+/// `return_index` is the return of the firing a point comes from: 0 for the first (or only)
+/// return and 1 for the second return of dual return packets.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PointBatch {
+    pub intensity: Vec<u8>,
+    pub channel: Vec<u8>,
+    pub timestamp: Vec<u64>,
+    pub azimuth: Vec<u16>,
+    pub altitude: Vec<i16>,
+    pub distance_m: Vec<f32>,
+    pub x: Vec<f32>,
+    pub y: Vec<f32>,
+    pub z: Vec<f32>,
+    pub split_value: Vec<i64>,
+    pub return_index: Vec<u8>,
+}
+
+impl PointBatch {
+    pub fn new() -> PointBatch {
+        Self::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> PointBatch {
+        PointBatch {
+            intensity: Vec::with_capacity(capacity),
+            channel: Vec::with_capacity(capacity),
+            timestamp: Vec::with_capacity(capacity),
+            azimuth: Vec::with_capacity(capacity),
+            altitude: Vec::with_capacity(capacity),
+            distance_m: Vec::with_capacity(capacity),
+            x: Vec::with_capacity(capacity),
+            y: Vec::with_capacity(capacity),
+            z: Vec::with_capacity(capacity),
+            split_value: Vec::with_capacity(capacity),
+            return_index: Vec::with_capacity(capacity),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.timestamp.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timestamp.is_empty()
+    }
+
+    pub fn push(&mut self, point: VeloPoint, split_value: i64) {
+        self.push_return(point, split_value, 0);
+    }
+
+    /// Adds a point of a multi return firing. `return_index` starts from 0.
+    pub fn push_return(&mut self, point: VeloPoint, split_value: i64, return_index: u8) {
+        self.intensity.push(point.intensity);
+        self.channel.push(point.channel);
+        self.timestamp.push(point.timestamp);
+        self.azimuth.push(point.azimuth);
+        self.altitude.push(point.altitude);
+        self.distance_m.push(point.distance_m);
+        self.x.push(point.x);
+        self.y.push(point.y);
+        self.z.push(point.z);
+        self.split_value.push(split_value);
+        self.return_index.push(return_index);
+    }
+
+    pub fn get(&self, index: usize) -> VeloPoint {
+        VeloPoint {
+            intensity: self.intensity[index],
+            channel: self.channel[index],
+            timestamp: self.timestamp[index],
+            azimuth: self.azimuth[index],
+            altitude: self.altitude[index],
+            distance_m: self.distance_m[index],
+            x: self.x[index],
+            y: self.y[index],
+            z: self.z[index],
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = VeloPoint> + '_ {
+        (0..self.len()).map(move |i| self.get(i))
+    }
+
+    /// Copies the points in `range` into a new batch.
+    pub fn slice(&self, range: Range<usize>) -> PointBatch {
+        PointBatch {
+            intensity: self.intensity[range.clone()].to_vec(),
+            channel: self.channel[range.clone()].to_vec(),
+            timestamp: self.timestamp[range.clone()].to_vec(),
+            azimuth: self.azimuth[range.clone()].to_vec(),
+            altitude: self.altitude[range.clone()].to_vec(),
+            distance_m: self.distance_m[range.clone()].to_vec(),
+            x: self.x[range.clone()].to_vec(),
+            y: self.y[range.clone()].to_vec(),
+            z: self.z[range.clone()].to_vec(),
+            split_value: self.split_value[range.clone()].to_vec(),
+            return_index: self.return_index[range].to_vec(),
+        }
+    }
+
+    /// Appends all points of `other`.
+    pub fn extend(&mut self, other: &PointBatch) {
+        self.intensity.extend_from_slice(&other.intensity);
+        self.channel.extend_from_slice(&other.channel);
+        self.timestamp.extend_from_slice(&other.timestamp);
+        self.azimuth.extend_from_slice(&other.azimuth);
+        self.altitude.extend_from_slice(&other.altitude);
+        self.distance_m.extend_from_slice(&other.distance_m);
+        self.x.extend_from_slice(&other.x);
+        self.y.extend_from_slice(&other.y);
+        self.z.extend_from_slice(&other.z);
+        self.split_value.extend_from_slice(&other.split_value);
+        self.return_index.extend_from_slice(&other.return_index);
+    }
+
+    pub fn clear(&mut self) {
+        self.intensity.clear();
+        self.channel.clear();
+        self.timestamp.clear();
+        self.azimuth.clear();
+        self.altitude.clear();
+        self.distance_m.clear();
+        self.x.clear();
+        self.y.clear();
+        self.z.clear();
+        self.split_value.clear();
+        self.return_index.clear();
+    }
+}
@@ -0,0 +1,122 @@
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::net::SocketAddr;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+
+/// Version of the output format specification (`docs/out-format.md`).
+pub const OUTPUT_FORMAT_VERSION: &str = "1.1";
+
+/// The input file a capture was converted from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SourceFile {
+    pub name: String,
+    pub size: u64,
+    /// SHA-256 of the whole file, lowercase hex. only with `--hash-source`
+    pub sha256: Option<String>,
+}
+
+impl SourceFile {
+    /// Hashing reads the whole input once more, so it is left to the caller.
+    pub fn from_path(path: &str, hash: bool) -> Result<SourceFile> {
+        let file = File::open(path).with_context(|| format!("failed to open {}", path))?;
+        let size = file.metadata()?.len();
+        let sha256 = if hash { Some(sha256_hex(file)?) } else { None };
+        let name = Path::new(path).file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        Ok(SourceFile { name, size, sha256 })
+    }
+}
+
+fn sha256_hex(file: File) -> Result<String> {
+    let mut reader = BufReader::with_capacity(1 << 20, file);
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher.finalize().iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Per channel calibration used to compute the coordinates. units: degrees
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Calibration {
+    pub elevation: Vec<f32>,
+    pub azimuth_offset: Vec<f32>,
+}
+
+/// Everything a reader needs to interpret a converted capture.
+///
+/// Parsers fill the sensor fields. `parser_name`, `parser_version`, `source_file` and
+/// `split_strategy` are filled by `SplittingWriter` when left empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SensorMetadata {
+    pub laser_num: u32,
+    pub frequency: f32, // Hz
+    /// Strongest: 0, Last: 1, Dual: 2
+    pub return_mode: u32,
+    pub manufacturer: String,
+    pub model: String,
+    pub serial: Option<String>,
+    /// UDP source of the sensor packets
+    pub source_address: Option<SocketAddr>,
+    pub calibration: Option<Calibration>,
+    pub parser_name: String,
+    pub parser_version: String,
+    pub source_file: Option<SourceFile>,
+    /// e.g. `azimuth(min_offset=6000)`
+    pub split_strategy: String,
+}
+
+impl SensorMetadata {
+    pub fn new(manufacturer: &str, model: &str) -> SensorMetadata {
+        SensorMetadata {
+            manufacturer: manufacturer.to_string(),
+            model: model.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Flattens the metadata into string pairs for formats that only have key-value metadata.
+    /// Keys are the same as the HDF5 root attribute names. Missing values are omitted.
+    pub fn to_pairs(&self) -> Vec<(String, String)> {
+        let mut pairs = vec![
+            ("output format version".to_string(), OUTPUT_FORMAT_VERSION.to_string()),
+            ("laser number".to_string(), self.laser_num.to_string()),
+            ("frequency".to_string(), self.frequency.to_string()),
+            ("return mode".to_string(), self.return_mode.to_string()),
+            ("manufacturer".to_string(), self.manufacturer.clone()),
+            ("model".to_string(), self.model.clone()),
+            ("parser name".to_string(), self.parser_name.clone()),
+            ("parser version".to_string(), self.parser_version.clone()),
+            ("split strategy".to_string(), self.split_strategy.clone()),
+        ];
+        if let Some(serial) = self.serial.as_ref() {
+            pairs.push(("serial".to_string(), serial.clone()));
+        }
+        if let Some(address) = self.source_address {
+            pairs.push(("source ip".to_string(), address.ip().to_string()));
+            pairs.push(("source port".to_string(), address.port().to_string()));
+        }
+        if let Some(source) = self.source_file.as_ref() {
+            pairs.push(("source file name".to_string(), source.name.clone()));
+            pairs.push(("source file size".to_string(), source.size.to_string()));
+            if let Some(sha256) = source.sha256.as_ref() {
+                pairs.push(("source file sha256".to_string(), sha256.clone()));
+            }
+        }
+        if let Some(calibration) = self.calibration.as_ref() {
+            let join = |v: &[f32]| v.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(",");
+            pairs.push(("elevation".to_string(), join(&calibration.elevation)));
+            pairs.push(("azimuth offset".to_string(), join(&calibration.azimuth_offset)));
+        }
+        pairs
+    }
+}
@@ -0,0 +1,194 @@
+use anyhow::Result;
+
+use crate::{
+    framesplitter::FrameSplitter,
+    framewriter::{FrameWriter, ProgressBarExt},
+    pointbatch::PointBatch,
+    sensormetadata::{SensorMetadata, SourceFile},
+    velopoint::VeloPoint,
+};
+
+pub type DynSplittingWriter = SplittingWriter<Box<dyn FrameSplitter>>;
+
+pub struct SplittingWriter<S: FrameSplitter> {
+    pub splitter: S,
+    pub writer: Box<dyn FrameWriter>,
+    progress_bar: indicatif::ProgressBar,
+    /// frames written. counted separately when the progress bar shows input bytes
+    frames: Option<u64>,
+    parser_name: String,
+    parser_version: String,
+    source_file: Option<SourceFile>,
+}
+
+impl<S: FrameSplitter> SplittingWriter<S> {
+    pub fn new(writer: Box<dyn FrameWriter>, splitter: S, frame_num: u64) -> SplittingWriter<S> {
+        let progress_bar = indicatif::ProgressBar::new_frame_progress_bar(frame_num);
+        SplittingWriter {
+            splitter,
+            writer,
+            progress_bar,
+            frames: None,
+            parser_name: String::new(),
+            parser_version: String::new(),
+            source_file: None,
+        }
+    }
+
+    /// Records the parser and the input file. They are added to the metadata on `write_attribute`.
+    pub fn set_source(&mut self, parser_name: &str, parser_version: &str, source_file: &SourceFile) {
+        self.parser_name = parser_name.to_string();
+        self.parser_version = parser_version.to_string();
+        self.source_file = Some(source_file.clone());
+    }
+
+    /// Shows the progress as bytes of the input instead of frames, for inputs whose frame count
+    /// is unknown up front. The position is updated with `set_input_position`.
+    pub fn track_input(&mut self, total_bytes: u64) {
+        self.progress_bar = indicatif::ProgressBar::new_bytes_progress_bar(total_bytes);
+        self.frames = Some(0);
+    }
+
+    pub fn set_input_position(&self, position: u64) {
+        self.progress_bar.set_position(position);
+    }
+
+    /// `split_value` is passed to the splitter as is (see `FrameSplitter::read`)
+    pub fn write_row(&mut self, row: VeloPoint, split_value: i64) -> Result<()> {
+        if self.splitter.read(&row, split_value) {
+            self.split_frame()?;
+        }
+        self.writer.write_row(row)
+    }
+
+    /// Writes the points of a packet at once. The batch is split where the splitter finds frame boundaries.
+    pub fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+        let mut start = 0;
+        for i in 0..batch.len() {
+            if self.splitter.read(&batch.get(i), batch.split_value[i]) {
+                if i > start {
+                    self.writer.write_batch(&batch.slice(start..i))?;
+                }
+                self.split_frame()?;
+                start = i;
+            }
+        }
+        if start == 0 {
+            self.writer.write_batch(batch)
+        } else {
+            self.writer.write_batch(&batch.slice(start..batch.len()))
+        }
+    }
+
+    /// notifies a frame boundary marker found by the decoder
+    pub fn signal_frame(&mut self) -> Result<()> {
+        if self.splitter.signal() {
+            self.split_frame()?;
+        }
+        Ok(())
+    }
+
+    /// Fills the parser, source file and split strategy fields left empty by the parser.
+    pub fn write_attribute(&mut self, mut metadata: SensorMetadata) -> Result<()> {
+        if metadata.parser_name.is_empty() {
+            metadata.parser_name = self.parser_name.clone();
+            metadata.parser_version = self.parser_version.clone();
+        }
+        if metadata.source_file.is_none() {
+            metadata.source_file = self.source_file.clone();
+        }
+        if metadata.split_strategy.is_empty() {
+            metadata.split_strategy = self.splitter.describe();
+        }
+        self.writer.write_attribute(&metadata)
+    }
+
+    pub fn finalize(&mut self) -> Result<()> {
+        self.split_frame()?;
+        self.writer.finalize()?;
+        self.progress_bar.finish();
+        Ok(())
+    }
+
+    fn split_frame(&mut self) -> Result<()> {
+        self.writer.split_frame()?;
+        match self.frames.as_mut() {
+            Some(frames) => {
+                *frames += 1;
+                self.progress_bar.set_message(format!("{} frames", frames));
+            },
+            None => self.progress_bar.inc(1),
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::framesplitter::TimeSplitter;
+    use crate::testutil::point;
+
+    #[derive(Debug, PartialEq)]
+    enum Event {
+        Batch(Vec<u64>),
+        Split,
+    }
+
+    /// Records the calls made by `SplittingWriter`.
+    struct Recorder(Rc<RefCell<Vec<Event>>>);
+
+    impl FrameWriter for Recorder {
+        fn write_row(&mut self, row: VeloPoint) -> Result<()> {
+            self.0.borrow_mut().push(Event::Batch(vec![row.timestamp]));
+            Ok(())
+        }
+
+        fn write_batch(&mut self, batch: &PointBatch) -> Result<()> {
+            self.0.borrow_mut().push(Event::Batch(batch.timestamp.clone()));
+            Ok(())
+        }
+
+        fn split_frame(&mut self) -> Result<()> {
+            self.0.borrow_mut().push(Event::Split);
+            Ok(())
+        }
+
+        fn write_attribute(&mut self, _metadata: &SensorMetadata) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn batch(timestamps: &[u64]) -> PointBatch {
+        let mut batch = PointBatch::new();
+        for &timestamp in timestamps {
+            batch.push(VeloPoint { timestamp, ..point(0) }, 0);
+        }
+        batch
+    }
+
+    #[test]
+    fn write_batch_is_sliced_at_the_splits() {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut writer = SplittingWriter::new(Box::new(Recorder(events.clone())), TimeSplitter::new(10), 0);
+        writer.write_batch(&batch(&[20, 25, 31, 35, 40])).unwrap();
+        writer.write_batch(&batch(&[45, 50, 60, 70])).unwrap();
+        writer.finalize().unwrap();
+        assert_eq!(*events.borrow(), [
+            // the first point starts a frame after the initial frame start of 0
+            Event::Split,
+            Event::Batch(vec![20, 25]),
+            Event::Split,
+            Event::Batch(vec![31, 35, 40]),
+            // a split at the start of a batch writes nothing before it
+            Event::Split,
+            Event::Batch(vec![45, 50]),
+            Event::Split,
+            Event::Batch(vec![60, 70]),
+            Event::Split,
+        ]);
+    }
+}
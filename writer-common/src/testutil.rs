@@ -0,0 +1,68 @@
+use std::net::SocketAddrV4;
+use std::path::PathBuf;
+
+use crate::velopoint::VeloPoint;
+
+/// Creates an empty directory for the output of a test.
+pub fn temp_dir(name: &str) -> String {
+    let dir: PathBuf = std::env::temp_dir().join(format!("writer-common-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir.to_string_lossy().to_string()
+}
+
+/// A point whose fields are all derived from `i`.
+pub fn point(i: u32) -> VeloPoint {
+    VeloPoint {
+        intensity: i as u8,
+        channel: (i % 32) as u8,
+        timestamp: 1_700_000_000_000_000_000 + i as u64 * 1000,
+        azimuth: (i * 10 % 36000) as u16,
+        altitude: -(i as i16 % 1500),
+        distance_m: i as f32 * 0.5,
+        x: i as f32,
+        y: -(i as f32),
+        z: i as f32 * 0.25,
+    }
+}
+
+/// seconds of the packet timestamps written by `pcap`
+pub const PCAP_SECONDS: u32 = 1_700_000_000;
+
+/// A pcap capture of Ethernet frames. `packets` are (fraction of the second, frame).
+pub fn pcap(big_endian: bool, nanosecond: bool, packets: &[(u32, &[u8])]) -> Vec<u8> {
+    let u32_bytes = |v: u32| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+    let u16_bytes = |v: u16| if big_endian { v.to_be_bytes() } else { v.to_le_bytes() };
+    let mut bytes = u32_bytes(if nanosecond { 0xa1b23c4d } else { 0xa1b2c3d4 }).to_vec();
+    bytes.extend_from_slice(&u16_bytes(2));
+    bytes.extend_from_slice(&u16_bytes(4));
+    bytes.extend_from_slice(&[0; 8]);
+    bytes.extend_from_slice(&u32_bytes(65535));
+    // Ethernet with the FCS length bits set
+    bytes.extend_from_slice(&u32_bytes(0x1000_0001));
+    for (fraction, data) in packets {
+        bytes.extend_from_slice(&u32_bytes(PCAP_SECONDS));
+        bytes.extend_from_slice(&u32_bytes(*fraction));
+        bytes.extend_from_slice(&u32_bytes(data.len() as u32));
+        bytes.extend_from_slice(&u32_bytes(data.len() as u32));
+        bytes.extend_from_slice(data);
+    }
+    bytes
+}
+
+/// An Ethernet frame of an IPv4 UDP packet sent to the broadcast address on the source port.
+pub fn udp_frame(source: SocketAddrV4, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0xff; 12];
+    frame.extend_from_slice(&0x0800u16.to_be_bytes());
+    frame.extend_from_slice(&[0x45, 0]);
+    frame.extend_from_slice(&((28 + payload.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0, 0, 0, 64, 17, 0, 0]);
+    frame.extend_from_slice(&source.ip().octets());
+    frame.extend_from_slice(&[255; 4]);
+    frame.extend_from_slice(&source.port().to_be_bytes());
+    frame.extend_from_slice(&source.port().to_be_bytes());
+    frame.extend_from_slice(&((8 + payload.len()) as u16).to_be_bytes());
+    frame.extend_from_slice(&[0, 0]);
+    frame.extend_from_slice(payload);
+    frame
+}
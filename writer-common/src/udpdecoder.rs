@@ -0,0 +1,515 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use anyhow::{ensure, Result};
+
+use crate::capture::CapturedPacket;
+
+// LINKTYPE_* values of the capture interfaces
+const LINKTYPE_ETHERNET: u32 = 1;
+const LINKTYPE_RAW: u32 = 101;
+const LINKTYPE_LINUX_SLL: u32 = 113;
+const LINKTYPE_IPV4: u32 = 228;
+const LINKTYPE_IPV6: u32 = 229;
+const LINKTYPE_LINUX_SLL2: u32 = 276;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const ETHERTYPE_QINQ: u16 = 0x88A8;
+
+const IP_PROTOCOL_UDP: u8 = 17;
+// IPv6 extension headers skipped before the upper layer header
+const IPV6_HOP_BY_HOP: u8 = 0;
+const IPV6_ROUTING: u8 = 43;
+const IPV6_FRAGMENT: u8 = 44;
+const IPV6_DESTINATION_OPTIONS: u8 = 60;
+
+const UDP_HEADER_SIZE: usize = 8;
+const MAX_DATAGRAM_SIZE: usize = 65535;
+/// incomplete datagrams kept for reassembly. the oldest one is dropped beyond this
+const MAX_PENDING_DATAGRAMS: usize = 256;
+
+/// A UDP datagram extracted from a captured frame.
+pub struct UdpPacket<'a> {
+    pub source: SocketAddr,
+    pub destination: SocketAddr,
+    /// UDP payload, without the link layer padding
+    pub payload: &'a [u8],
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct FragmentKey {
+    source: IpAddr,
+    destination: IpAddr,
+    protocol: u8,
+    identification: u32,
+}
+
+/// Fragments of a datagram received so far, by offset.
+struct PendingDatagram {
+    fragments: BTreeMap<usize, Vec<u8>>,
+    /// payload length, known once the last fragment arrives
+    total_len: Option<usize>,
+    /// order of the first fragment, to drop the oldest datagram
+    sequence: u64,
+}
+
+impl PendingDatagram {
+    fn is_complete(&self) -> bool {
+        let Some(total_len) = self.total_len else {
+            return false;
+        };
+        let mut covered = 0;
+        for (&offset, data) in self.fragments.iter() {
+            if offset > covered {
+                return false;
+            }
+            covered = covered.max(offset + data.len());
+        }
+        covered >= total_len
+    }
+}
+
+/// The network layer of a frame, before reassembly.
+struct IpPacket<'a> {
+    source: IpAddr,
+    destination: IpAddr,
+    protocol: u8,
+    /// set when the packet is a fragment: (identification, offset, more fragments)
+    fragment: Option<(u32, usize, bool)>,
+    payload: &'a [u8],
+}
+
+/// Extracts UDP datagrams from captured frames.
+///
+/// Ethernet (with 802.1Q/802.1ad VLAN tags), Linux cooked (SLL, SLL2) and raw IP captures are
+/// supported, over IPv4 or IPv6. Fragmented datagrams are reassembled and returned when their
+/// last fragment is decoded. UDP checksums are not verified, since captures taken on the sending
+/// host often have them left to the NIC.
+#[derive(Default)]
+pub struct UdpDecoder {
+    reassembler: Reassembler,
+    /// frames whose headers are inconsistent with the captured length
+    malformed: u64,
+    first_malformed: Option<String>,
+}
+
+/// Fragments of the datagrams which are not complete yet.
+#[derive(Default)]
+struct Reassembler {
+    pending: HashMap<FragmentKey, PendingDatagram>,
+    next_sequence: u64,
+    /// the last reassembled datagram
+    reassembled: Vec<u8>,
+}
+
+impl UdpDecoder {
+    pub fn new() -> UdpDecoder {
+        UdpDecoder::default()
+    }
+
+    /// Returns the UDP datagram carried by `packet`.
+    ///
+    /// `None` is returned for frames which are not UDP, such as ARP, TCP or frames of an unknown
+    /// link type, and for fragments of a datagram which is not complete yet. Frames whose headers
+    /// are inconsistent with the captured length, such as frames cut by the snapshot length, are
+    /// skipped too and counted in `malformed_frames`.
+    pub fn decode<'a>(&'a mut self, packet: &CapturedPacket<'a>) -> Option<UdpPacket<'a>> {
+        match decode_frame(&mut self.reassembler, packet) {
+            Ok(udp) => udp,
+            Err(e) => {
+                self.malformed += 1;
+                self.first_malformed.get_or_insert_with(|| format!("{:#}", e));
+                None
+            },
+        }
+    }
+
+    /// Number of the frames skipped by `decode` because of inconsistent headers.
+    pub fn malformed_frames(&self) -> u64 {
+        self.malformed
+    }
+
+    /// Prints a warning with the number of the skipped frames, if any.
+    pub fn warn_malformed(&self) {
+        if let Some(first) = &self.first_malformed {
+            eprintln!("warning: {} malformed frames have been skipped (first: {})", self.malformed, first);
+        }
+    }
+}
+
+fn decode_frame<'a>(reassembler: &'a mut Reassembler, packet: &CapturedPacket<'a>) -> Result<Option<UdpPacket<'a>>> {
+    let Some(ip) = decode_link(packet.link_type, packet.data)? else {
+        return Ok(None);
+    };
+    let datagram = match ip.fragment {
+        None => ip.payload,
+        Some((identification, offset, more)) => {
+            let key = FragmentKey {
+                source: ip.source,
+                destination: ip.destination,
+                protocol: ip.protocol,
+                identification,
+            };
+            if !reassembler.add_fragment(key, offset, more, ip.payload)? {
+                return Ok(None);
+            }
+            &reassembler.reassembled
+        },
+    };
+    let udp = decode_udp(datagram)?;
+    Ok(Some(UdpPacket {
+        source: SocketAddr::new(ip.source, udp.0),
+        destination: SocketAddr::new(ip.destination, udp.1),
+        payload: udp.2,
+    }))
+}
+
+impl Reassembler {
+    /// Stores a fragment. Returns true when the datagram is complete and has been copied to
+    /// `reassembled`.
+    fn add_fragment(&mut self, key: FragmentKey, offset: usize, more: bool, data: &[u8]) -> Result<bool> {
+        ensure!(offset + data.len() <= MAX_DATAGRAM_SIZE, "ip fragment exceeds the maximum datagram size");
+        if !self.pending.contains_key(&key) && self.pending.len() >= MAX_PENDING_DATAGRAMS {
+            let oldest = self.pending.iter().min_by_key(|(_, datagram)| datagram.sequence).map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.pending.remove(&oldest);
+            }
+        }
+        let sequence = self.next_sequence;
+        let datagram = self.pending.entry(key).or_insert_with(|| PendingDatagram {
+            fragments: BTreeMap::new(),
+            total_len: None,
+            sequence,
+        });
+        if datagram.sequence == sequence {
+            self.next_sequence += 1;
+        }
+        if !more {
+            datagram.total_len = Some(offset + data.len());
+        }
+        datagram.fragments.insert(offset, data.to_vec());
+        if !datagram.is_complete() {
+            return Ok(false);
+        }
+
+        let datagram = self.pending.remove(&key).unwrap();
+        let total_len = datagram.total_len.unwrap();
+        self.reassembled.clear();
+        self.reassembled.resize(total_len, 0);
+        for (offset, data) in datagram.fragments.iter() {
+            let end = (offset + data.len()).min(total_len);
+            if *offset < end {
+                self.reassembled[*offset..end].copy_from_slice(&data[..end - offset]);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Strips the link layer header. Returns `None` for frames which do not carry UDP over IP.
+fn decode_link(link_type: u32, data: &[u8]) -> Result<Option<IpPacket<'_>>> {
+    let (ether_type, payload) = match link_type {
+        LINKTYPE_ETHERNET => {
+            ensure!(data.len() >= 14, "ethernet frame is too short");
+            (read_u16(data, 12), &data[14..])
+        },
+        LINKTYPE_LINUX_SLL => {
+            ensure!(data.len() >= 16, "linux cooked frame is too short");
+            (read_u16(data, 14), &data[16..])
+        },
+        LINKTYPE_LINUX_SLL2 => {
+            ensure!(data.len() >= 20, "linux cooked v2 frame is too short");
+            (read_u16(data, 0), &data[20..])
+        },
+        LINKTYPE_RAW => {
+            ensure!(!data.is_empty(), "raw ip frame is empty");
+            match data[0] >> 4 {
+                4 => (ETHERTYPE_IPV4, data),
+                6 => (ETHERTYPE_IPV6, data),
+                _ => return Ok(None),
+            }
+        },
+        LINKTYPE_IPV4 => (ETHERTYPE_IPV4, data),
+        LINKTYPE_IPV6 => (ETHERTYPE_IPV6, data),
+        _ => return Ok(None),
+    };
+    let (ether_type, payload) = strip_vlan_tags(ether_type, payload)?;
+    match ether_type {
+        ETHERTYPE_IPV4 => decode_ipv4(payload),
+        ETHERTYPE_IPV6 => decode_ipv6(payload),
+        _ => Ok(None),
+    }
+}
+
+fn strip_vlan_tags(mut ether_type: u16, mut data: &[u8]) -> Result<(u16, &[u8])> {
+    while ether_type == ETHERTYPE_VLAN || ether_type == ETHERTYPE_QINQ {
+        ensure!(data.len() >= 4, "vlan tag is truncated");
+        ether_type = read_u16(data, 2);
+        data = &data[4..];
+    }
+    Ok((ether_type, data))
+}
+
+/// The protocol is checked before the lengths, since other traffic such as TCP segments
+/// offloaded to the NIC may have a total length of 0.
+fn decode_ipv4(data: &[u8]) -> Result<Option<IpPacket<'_>>> {
+    ensure!(data.len() >= 20, "ipv4 header is truncated");
+    ensure!(data[0] >> 4 == 4, "ip version is not 4");
+    if data[9] != IP_PROTOCOL_UDP {
+        return Ok(None);
+    }
+    let header_len = (data[0] & 15) as usize * 4;
+    // frames shorter than the ethernet minimum are padded after the total length
+    let total_len = read_u16(data, 2) as usize;
+    ensure!(header_len >= 20 && total_len >= header_len, "invalid ipv4 header length");
+    ensure!(total_len <= data.len(), "ipv4 packet is truncated ({} of {} bytes captured)", data.len(), total_len);
+    let flags_offset = read_u16(data, 6);
+    let more_fragments = flags_offset & 0x2000 != 0;
+    let offset = (flags_offset & 0x1FFF) as usize * 8;
+    let fragment = if more_fragments || offset > 0 {
+        Some((read_u16(data, 4) as u32, offset, more_fragments))
+    } else {
+        None
+    };
+    Ok(Some(IpPacket {
+        source: IpAddr::V4(Ipv4Addr::new(data[12], data[13], data[14], data[15])),
+        destination: IpAddr::V4(Ipv4Addr::new(data[16], data[17], data[18], data[19])),
+        protocol: data[9],
+        fragment,
+        payload: &data[header_len..total_len],
+    }))
+}
+
+/// The extension headers are walked over the captured bytes first, so that packets of other
+/// protocols are returned as `None` whatever their payload length.
+fn decode_ipv6(data: &[u8]) -> Result<Option<IpPacket<'_>>> {
+    ensure!(data.len() >= 40, "ipv6 header is truncated");
+    ensure!(data[0] >> 4 == 6, "ip version is not 6");
+    let payload_len = read_u16(data, 4) as usize;
+    let source = IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&data[8..24]).unwrap()));
+    let destination = IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&data[24..40]).unwrap()));
+
+    let captured = &data[40..];
+    let mut next_header = data[6];
+    let mut header_len = 0;
+    let mut fragment = None;
+    loop {
+        let header = &captured[header_len.min(captured.len())..];
+        match next_header {
+            IPV6_HOP_BY_HOP | IPV6_ROUTING | IPV6_DESTINATION_OPTIONS => {
+                ensure!(header.len() >= 8, "ipv6 extension header is truncated");
+                next_header = header[0];
+                header_len += (header[1] as usize + 1) * 8;
+            },
+            IPV6_FRAGMENT => {
+                ensure!(header.len() >= 8, "ipv6 fragment header is truncated");
+                let offset_flags = read_u16(header, 2);
+                let identification = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+                fragment = Some((identification, (offset_flags & 0xFFF8) as usize, offset_flags & 1 != 0));
+                next_header = header[0];
+                header_len += 8;
+                // the headers after the fragment header are part of the fragmented data
+                break;
+            },
+            _ => break,
+        }
+    }
+    if next_header != IP_PROTOCOL_UDP {
+        return Ok(None);
+    }
+    ensure!(payload_len > 0, "ipv6 jumbograms are not supported");
+    ensure!(payload_len <= captured.len(), "ipv6 packet is truncated ({} of {} bytes captured)", data.len(), 40 + payload_len);
+    ensure!(header_len <= payload_len, "ipv6 extension headers exceed the payload length");
+    Ok(Some(IpPacket { source, destination, protocol: next_header, fragment, payload: &captured[header_len..payload_len] }))
+}
+
+/// Returns the source port, destination port and payload of a UDP datagram.
+fn decode_udp(data: &[u8]) -> Result<(u16, u16, &[u8])> {
+    ensure!(data.len() >= UDP_HEADER_SIZE, "udp header is truncated");
+    let len = read_u16(data, 4) as usize;
+    ensure!(len >= UDP_HEADER_SIZE, "invalid udp length {}", len);
+    ensure!(len <= data.len(), "udp datagram is truncated ({} of {} bytes captured)", data.len(), len);
+    Ok((read_u16(data, 0), read_u16(data, 2), &data[UDP_HEADER_SIZE..len]))
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE_V4: [u8; 4] = [192, 168, 1, 201];
+    const DESTINATION_V4: [u8; 4] = [255, 255, 255, 255];
+    const SOURCE_V6: [u8; 16] = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    const DESTINATION_V6: [u8; 16] = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+    const IP_PROTOCOL_TCP: u8 = 6;
+
+    fn udp(payload: &[u8]) -> Vec<u8> {
+        let mut data = 2368u16.to_be_bytes().to_vec();
+        data.extend_from_slice(&2369u16.to_be_bytes());
+        data.extend_from_slice(&((payload.len() + UDP_HEADER_SIZE) as u16).to_be_bytes());
+        data.extend_from_slice(&[0, 0]);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// `offset` in bytes, a multiple of 8
+    fn ipv4(protocol: u8, identification: u16, offset: usize, more: bool, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0x45, 0];
+        data.extend_from_slice(&((20 + payload.len()) as u16).to_be_bytes());
+        data.extend_from_slice(&identification.to_be_bytes());
+        data.extend_from_slice(&((offset / 8) as u16 | if more { 0x2000 } else { 0 }).to_be_bytes());
+        data.extend_from_slice(&[64, protocol, 0, 0]);
+        data.extend_from_slice(&SOURCE_V4);
+        data.extend_from_slice(&DESTINATION_V4);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    /// `fragment`: (identification, offset, more fragments)
+    fn ipv6(fragment: Option<(u32, usize, bool)>, payload: &[u8]) -> Vec<u8> {
+        let mut extension = Vec::new();
+        let next_header = match fragment {
+            Some((identification, offset, more)) => {
+                extension.extend_from_slice(&[IP_PROTOCOL_UDP, 0]);
+                extension.extend_from_slice(&(offset as u16 | more as u16).to_be_bytes());
+                extension.extend_from_slice(&identification.to_be_bytes());
+                IPV6_FRAGMENT
+            },
+            None => IP_PROTOCOL_UDP,
+        };
+        let mut data = vec![0x60, 0, 0, 0];
+        data.extend_from_slice(&((extension.len() + payload.len()) as u16).to_be_bytes());
+        data.extend_from_slice(&[next_header, 64]);
+        data.extend_from_slice(&SOURCE_V6);
+        data.extend_from_slice(&DESTINATION_V6);
+        data.extend(extension);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn ethernet(tags: &[u16], ether_type: u16, payload: &[u8]) -> Vec<u8> {
+        let mut data = vec![0xff; 12];
+        for tag in tags {
+            data.extend_from_slice(&tag.to_be_bytes());
+            data.extend_from_slice(&[0x00, 0x05]); // VLAN 5
+        }
+        data.extend_from_slice(&ether_type.to_be_bytes());
+        data.extend_from_slice(payload);
+        // padded to the ethernet minimum
+        data.resize(data.len().max(60), 0);
+        data
+    }
+
+    fn decode(decoder: &mut UdpDecoder, link_type: u32, data: &[u8]) -> Option<(SocketAddr, SocketAddr, Vec<u8>)> {
+        let packet = CapturedPacket { timestamp_ns: 0, link_type, data, end_offset: 0 };
+        decoder.decode(&packet).map(|udp| (udp.source, udp.destination, udp.payload.to_vec()))
+    }
+
+    fn decode_one(link_type: u32, data: &[u8]) -> Option<(SocketAddr, SocketAddr, Vec<u8>)> {
+        let mut decoder = UdpDecoder::new();
+        let result = decode(&mut decoder, link_type, data);
+        assert_eq!(decoder.malformed_frames(), 0);
+        result
+    }
+
+    fn v4_result(payload: &[u8]) -> Option<(SocketAddr, SocketAddr, Vec<u8>)> {
+        Some((SocketAddr::new(Ipv4Addr::from(SOURCE_V4).into(), 2368), SocketAddr::new(Ipv4Addr::from(DESTINATION_V4).into(), 2369), payload.to_vec()))
+    }
+
+    fn v6_result(payload: &[u8]) -> Option<(SocketAddr, SocketAddr, Vec<u8>)> {
+        Some((SocketAddr::new(Ipv6Addr::from(SOURCE_V6).into(), 2368), SocketAddr::new(Ipv6Addr::from(DESTINATION_V6).into(), 2369), payload.to_vec()))
+    }
+
+    #[test]
+    fn ethernet_and_vlan_tags() {
+        let ip = ipv4(IP_PROTOCOL_UDP, 1, 0, false, &udp(b"abc"));
+        for tags in [&[][..], &[ETHERTYPE_VLAN], &[ETHERTYPE_QINQ, ETHERTYPE_VLAN]] {
+            // the padding after the short datagram is not part of the payload
+            assert_eq!(decode_one(LINKTYPE_ETHERNET, &ethernet(tags, ETHERTYPE_IPV4, &ip)), v4_result(b"abc"));
+        }
+        let ip = ipv6(None, &udp(b"v6"));
+        assert_eq!(decode_one(LINKTYPE_ETHERNET, &ethernet(&[ETHERTYPE_VLAN], ETHERTYPE_IPV6, &ip)), v6_result(b"v6"));
+    }
+
+    #[test]
+    fn linux_cooked_and_raw_frames() {
+        let ip = ipv4(IP_PROTOCOL_UDP, 1, 0, false, &udp(b"cooked"));
+        let mut sll = vec![0, 0, 0, 1, 0, 6, 1, 2, 3, 4, 5, 6, 0, 0];
+        sll.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+        sll.extend_from_slice(&ip);
+        assert_eq!(decode_one(LINKTYPE_LINUX_SLL, &sll), v4_result(b"cooked"));
+
+        let mut sll2 = ETHERTYPE_IPV4.to_be_bytes().to_vec();
+        sll2.extend_from_slice(&[0, 0, 0, 0, 0, 2, 1, 0, 0, 1, 4, 6, 1, 2, 3, 4, 5, 6]);
+        sll2.extend_from_slice(&ip);
+        assert_eq!(decode_one(LINKTYPE_LINUX_SLL2, &sll2), v4_result(b"cooked"));
+
+        assert_eq!(decode_one(LINKTYPE_RAW, &ip), v4_result(b"cooked"));
+        assert_eq!(decode_one(LINKTYPE_IPV4, &ip), v4_result(b"cooked"));
+        let ip = ipv6(None, &udp(b"raw"));
+        assert_eq!(decode_one(LINKTYPE_RAW, &ip), v6_result(b"raw"));
+        assert_eq!(decode_one(LINKTYPE_IPV6, &ip), v6_result(b"raw"));
+    }
+
+    #[test]
+    fn other_traffic_is_skipped_without_errors() {
+        // TCP segment offloaded to the NIC, with a total length of 0
+        let mut tcp = ipv4(IP_PROTOCOL_TCP, 1, 0, false, &[0; 40]);
+        tcp[2..4].copy_from_slice(&[0, 0]);
+        assert_eq!(decode_one(LINKTYPE_ETHERNET, &ethernet(&[], ETHERTYPE_IPV4, &tcp)), None);
+        // ARP
+        assert_eq!(decode_one(LINKTYPE_ETHERNET, &ethernet(&[], 0x0806, &[0; 28])), None);
+        // IEEE 802.11
+        assert_eq!(decode_one(105, &[0; 64]), None);
+    }
+
+    #[test]
+    fn malformed_frames_are_counted() {
+        let mut decoder = UdpDecoder::new();
+        let frame = ethernet(&[], ETHERTYPE_IPV4, &ipv4(IP_PROTOCOL_UDP, 1, 0, false, &udp(&[7; 100])));
+        // cut by the snapshot length
+        assert_eq!(decode(&mut decoder, LINKTYPE_ETHERNET, &frame[..80]), None);
+        assert_eq!(decode(&mut decoder, LINKTYPE_ETHERNET, &frame[..10]), None);
+        assert_eq!(decoder.malformed_frames(), 2);
+        assert_eq!(decode(&mut decoder, LINKTYPE_ETHERNET, &frame), v4_result(&[7; 100]));
+        assert_eq!(decoder.malformed_frames(), 2);
+    }
+
+    #[test]
+    fn ipv4_fragments_out_of_order() {
+        let payload: Vec<u8> = (0..100).collect();
+        let datagram = udp(&payload);
+        let fragments = [
+            ipv4(IP_PROTOCOL_UDP, 7, 0, true, &datagram[..48]),
+            ipv4(IP_PROTOCOL_UDP, 7, 48, true, &datagram[48..96]),
+            ipv4(IP_PROTOCOL_UDP, 7, 96, false, &datagram[96..]),
+        ];
+        let mut decoder = UdpDecoder::new();
+        assert_eq!(decode(&mut decoder, LINKTYPE_RAW, &fragments[2]), None);
+        // a fragment of another datagram in between
+        assert_eq!(decode(&mut decoder, LINKTYPE_RAW, &ipv4(IP_PROTOCOL_UDP, 8, 0, true, &datagram[..48])), None);
+        assert_eq!(decode(&mut decoder, LINKTYPE_RAW, &fragments[0]), None);
+        assert_eq!(decode(&mut decoder, LINKTYPE_RAW, &fragments[1]), v4_result(&payload));
+        // the datagram is not returned twice
+        assert_eq!(decode(&mut decoder, LINKTYPE_RAW, &fragments[1]), None);
+        assert_eq!(decoder.malformed_frames(), 0);
+    }
+
+    #[test]
+    fn ipv6_fragments_out_of_order() {
+        let payload: Vec<u8> = (0..200).map(|i| i as u8).collect();
+        let datagram = udp(&payload);
+        let mut decoder = UdpDecoder::new();
+        let frame = |offset: usize, end: usize, more: bool| {
+            ethernet(&[], ETHERTYPE_IPV6, &ipv6(Some((0x1234, offset, more)), &datagram[offset..end]))
+        };
+        assert_eq!(decode(&mut decoder, LINKTYPE_ETHERNET, &frame(104, 208, false)), None);
+        assert_eq!(decode(&mut decoder, LINKTYPE_ETHERNET, &frame(0, 104, true)), v6_result(&payload));
+        assert_eq!(decoder.malformed_frames(), 0);
+    }
+}
@@ -36,4 +36,13 @@ impl VeloPoint {
             self.z,
         )
     }
-}
\ No newline at end of file
+}
+/// An entry of the `frames` dataset in the single table HDF5 layout.
+#[derive(H5Type, Clone, PartialEq, Debug)]
+#[repr(C)]
+pub struct FrameIndex {
+    pub start: u64,           // offset of the first point in `points`
+    pub count: u64,           // number of points
+    pub first_timestamp: u64, // timestamp of the first point. units: nanoseconds
+    pub last_timestamp: u64,  // timestamp of the last point. units: nanoseconds
+}